@@ -0,0 +1,7 @@
+-- Emails are compared case-insensitively. Accounts that only differ by case
+-- must be merged first (see `src/bin/merge_duplicate_emails.rs`), otherwise
+-- creating the index below fails.
+
+UPDATE users SET email = lower(trim(email));
+
+CREATE UNIQUE INDEX users_email_lower_key ON users (lower(email));
@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE users ADD COLUMN salt text NOT NULL DEFAULT '';
+
+-- $argon2id$v=19$m=...,t=...,p=...$<salt>$<hash>
+UPDATE users SET salt = split_part(hashed_password, '$', 5);
+
+ALTER TABLE users ALTER COLUMN salt DROP DEFAULT;
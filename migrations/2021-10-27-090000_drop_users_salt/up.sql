@@ -0,0 +1,3 @@
+-- The salt is already part of the PHC string in `hashed_password`
+
+ALTER TABLE users DROP COLUMN salt;
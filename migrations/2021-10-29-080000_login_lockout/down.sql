@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+
+DROP TABLE login_ip_attempts;
+
+ALTER TABLE users
+    DROP COLUMN role,
+    DROP COLUMN failed_login_attempts,
+    DROP COLUMN last_failed_login_at,
+    DROP COLUMN locked_until;
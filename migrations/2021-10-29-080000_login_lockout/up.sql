@@ -0,0 +1,14 @@
+-- Your SQL goes here
+
+ALTER TABLE users
+    ADD COLUMN role text NOT NULL DEFAULT 'user',
+    ADD COLUMN failed_login_attempts integer NOT NULL DEFAULT 0,
+    ADD COLUMN last_failed_login_at timestamptz,
+    ADD COLUMN locked_until timestamptz;
+
+CREATE TABLE login_ip_attempts (
+    ip text PRIMARY KEY,
+    failed_attempts integer NOT NULL DEFAULT 0,
+    last_failed_at timestamptz NOT NULL,
+    locked_until timestamptz
+);
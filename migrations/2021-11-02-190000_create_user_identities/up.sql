@@ -0,0 +1,26 @@
+-- Your SQL goes here
+
+CREATE TABLE user_identities (
+    id SERIAL PRIMARY KEY,
+    user_id integer NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    provider text NOT NULL,
+    subject text NOT NULL,
+    email text,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    UNIQUE (provider, subject)
+);
+
+CREATE INDEX user_identities_user_id_idx ON user_identities (user_id);
+
+-- Pending authorization requests, consumed by the callback
+CREATE TABLE oauth_states (
+    state text PRIMARY KEY,
+    provider text NOT NULL,
+    code_verifier text NOT NULL,
+    -- hash of the nonce returned to the browser that started the flow, the
+    -- callback only accepts the state together with it
+    nonce_hash text NOT NULL,
+    -- set when a logged in user links another identity
+    link_user_id integer REFERENCES users (id) ON DELETE CASCADE,
+    created_at timestamptz NOT NULL DEFAULT now()
+);
@@ -0,0 +1,15 @@
+-- Your SQL goes here
+
+-- Only hashes are stored, the token travels by email and the nonce stays on
+-- the device that asked for the link
+CREATE TABLE magic_links (
+    id SERIAL PRIMARY KEY,
+    user_id integer NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    token_hash text NOT NULL UNIQUE,
+    nonce_hash text NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    expires_at timestamptz NOT NULL,
+    used_at timestamptz
+);
+
+CREATE INDEX magic_links_user_id_idx ON magic_links (user_id);
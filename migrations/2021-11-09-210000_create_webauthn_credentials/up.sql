@@ -0,0 +1,26 @@
+-- Your SQL goes here
+
+CREATE TABLE webauthn_credentials (
+    id SERIAL PRIMARY KEY,
+    user_id integer NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    -- base64url, as the browser reports it
+    credential_id text NOT NULL UNIQUE,
+    -- COSE encoded
+    public_key bytea NOT NULL,
+    algorithm integer NOT NULL,
+    sign_count bigint NOT NULL DEFAULT 0,
+    name text NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    last_used_at timestamptz
+);
+
+CREATE INDEX webauthn_credentials_user_id_idx ON webauthn_credentials (user_id);
+
+-- purpose is one of registration, authentication or second_factor
+CREATE TABLE webauthn_challenges (
+    id SERIAL PRIMARY KEY,
+    user_id integer REFERENCES users (id) ON DELETE CASCADE,
+    challenge text NOT NULL,
+    purpose text NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now()
+);
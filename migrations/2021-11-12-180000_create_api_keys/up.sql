@@ -0,0 +1,17 @@
+-- Your SQL goes here
+
+CREATE TABLE api_keys (
+    id SERIAL PRIMARY KEY,
+    user_id integer NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    name text NOT NULL,
+    -- the public part of the key, used to find the row
+    prefix text NOT NULL UNIQUE,
+    -- sha256 of the whole key
+    key_hash text NOT NULL,
+    scopes text[] NOT NULL,
+    expires_at timestamptz,
+    last_used_at timestamptz,
+    created_at timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE INDEX api_keys_user_id_idx ON api_keys (user_id);
@@ -0,0 +1,28 @@
+-- Your SQL goes here
+
+-- No foreign keys, events have to outlive the users they mention
+CREATE TABLE audit_events (
+    id BIGSERIAL PRIMARY KEY,
+    event text NOT NULL,
+    actor_user_id integer,
+    target_user_id integer,
+    ip text,
+    user_agent text,
+    details jsonb NOT NULL DEFAULT '{}',
+    created_at timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE INDEX audit_events_event_idx ON audit_events (event);
+CREATE INDEX audit_events_actor_user_id_idx ON audit_events (actor_user_id);
+CREATE INDEX audit_events_target_user_id_idx ON audit_events (target_user_id);
+CREATE INDEX audit_events_created_at_idx ON audit_events (created_at);
+
+CREATE FUNCTION audit_events_append_only() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_events is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_events_append_only
+    BEFORE UPDATE OR DELETE ON audit_events
+    FOR EACH ROW EXECUTE PROCEDURE audit_events_append_only();
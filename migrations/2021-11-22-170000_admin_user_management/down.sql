@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+
+DROP TABLE password_resets;
+
+ALTER TABLE users DROP COLUMN disabled_at;
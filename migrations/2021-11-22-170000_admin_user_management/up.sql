@@ -0,0 +1,13 @@
+-- Your SQL goes here
+
+ALTER TABLE users ADD COLUMN disabled_at timestamptz;
+
+CREATE TABLE password_resets (
+    id SERIAL PRIMARY KEY,
+    user_id integer NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    -- sha256 of the token in the emailed link
+    token_hash text NOT NULL UNIQUE,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    expires_at timestamptz NOT NULL,
+    used_at timestamptz
+);
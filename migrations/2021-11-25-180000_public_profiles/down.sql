@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE users
+    DROP COLUMN username,
+    DROP COLUMN display_name,
+    DROP COLUMN bio,
+    DROP COLUMN website,
+    DROP COLUMN social_links,
+    DROP COLUMN avatar_url;
@@ -0,0 +1,11 @@
+-- Your SQL goes here
+
+ALTER TABLE users
+    -- stored lowercase, see `normalize_username`
+    ADD COLUMN username text UNIQUE,
+    ADD COLUMN display_name text,
+    ADD COLUMN bio text,
+    ADD COLUMN website text,
+    -- e.g. {"github": "https://github.com/..."}
+    ADD COLUMN social_links jsonb NOT NULL DEFAULT '{}',
+    ADD COLUMN avatar_url text;
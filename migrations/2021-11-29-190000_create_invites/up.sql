@@ -0,0 +1,16 @@
+-- Your SQL goes here
+
+CREATE TABLE invites (
+    id SERIAL PRIMARY KEY,
+    -- sha256 of the code, the code itself is only shown when it is created
+    code_hash text NOT NULL UNIQUE,
+    -- first characters of the code so admins can tell invites apart
+    prefix text NOT NULL,
+    -- given to the accounts registered with this invite
+    role text NOT NULL DEFAULT 'user',
+    max_uses integer NOT NULL DEFAULT 1 CHECK (max_uses > 0),
+    uses integer NOT NULL DEFAULT 0,
+    expires_at timestamptz,
+    created_by integer REFERENCES users (id) ON DELETE SET NULL,
+    created_at timestamptz NOT NULL DEFAULT now()
+);
@@ -0,0 +1,537 @@
+use crate::api::admin::{caller_email, require_admin};
+use crate::api::profile::session_email;
+use crate::audit;
+use crate::config::{Argon2Config, MailConfig, PasswordResetConfig};
+use crate::constants::{
+    MESSAGE_CHANGE_ROLE_SUCCESS, MESSAGE_DISABLE_USER_SUCCESS, MESSAGE_ENABLE_USER_SUCCESS,
+    MESSAGE_GET_USERS_SUCCESS, MESSAGE_GET_USER_SUCCESS, MESSAGE_IMPERSONATION_STARTED,
+    MESSAGE_PASSWORD_RESET_SENT, ROLES, ROLE_ADMIN,
+};
+use crate::extractor::auth::AuthExtractor;
+use crate::mailer::send_email_in_background;
+use crate::model::errors::ServiceError;
+use crate::model::pagination::{Page, PageQuery};
+use crate::model::password_reset::NewPasswordReset;
+use crate::model::user::User;
+use crate::model::{db::Pool, errors::GlobalServiceError, response::ResponseBody};
+use crate::schema::{password_resets, sessions, users};
+use crate::session::{create_impersonation_token, ClientInfo};
+use crate::telemetry;
+use crate::utils::{generate_random_token, hash_password, sha256_hex};
+use actix_web::error::BlockingError;
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use chrono::{DateTime, Duration, Utc};
+use diesel::pg::Pg;
+use diesel::{
+    BoolExpressionMethods, Connection, ExpressionMethods, OptionalExtension, PgConnection,
+    PgTextExpressionMethods, QueryDsl, RunQueryDsl,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::instrument;
+use url::Url;
+
+// `q` is matched against email, username and full name
+#[derive(Deserialize)]
+pub struct UserFilter {
+    q: Option<String>,
+    role: Option<String>,
+    disabled: Option<bool>,
+}
+
+#[derive(Deserialize)]
+pub struct ChangeRoleRequest {
+    role: String,
+}
+
+// What admins get to see of an account, never the password hash
+#[derive(Serialize)]
+pub struct AdminUserView {
+    id: i32,
+    email: String,
+    username: Option<String>,
+    full_name: Option<String>,
+    role: String,
+    failed_login_attempts: i32,
+    locked_until: Option<DateTime<Utc>>,
+    disabled_at: Option<DateTime<Utc>>,
+}
+
+impl From<User> for AdminUserView {
+    fn from(user: User) -> AdminUserView {
+        AdminUserView {
+            id: user.id,
+            email: user.email,
+            username: user.username,
+            full_name: user.full_name,
+            role: user.role,
+            failed_login_attempts: user.failed_login_attempts,
+            locked_until: user.locked_until,
+            disabled_at: user.disabled_at,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ImpersonationResponse {
+    user: AdminUserView,
+    token: String,
+}
+
+#[instrument(skip_all)]
+pub async fn list_users_handler(
+    filter: web::Query<UserFilter>,
+    page: web::Query<PageQuery>,
+    pool: web::Data<Pool>,
+    auth_data: AuthExtractor,
+) -> Result<HttpResponse, GlobalServiceError> {
+    let admin_email = caller_email(&auth_data)?;
+    let res = telemetry::block(move || {
+        list_query(admin_email, filter.into_inner(), page.into_inner(), pool)
+    })
+    .await;
+
+    match res {
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
+        Err(err) => match err {
+            BlockingError::Error(service_error) => Err(service_error),
+            BlockingError::Canceled => Err(GlobalServiceError::InternalServerError),
+        },
+    }
+}
+
+#[instrument(skip_all)]
+pub async fn get_user_handler(
+    user_id: web::Path<i32>,
+    pool: web::Data<Pool>,
+    auth_data: AuthExtractor,
+) -> Result<HttpResponse, GlobalServiceError> {
+    let admin_email = caller_email(&auth_data)?;
+    let res = telemetry::block(move || get_query(admin_email, user_id.into_inner(), pool)).await;
+
+    match res {
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
+        Err(err) => match err {
+            BlockingError::Error(service_error) => Err(service_error),
+            BlockingError::Canceled => Err(GlobalServiceError::InternalServerError),
+        },
+    }
+}
+
+#[instrument(skip_all)]
+pub async fn change_role_handler(
+    http_req: HttpRequest,
+    user_id: web::Path<i32>,
+    req: web::Json<ChangeRoleRequest>,
+    pool: web::Data<Pool>,
+    auth_data: AuthExtractor,
+) -> Result<HttpResponse, GlobalServiceError> {
+    let admin_email = caller_email(&auth_data)?;
+    let client = ClientInfo::from_request(&http_req);
+    let res = telemetry::block(move || {
+        change_role_query(
+            admin_email,
+            user_id.into_inner(),
+            req.into_inner(),
+            client,
+            pool,
+        )
+    })
+    .await;
+
+    match res {
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
+        Err(err) => match err {
+            BlockingError::Error(service_error) => Err(service_error),
+            BlockingError::Canceled => Err(GlobalServiceError::InternalServerError),
+        },
+    }
+}
+
+#[instrument(skip_all)]
+pub async fn disable_user_handler(
+    http_req: HttpRequest,
+    user_id: web::Path<i32>,
+    pool: web::Data<Pool>,
+    auth_data: AuthExtractor,
+) -> Result<HttpResponse, GlobalServiceError> {
+    let admin_email = caller_email(&auth_data)?;
+    let client = ClientInfo::from_request(&http_req);
+    let res = telemetry::block(move || {
+        set_disabled_query(admin_email, user_id.into_inner(), true, client, pool)
+    })
+    .await;
+
+    match res {
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
+        Err(err) => match err {
+            BlockingError::Error(service_error) => Err(service_error),
+            BlockingError::Canceled => Err(GlobalServiceError::InternalServerError),
+        },
+    }
+}
+
+#[instrument(skip_all)]
+pub async fn enable_user_handler(
+    http_req: HttpRequest,
+    user_id: web::Path<i32>,
+    pool: web::Data<Pool>,
+    auth_data: AuthExtractor,
+) -> Result<HttpResponse, GlobalServiceError> {
+    let admin_email = caller_email(&auth_data)?;
+    let client = ClientInfo::from_request(&http_req);
+    let res = telemetry::block(move || {
+        set_disabled_query(admin_email, user_id.into_inner(), false, client, pool)
+    })
+    .await;
+
+    match res {
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
+        Err(err) => match err {
+            BlockingError::Error(service_error) => Err(service_error),
+            BlockingError::Canceled => Err(GlobalServiceError::InternalServerError),
+        },
+    }
+}
+
+#[instrument(skip_all)]
+pub async fn force_password_reset_handler(
+    http_req: HttpRequest,
+    user_id: web::Path<i32>,
+    pool: web::Data<Pool>,
+    auth_data: AuthExtractor,
+    config: web::Data<PasswordResetConfig>,
+    argon2_config: web::Data<Argon2Config>,
+    mail_config: web::Data<MailConfig>,
+) -> Result<HttpResponse, GlobalServiceError> {
+    let admin_email = caller_email(&auth_data)?;
+    let client = ClientInfo::from_request(&http_req);
+    let res = telemetry::block(move || {
+        reset_password_query(
+            admin_email,
+            user_id.into_inner(),
+            client,
+            pool,
+            &config,
+            &argon2_config,
+            &mail_config,
+        )
+    })
+    .await;
+
+    match res {
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
+        Err(err) => match err {
+            BlockingError::Error(service_error) => Err(service_error),
+            BlockingError::Canceled => Err(GlobalServiceError::InternalServerError),
+        },
+    }
+}
+
+#[instrument(skip_all)]
+pub async fn impersonate_user_handler(
+    http_req: HttpRequest,
+    user_id: web::Path<i32>,
+    pool: web::Data<Pool>,
+    auth_data: AuthExtractor,
+) -> Result<HttpResponse, GlobalServiceError> {
+    // hands out a token, so like the credential endpoints it needs a session
+    let admin_email = session_email(&auth_data)?;
+    let client = ClientInfo::from_request(&http_req);
+    let res = telemetry::block(move || {
+        impersonate_query(admin_email, user_id.into_inner(), client, pool)
+    })
+    .await;
+
+    match res {
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
+        Err(err) => match err {
+            BlockingError::Error(service_error) => Err(service_error),
+            BlockingError::Canceled => Err(GlobalServiceError::InternalServerError),
+        },
+    }
+}
+
+fn find_user(conn: &PgConnection, user_id: i32) -> Result<User, GlobalServiceError> {
+    users::table
+        .find(user_id)
+        .first::<User>(conn)
+        .optional()?
+        .ok_or(GlobalServiceError::NotFound(ServiceError::UserNotFound))
+}
+
+// Admins act on other accounts, changing their own role or disabling
+// themselves could leave nobody to undo it
+fn require_other_user(admin: &User, user_id: i32) -> Result<(), GlobalServiceError> {
+    if admin.id == user_id {
+        return Err(GlobalServiceError::Forbidden(
+            ServiceError::PermissionDenied,
+        ));
+    }
+
+    Ok(())
+}
+
+fn filtered(filter: &UserFilter) -> users::BoxedQuery<'_, Pg> {
+    let mut query = users::table.into_boxed();
+
+    if let Some(ref q) = filter.q {
+        let pattern = format!(
+            "%{}%",
+            q.trim()
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        );
+        query = query.filter(
+            users::email
+                .ilike(pattern.clone())
+                .or(users::full_name.ilike(pattern.clone()))
+                .or(users::username.ilike(pattern)),
+        );
+    }
+    if let Some(ref role) = filter.role {
+        query = query.filter(users::role.eq(role));
+    }
+    match filter.disabled {
+        Some(true) => query = query.filter(users::disabled_at.is_not_null()),
+        Some(false) => query = query.filter(users::disabled_at.is_null()),
+        None => {}
+    }
+
+    query
+}
+
+fn list_query(
+    admin_email: String,
+    filter: UserFilter,
+    page: PageQuery,
+    pool: web::Data<Pool>,
+) -> Result<ResponseBody<Page<AdminUserView>>, GlobalServiceError> {
+    let conn: &PgConnection = &pool.get().unwrap();
+    require_admin(conn, &admin_email)?;
+
+    let total = filtered(&filter).count().get_result::<i64>(conn)?;
+    let users = filtered(&filter)
+        .order(users::id)
+        .limit(page.per_page())
+        .offset(page.offset())
+        .load::<User>(conn)?
+        .into_iter()
+        .map(AdminUserView::from)
+        .collect();
+
+    Ok(ResponseBody::new(
+        MESSAGE_GET_USERS_SUCCESS,
+        Some(Page::new(users, &page, total)),
+        None,
+    ))
+}
+
+fn get_query(
+    admin_email: String,
+    user_id: i32,
+    pool: web::Data<Pool>,
+) -> Result<ResponseBody<AdminUserView>, GlobalServiceError> {
+    let conn: &PgConnection = &pool.get().unwrap();
+    require_admin(conn, &admin_email)?;
+    let user = find_user(conn, user_id)?;
+
+    Ok(ResponseBody::new(
+        MESSAGE_GET_USER_SUCCESS,
+        Some(AdminUserView::from(user)),
+        None,
+    ))
+}
+
+fn change_role_query(
+    admin_email: String,
+    user_id: i32,
+    req: ChangeRoleRequest,
+    client: ClientInfo,
+    pool: web::Data<Pool>,
+) -> Result<ResponseBody<AdminUserView>, GlobalServiceError> {
+    let conn: &PgConnection = &pool.get().unwrap();
+    let admin = require_admin(conn, &admin_email)?;
+    require_other_user(&admin, user_id)?;
+
+    if !ROLES.contains(&req.role.as_str()) {
+        return Err(GlobalServiceError::BadRequest(format!(
+            "Unknown role {}",
+            req.role
+        )));
+    }
+
+    let user = find_user(conn, user_id)?;
+    let user = conn.transaction::<_, GlobalServiceError, _>(|| {
+        let user = diesel::update(&user)
+            .set(users::role.eq(&req.role))
+            .get_result::<User>(conn)?;
+
+        audit::record(
+            conn,
+            audit::ADMIN_ROLE_CHANGED,
+            Some(admin.id),
+            Some(user.id),
+            &client,
+            json!({ "role": user.role }),
+        )?;
+
+        Ok(user)
+    })?;
+
+    Ok(ResponseBody::new(
+        MESSAGE_CHANGE_ROLE_SUCCESS,
+        Some(AdminUserView::from(user)),
+        None,
+    ))
+}
+
+fn set_disabled_query(
+    admin_email: String,
+    user_id: i32,
+    disabled: bool,
+    client: ClientInfo,
+    pool: web::Data<Pool>,
+) -> Result<ResponseBody<AdminUserView>, GlobalServiceError> {
+    let conn: &PgConnection = &pool.get().unwrap();
+    let admin = require_admin(conn, &admin_email)?;
+    require_other_user(&admin, user_id)?;
+
+    let user = find_user(conn, user_id)?;
+    let disabled_at = if disabled {
+        // Keep the original time when it was already disabled
+        Some(user.disabled_at.unwrap_or_else(Utc::now))
+    } else {
+        None
+    };
+    let (event, message) = if disabled {
+        (audit::ADMIN_USER_DISABLED, MESSAGE_DISABLE_USER_SUCCESS)
+    } else {
+        (audit::ADMIN_USER_ENABLED, MESSAGE_ENABLE_USER_SUCCESS)
+    };
+
+    let user = conn.transaction::<_, GlobalServiceError, _>(|| {
+        let user = diesel::update(&user)
+            .set(users::disabled_at.eq(disabled_at))
+            .get_result::<User>(conn)?;
+
+        if disabled {
+            // Logged out everywhere, enabling the account again doesn't bring
+            // the old sessions back
+            diesel::delete(sessions::table.filter(sessions::user_id.eq(user.id))).execute(conn)?;
+        }
+        audit::record(
+            conn,
+            event,
+            Some(admin.id),
+            Some(user.id),
+            &client,
+            json!({}),
+        )?;
+
+        Ok(user)
+    })?;
+
+    Ok(ResponseBody::new(
+        message,
+        Some(AdminUserView::from(user)),
+        None,
+    ))
+}
+
+// The current password stops working right away, the user picks a new one
+// through the emailed link
+fn reset_password_query(
+    admin_email: String,
+    user_id: i32,
+    client: ClientInfo,
+    pool: web::Data<Pool>,
+    config: &PasswordResetConfig,
+    argon2_config: &Argon2Config,
+    mail_config: &MailConfig,
+) -> Result<ResponseBody<()>, GlobalServiceError> {
+    let conn: &PgConnection = &pool.get().unwrap();
+    let admin = require_admin(conn, &admin_email)?;
+
+    let user = find_user(conn, user_id)?;
+    let token = generate_random_token(32);
+    let mut link =
+        Url::parse(&config.url).map_err(|_err| GlobalServiceError::InternalServerError)?;
+    link.query_pairs_mut().append_pair("token", &token);
+
+    // Nobody knows this password
+    let unusable_password = hash_password(&generate_random_token(32), argon2_config)?;
+    conn.transaction::<_, GlobalServiceError, _>(|| {
+        diesel::update(&user)
+            .set(users::hashed_password.eq(unusable_password))
+            .execute(conn)?;
+        diesel::delete(sessions::table.filter(sessions::user_id.eq(user.id))).execute(conn)?;
+        diesel::insert_into(password_resets::table)
+            .values(&NewPasswordReset {
+                user_id: user.id,
+                token_hash: &sha256_hex(&token),
+                expires_at: Utc::now() + Duration::hours(config.lifetime_hours),
+            })
+            .execute(conn)?;
+
+        audit::record(
+            conn,
+            audit::ADMIN_PASSWORD_RESET,
+            Some(admin.id),
+            Some(user.id),
+            &client,
+            json!({}),
+        )
+    })?;
+
+    // Only once the reset is committed. A failed send is logged, the admin
+    // can reset again to send a new link.
+    send_email_in_background(
+        mail_config,
+        user.email,
+        "Choose a new password",
+        format!(
+            "An administrator reset the password of your account. Use this link within \
+             the next {} hours to choose a new one:\n\n{}",
+            config.lifetime_hours, link
+        ),
+    );
+
+    Ok(ResponseBody::new(MESSAGE_PASSWORD_RESET_SENT, None, None))
+}
+
+fn impersonate_query(
+    admin_email: String,
+    user_id: i32,
+    client: ClientInfo,
+    pool: web::Data<Pool>,
+) -> Result<ResponseBody<ImpersonationResponse>, GlobalServiceError> {
+    let conn: &PgConnection = &pool.get().unwrap();
+    let admin = require_admin(conn, &admin_email)?;
+    require_other_user(&admin, user_id)?;
+
+    let user = find_user(conn, user_id)?;
+    // Impersonating another admin would be a way around their audit trail
+    if user.role == ROLE_ADMIN {
+        return Err(GlobalServiceError::Forbidden(
+            ServiceError::PermissionDenied,
+        ));
+    }
+    if user.disabled_at.is_some() {
+        return Err(GlobalServiceError::Forbidden(ServiceError::AccountDisabled));
+    }
+
+    let token = conn.transaction::<_, GlobalServiceError, _>(|| {
+        create_impersonation_token(conn, &admin, &user, &client)
+    })?;
+
+    Ok(ResponseBody::new(
+        MESSAGE_IMPERSONATION_STARTED,
+        Some(ImpersonationResponse {
+            user: AdminUserView::from(user),
+            token,
+        }),
+        None,
+    ))
+}
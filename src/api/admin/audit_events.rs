@@ -0,0 +1,103 @@
+use crate::api::admin::{caller_email, require_admin};
+use crate::constants::MESSAGE_GET_AUDIT_EVENTS_SUCCESS;
+use crate::extractor::auth::AuthExtractor;
+use crate::model::audit::AuditEvent;
+use crate::model::pagination::{Page, PageQuery};
+use crate::model::{db::Pool, errors::GlobalServiceError, response::ResponseBody};
+use crate::schema::audit_events;
+use crate::telemetry;
+use actix_web::error::BlockingError;
+use actix_web::{web, HttpResponse, Result};
+use chrono::{DateTime, Utc};
+use diesel::pg::Pg;
+use diesel::{BoolExpressionMethods, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use serde::Deserialize;
+use tracing::instrument;
+
+// Every filter is optional, `user_id` matches either side of the event
+#[derive(Deserialize)]
+pub struct AuditEventFilter {
+    event: Option<String>,
+    actor_user_id: Option<i32>,
+    target_user_id: Option<i32>,
+    user_id: Option<i32>,
+    ip: Option<String>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+}
+
+#[instrument(skip_all)]
+pub async fn list_audit_events_handler(
+    filter: web::Query<AuditEventFilter>,
+    page: web::Query<PageQuery>,
+    pool: web::Data<Pool>,
+    auth_data: AuthExtractor,
+) -> Result<HttpResponse, GlobalServiceError> {
+    let admin_email = caller_email(&auth_data)?;
+    let res =
+        telemetry::block(move || query(admin_email, filter.into_inner(), page.into_inner(), pool))
+            .await;
+
+    match res {
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
+        Err(err) => match err {
+            BlockingError::Error(service_error) => Err(service_error),
+            BlockingError::Canceled => Err(GlobalServiceError::InternalServerError),
+        },
+    }
+}
+
+fn filtered(filter: &AuditEventFilter) -> audit_events::BoxedQuery<'_, Pg> {
+    let mut query = audit_events::table.into_boxed();
+
+    if let Some(ref event) = filter.event {
+        query = query.filter(audit_events::event.eq(event));
+    }
+    if let Some(actor_user_id) = filter.actor_user_id {
+        query = query.filter(audit_events::actor_user_id.eq(actor_user_id));
+    }
+    if let Some(target_user_id) = filter.target_user_id {
+        query = query.filter(audit_events::target_user_id.eq(target_user_id));
+    }
+    if let Some(user_id) = filter.user_id {
+        query = query.filter(
+            audit_events::actor_user_id
+                .eq(user_id)
+                .or(audit_events::target_user_id.eq(user_id)),
+        );
+    }
+    if let Some(ref ip) = filter.ip {
+        query = query.filter(audit_events::ip.eq(ip));
+    }
+    if let Some(since) = filter.since {
+        query = query.filter(audit_events::created_at.ge(since));
+    }
+    if let Some(until) = filter.until {
+        query = query.filter(audit_events::created_at.lt(until));
+    }
+
+    query
+}
+
+fn query(
+    admin_email: String,
+    filter: AuditEventFilter,
+    page: PageQuery,
+    pool: web::Data<Pool>,
+) -> Result<ResponseBody<Page<AuditEvent>>, GlobalServiceError> {
+    let conn: &PgConnection = &pool.get().unwrap();
+    require_admin(conn, &admin_email)?;
+
+    let total = filtered(&filter).count().get_result::<i64>(conn)?;
+    let events = filtered(&filter)
+        .order(audit_events::id.desc())
+        .limit(page.per_page())
+        .offset(page.offset())
+        .load::<AuditEvent>(conn)?;
+
+    Ok(ResponseBody::new(
+        MESSAGE_GET_AUDIT_EVENTS_SUCCESS,
+        Some(Page::new(events, &page, total)),
+        None,
+    ))
+}
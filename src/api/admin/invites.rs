@@ -0,0 +1,209 @@
+use crate::api::admin::{caller_email, require_admin};
+use crate::audit;
+use crate::constants::{
+    MESSAGE_CREATE_INVITE_SUCCESS, MESSAGE_GET_INVITES_SUCCESS, MESSAGE_REVOKE_INVITE_SUCCESS,
+    ROLES, ROLE_USER,
+};
+use crate::extractor::auth::AuthExtractor;
+use crate::model::errors::ServiceError;
+use crate::model::invite::{Invite, NewInvite};
+use crate::model::pagination::{Page, PageQuery};
+use crate::model::{db::Pool, errors::GlobalServiceError, response::ResponseBody};
+use crate::schema::invites;
+use crate::session::ClientInfo;
+use crate::telemetry;
+use crate::utils::{generate_random_token, sha256_hex};
+use actix_web::error::BlockingError;
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use chrono::{Duration, Utc};
+use diesel::{ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::instrument;
+
+#[derive(Deserialize)]
+pub struct CreateInviteRequest {
+    // single use when left out
+    max_uses: Option<i32>,
+    // never expires when left out
+    expires_in_days: Option<i64>,
+    role: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct CreateInviteResponse {
+    #[serde(flatten)]
+    invite: Invite,
+    // only ever returned here
+    code: String,
+}
+
+#[instrument(skip_all)]
+pub async fn create_invite_handler(
+    http_req: HttpRequest,
+    req: web::Json<CreateInviteRequest>,
+    pool: web::Data<Pool>,
+    auth_data: AuthExtractor,
+) -> Result<HttpResponse, GlobalServiceError> {
+    let admin_email = caller_email(&auth_data)?;
+    let client = ClientInfo::from_request(&http_req);
+    let res =
+        telemetry::block(move || create_query(admin_email, req.into_inner(), client, pool)).await;
+
+    match res {
+        Ok(response) => Ok(HttpResponse::Created().json(response)),
+        Err(err) => match err {
+            BlockingError::Error(service_error) => Err(service_error),
+            BlockingError::Canceled => Err(GlobalServiceError::InternalServerError),
+        },
+    }
+}
+
+#[instrument(skip_all)]
+pub async fn list_invites_handler(
+    page: web::Query<PageQuery>,
+    pool: web::Data<Pool>,
+    auth_data: AuthExtractor,
+) -> Result<HttpResponse, GlobalServiceError> {
+    let admin_email = caller_email(&auth_data)?;
+    let res = telemetry::block(move || list_query(admin_email, page.into_inner(), pool)).await;
+
+    match res {
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
+        Err(err) => match err {
+            BlockingError::Error(service_error) => Err(service_error),
+            BlockingError::Canceled => Err(GlobalServiceError::InternalServerError),
+        },
+    }
+}
+
+#[instrument(skip_all)]
+pub async fn revoke_invite_handler(
+    http_req: HttpRequest,
+    invite_id: web::Path<i32>,
+    pool: web::Data<Pool>,
+    auth_data: AuthExtractor,
+) -> Result<HttpResponse, GlobalServiceError> {
+    let admin_email = caller_email(&auth_data)?;
+    let client = ClientInfo::from_request(&http_req);
+    let res =
+        telemetry::block(move || revoke_query(admin_email, invite_id.into_inner(), client, pool))
+            .await;
+
+    match res {
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
+        Err(err) => match err {
+            BlockingError::Error(service_error) => Err(service_error),
+            BlockingError::Canceled => Err(GlobalServiceError::InternalServerError),
+        },
+    }
+}
+
+fn create_query(
+    admin_email: String,
+    req: CreateInviteRequest,
+    client: ClientInfo,
+    pool: web::Data<Pool>,
+) -> Result<ResponseBody<CreateInviteResponse>, GlobalServiceError> {
+    let conn: &PgConnection = &pool.get().unwrap();
+    let admin = require_admin(conn, &admin_email)?;
+
+    let max_uses = req.max_uses.unwrap_or(1);
+    if max_uses <= 0 {
+        return Err(GlobalServiceError::BadRequest(
+            "max_uses must be positive".to_string(),
+        ));
+    }
+    let role = req.role.unwrap_or_else(|| ROLE_USER.to_string());
+    if !ROLES.contains(&role.as_str()) {
+        return Err(GlobalServiceError::BadRequest(format!(
+            "Unknown role {}",
+            role
+        )));
+    }
+    let expires_at = match req.expires_in_days {
+        Some(days) if days <= 0 => {
+            return Err(GlobalServiceError::BadRequest(
+                "expires_in_days must be positive".to_string(),
+            ))
+        }
+        Some(days) => Some(Utc::now() + Duration::days(days)),
+        None => None,
+    };
+
+    let code = generate_random_token(16);
+    let invite = diesel::insert_into(invites::table)
+        .values(&NewInvite {
+            code_hash: &sha256_hex(&code),
+            prefix: &code[..8],
+            role: &role,
+            max_uses,
+            expires_at,
+            created_by: Some(admin.id),
+        })
+        .get_result::<Invite>(conn)?;
+
+    audit::record(
+        conn,
+        audit::ADMIN_INVITE_CREATED,
+        Some(admin.id),
+        None,
+        &client,
+        json!({ "invite_id": invite.id, "role": invite.role, "max_uses": invite.max_uses }),
+    )?;
+
+    Ok(ResponseBody::new(
+        MESSAGE_CREATE_INVITE_SUCCESS,
+        Some(CreateInviteResponse { invite, code }),
+        None,
+    ))
+}
+
+fn list_query(
+    admin_email: String,
+    page: PageQuery,
+    pool: web::Data<Pool>,
+) -> Result<ResponseBody<Page<Invite>>, GlobalServiceError> {
+    let conn: &PgConnection = &pool.get().unwrap();
+    require_admin(conn, &admin_email)?;
+
+    let total = invites::table.count().get_result::<i64>(conn)?;
+    let invites = invites::table
+        .order(invites::id.desc())
+        .limit(page.per_page())
+        .offset(page.offset())
+        .load::<Invite>(conn)?;
+
+    Ok(ResponseBody::new(
+        MESSAGE_GET_INVITES_SUCCESS,
+        Some(Page::new(invites, &page, total)),
+        None,
+    ))
+}
+
+// Accounts already registered with the invite are not affected
+fn revoke_query(
+    admin_email: String,
+    invite_id: i32,
+    client: ClientInfo,
+    pool: web::Data<Pool>,
+) -> Result<ResponseBody<()>, GlobalServiceError> {
+    let conn: &PgConnection = &pool.get().unwrap();
+    let admin = require_admin(conn, &admin_email)?;
+
+    match diesel::delete(invites::table.find(invite_id)).execute(conn)? {
+        0 => Err(GlobalServiceError::NotFound(ServiceError::InviteNotFound)),
+        _ => {
+            audit::record(
+                conn,
+                audit::ADMIN_INVITE_REVOKED,
+                Some(admin.id),
+                None,
+                &client,
+                json!({ "invite_id": invite_id }),
+            )?;
+
+            Ok(ResponseBody::new(MESSAGE_REVOKE_INVITE_SUCCESS, None, None))
+        }
+    }
+}
@@ -0,0 +1,33 @@
+pub mod accounts;
+pub mod audit_events;
+pub mod invites;
+pub mod unlock_user;
+
+use crate::constants::ROLE_ADMIN;
+use crate::extractor::auth::AuthExtractor;
+use crate::model::errors::{GlobalServiceError, ServiceError};
+use crate::model::user::{find_by_email, User};
+use diesel::PgConnection;
+
+// Admin endpoints also take API keys with the admin scope, whoever is behind
+// the request still has to pass `require_admin`
+pub fn caller_email(auth_data: &AuthExtractor) -> Result<String, GlobalServiceError> {
+    auth_data
+        .as_ref()
+        .map(|data| data.email.clone())
+        .ok_or(GlobalServiceError::Unauthorized(ServiceError::InvalidToken))
+}
+
+// Loads the authenticated user and makes sure they are an admin
+pub fn require_admin(conn: &PgConnection, user_email: &str) -> Result<User, GlobalServiceError> {
+    let user = find_by_email(conn, user_email)
+        .map_err(|_err| GlobalServiceError::Forbidden(ServiceError::PermissionDenied))?;
+
+    if user.role != ROLE_ADMIN {
+        return Err(GlobalServiceError::Forbidden(
+            ServiceError::PermissionDenied,
+        ));
+    }
+
+    Ok(user)
+}
@@ -0,0 +1,61 @@
+use crate::api::admin::{caller_email, require_admin};
+use crate::api::auth::lockout;
+use crate::audit;
+use crate::constants::MESSAGE_UNLOCK_USER_SUCCESS;
+use crate::extractor::auth::AuthExtractor;
+use crate::model::errors::ServiceError;
+use crate::model::{db::Pool, errors::GlobalServiceError, response::ResponseBody};
+use crate::session::ClientInfo;
+use crate::telemetry;
+use actix_web::error::BlockingError;
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use diesel::PgConnection;
+use serde_json::json;
+use tracing::instrument;
+
+#[instrument(skip_all)]
+pub async fn unlock_user_handler(
+    http_req: HttpRequest,
+    pool: web::Data<Pool>,
+    auth_data: AuthExtractor,
+    user_id: web::Path<i32>,
+) -> Result<HttpResponse, GlobalServiceError> {
+    let admin_email = caller_email(&auth_data)?;
+    let client = ClientInfo::from_request(&http_req);
+    let res =
+        telemetry::block(move || query(admin_email, user_id.into_inner(), client, pool)).await;
+
+    match res {
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
+        Err(err) => match err {
+            BlockingError::Error(service_error) => Err(service_error),
+            BlockingError::Canceled => Err(GlobalServiceError::InternalServerError),
+        },
+    }
+}
+
+fn query(
+    admin_email: String,
+    user_id: i32,
+    client: ClientInfo,
+    pool: web::Data<Pool>,
+) -> Result<ResponseBody<()>, GlobalServiceError> {
+    let conn: &PgConnection = &pool.get().unwrap();
+    let admin = require_admin(conn, &admin_email)?;
+
+    match lockout::reset_account(conn, user_id)? {
+        0 => Err(GlobalServiceError::NotFound(ServiceError::UserNotFound)),
+        _ => {
+            audit::record(
+                conn,
+                audit::ADMIN_USER_UNLOCKED,
+                Some(admin.id),
+                Some(user_id),
+                &client,
+                json!({}),
+            )?;
+
+            Ok(ResponseBody::new(MESSAGE_UNLOCK_USER_SUCCESS, None, None))
+        }
+    }
+}
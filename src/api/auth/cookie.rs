@@ -0,0 +1,62 @@
+use crate::config::AuthCookieConfig;
+use crate::constants::JWT_EXPIRATION_SECONDS;
+use crate::utils::generate_random_token;
+use actix_web::cookie::{Cookie, SameSite};
+
+fn same_site(config: &AuthCookieConfig) -> SameSite {
+    match config.same_site.to_ascii_lowercase().as_str() {
+        "none" => SameSite::None,
+        "lax" => SameSite::Lax,
+        _ => SameSite::Strict,
+    }
+}
+
+fn build_cookie(
+    config: &AuthCookieConfig,
+    name: String,
+    value: String,
+    http_only: bool,
+    max_age_seconds: i64,
+) -> Cookie<'static> {
+    let mut cookie = Cookie::build(name, value)
+        .path("/")
+        .http_only(http_only)
+        .secure(config.secure)
+        .same_site(same_site(config))
+        .max_age(time::Duration::seconds(max_age_seconds))
+        .finish();
+
+    if let Some(ref domain) = config.domain {
+        cookie.set_domain(domain.clone());
+    }
+
+    cookie
+}
+
+// The JWT is kept away from JavaScript, the CSRF token is meant to be read by
+// the frontend and sent back in the `X-CSRF-Token` header (double submit).
+pub fn session_cookies(config: &AuthCookieConfig, token: String) -> Vec<Cookie<'static>> {
+    vec![
+        build_cookie(
+            config,
+            config.name.clone(),
+            token,
+            true,
+            JWT_EXPIRATION_SECONDS,
+        ),
+        build_cookie(
+            config,
+            config.csrf_name.clone(),
+            generate_random_token(32),
+            false,
+            JWT_EXPIRATION_SECONDS,
+        ),
+    ]
+}
+
+pub fn expired_session_cookies(config: &AuthCookieConfig) -> Vec<Cookie<'static>> {
+    vec![
+        build_cookie(config, config.name.clone(), String::new(), true, 0),
+        build_cookie(config, config.csrf_name.clone(), String::new(), false, 0),
+    ]
+}
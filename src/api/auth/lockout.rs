@@ -0,0 +1,128 @@
+use crate::config::LoginThrottleConfig;
+use crate::model::errors::{GlobalServiceError, ServiceError};
+use crate::model::login_attempt::LoginIpAttempt;
+use crate::model::user::User;
+use crate::schema::{login_ip_attempts, users};
+use chrono::{DateTime, Duration, Utc};
+use diesel::sql_types::{Int4, Text, Timestamptz};
+use diesel::{
+    sql_query, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, RunQueryDsl,
+};
+
+fn retry_after_seconds(locked_until: DateTime<Utc>, now: DateTime<Utc>) -> i64 {
+    // round up so clients never retry a second too early
+    let millis = (locked_until - now).num_milliseconds();
+    (millis + 999) / 1000
+}
+
+// Counters are incremented by the database, concurrent failures would
+// otherwise all read the same count and only add one between them
+#[derive(QueryableByName)]
+struct FailedAttempts {
+    #[sql_type = "Int4"]
+    failed_attempts: i32,
+}
+
+pub fn check_ip(conn: &PgConnection, ip: &str) -> Result<(), GlobalServiceError> {
+    let now = Utc::now();
+    let attempt = login_ip_attempts::table
+        .find(ip)
+        .first::<LoginIpAttempt>(conn)
+        .optional()
+        .map_err(|_err| GlobalServiceError::InternalServerError)?;
+
+    match attempt.and_then(|attempt| attempt.locked_until) {
+        Some(locked_until) if locked_until > now => Err(GlobalServiceError::TooManyRequests(
+            ServiceError::TooManyAttempts,
+            retry_after_seconds(locked_until, now),
+        )),
+        _ => Ok(()),
+    }
+}
+
+pub fn account_locked(user: &User) -> bool {
+    user.locked_until
+        .is_some_and(|locked_until| locked_until > Utc::now())
+}
+
+pub fn record_ip_failure(
+    conn: &PgConnection,
+    ip: &str,
+    config: &LoginThrottleConfig,
+) -> Result<(), GlobalServiceError> {
+    let now = Utc::now();
+    let window_start = now - Duration::seconds(config.attempt_window_seconds);
+
+    // failures older than the window don't count, the next one starts over
+    let FailedAttempts { failed_attempts } = sql_query(
+        "INSERT INTO login_ip_attempts (ip, failed_attempts, last_failed_at) \
+         VALUES ($1, 1, $3) \
+         ON CONFLICT (ip) DO UPDATE SET \
+             failed_attempts = CASE WHEN login_ip_attempts.last_failed_at > $2 \
+                 THEN login_ip_attempts.failed_attempts + 1 ELSE 1 END, \
+             last_failed_at = EXCLUDED.last_failed_at \
+         RETURNING failed_attempts",
+    )
+    .bind::<Text, _>(ip)
+    .bind::<Timestamptz, _>(window_start)
+    .bind::<Timestamptz, _>(now)
+    .get_result(conn)
+    .map_err(|_err| GlobalServiceError::InternalServerError)?;
+
+    if let Some(seconds) = config.lockout_seconds(failed_attempts, config.max_attempts_per_ip) {
+        diesel::update(login_ip_attempts::table.find(ip))
+            .set(login_ip_attempts::locked_until.eq(now + Duration::seconds(seconds)))
+            .execute(conn)
+            .map_err(|_err| GlobalServiceError::InternalServerError)?;
+    }
+
+    Ok(())
+}
+
+// Returns the failures counted so far, and the lock expiry when this failure
+// locked the account
+pub fn record_account_failure(
+    conn: &PgConnection,
+    user: &User,
+    config: &LoginThrottleConfig,
+) -> Result<(i32, Option<DateTime<Utc>>), GlobalServiceError> {
+    let now = Utc::now();
+    let window_start = now - Duration::seconds(config.attempt_window_seconds);
+
+    let FailedAttempts { failed_attempts } = sql_query(
+        "UPDATE users SET \
+             failed_login_attempts = CASE WHEN last_failed_login_at > $2 \
+                 THEN failed_login_attempts + 1 ELSE 1 END, \
+             last_failed_login_at = $3 \
+         WHERE id = $1 \
+         RETURNING failed_login_attempts AS failed_attempts",
+    )
+    .bind::<Int4, _>(user.id)
+    .bind::<Timestamptz, _>(window_start)
+    .bind::<Timestamptz, _>(now)
+    .get_result(conn)
+    .map_err(|_err| GlobalServiceError::InternalServerError)?;
+
+    let locked_until = config
+        .lockout_seconds(failed_attempts, config.max_attempts_per_account)
+        .map(|seconds| now + Duration::seconds(seconds));
+    if let Some(locked_until) = locked_until {
+        diesel::update(user)
+            .set(users::locked_until.eq(locked_until))
+            .execute(conn)
+            .map_err(|_err| GlobalServiceError::InternalServerError)?;
+    }
+
+    Ok((failed_attempts, locked_until))
+}
+
+pub fn reset_account(conn: &PgConnection, user_id: i32) -> Result<usize, GlobalServiceError> {
+    diesel::update(users::table.find(user_id))
+        .set((
+            users::failed_login_attempts.eq(0),
+            users::last_failed_login_at.eq(None::<DateTime<Utc>>),
+            users::locked_until.eq(None::<DateTime<Utc>>),
+        ))
+        .execute(conn)
+        .map_err(|_err| GlobalServiceError::InternalServerError)
+}
@@ -1,37 +1,107 @@
-use crate::constants::MESSAGE_LOGIN_SUCCESS;
+use crate::api::auth::cookie::session_cookies;
+use crate::api::auth::lockout;
+use crate::api::auth::webauthn::{has_passkeys, request_options, PasskeyChallenge, RequestOptions};
+use crate::audit;
+use crate::config::{
+    Argon2Config, AuthCookieConfig, LoginThrottleConfig, MailConfig, WebauthnConfig,
+};
+use crate::constants::{MESSAGE_LOGIN_SUCCESS, MESSAGE_SECOND_FACTOR_REQUIRED};
+use crate::mailer::send_email_in_background;
+use crate::metrics;
 use crate::model::errors::ServiceError;
 use crate::model::response::ResponseBody;
 use crate::model::user::User;
-use crate::schema::users::dsl::{email, users};
+use crate::model::webauthn::PURPOSE_SECOND_FACTOR;
+use crate::schema::users::dsl::{email, hashed_password, users};
+use crate::telemetry;
 use actix_web::error::BlockingError;
-use actix_web::{web, HttpResponse, Result};
+use actix_web::{web, HttpRequest, HttpResponse, Result};
 use diesel::{PgConnection, QueryDsl, RunQueryDsl};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::instrument;
 
-use crate::utils::{generate_jwt, verify_password};
-use crate::{model::db::Pool, model::errors::GlobalServiceError};
+use crate::session::{create_session_token, ClientInfo};
+use crate::utils::{hash_password, normalize_email, password_needs_rehash, verify_password};
+use crate::{model::db::lower, model::db::Pool, model::errors::GlobalServiceError};
 
 #[derive(Deserialize)]
 pub struct LoginRequest {
     email: String,
     password: String,
+    // browser clients get the token in an HttpOnly cookie instead of the body
+    #[serde(default)]
+    cookie: bool,
 }
 
 #[derive(Serialize)]
 pub struct LoginResponse {
-    email: String,
-    full_name: String,
-    token: String,
+    pub email: String,
+    pub full_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    // set instead of the token when the user still has to confirm with a passkey
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub second_factor: Option<PasskeyChallenge<RequestOptions>>,
 }
 
+// Moves the token into the session cookies when the client asked for them
+pub fn login_http_response(
+    mut login_response: ResponseBody<LoginResponse>,
+    use_cookie: bool,
+    cookie_config: &AuthCookieConfig,
+) -> HttpResponse {
+    let mut response = HttpResponse::Ok();
+
+    if use_cookie {
+        let token = login_response
+            .data
+            .as_mut()
+            .and_then(|data| data.token.take());
+        // No session yet while a second factor is pending
+        if let Some(token) = token {
+            for cookie in session_cookies(cookie_config, token) {
+                response.cookie(cookie);
+            }
+        }
+    }
+
+    response.json(login_response)
+}
+
+#[instrument(skip_all)]
+#[allow(clippy::too_many_arguments)]
 pub async fn login_handler(
+    http_req: HttpRequest,
     req: web::Json<LoginRequest>,
     pool: web::Data<Pool>,
+    cookie_config: web::Data<AuthCookieConfig>,
+    webauthn_config: web::Data<WebauthnConfig>,
+    throttle_config: web::Data<LoginThrottleConfig>,
+    argon2_config: web::Data<Argon2Config>,
+    mail_config: web::Data<MailConfig>,
 ) -> Result<HttpResponse, GlobalServiceError> {
-    let res = web::block(move || query(req.into_inner(), pool)).await;
+    let client = ClientInfo::from_request(&http_req);
+    let use_cookie = req.cookie;
+    let res = telemetry::block(move || {
+        query(
+            req.into_inner(),
+            client,
+            pool,
+            &webauthn_config,
+            &throttle_config,
+            &argon2_config,
+            &mail_config,
+        )
+    })
+    .await;
 
     match res {
-        Ok(login_response) => return Ok(HttpResponse::Ok().json(login_response)),
+        Ok(login_response) => Ok(login_http_response(
+            login_response,
+            use_cookie,
+            &cookie_config,
+        )),
         Err(err) => match err {
             BlockingError::Error(service_error) => Err(service_error),
             BlockingError::Canceled => Err(GlobalServiceError::InternalServerError),
@@ -41,45 +111,144 @@ pub async fn login_handler(
 
 fn query(
     req: LoginRequest,
+    client: ClientInfo,
     pool: web::Data<Pool>,
+    webauthn_config: &WebauthnConfig,
+    throttle_config: &LoginThrottleConfig,
+    argon2_config: &Argon2Config,
+    mail_config: &MailConfig,
 ) -> Result<ResponseBody<LoginResponse>, GlobalServiceError> {
     let conn: &PgConnection = &pool.get().unwrap();
     use crate::diesel::ExpressionMethods;
 
-    let res = users.filter(email.eq(&req.email)).load::<User>(conn);
+    let ip = &client.ip;
+    let req_email = normalize_email(&req.email);
+    let record_failure = |user_id: Option<i32>, reason: &str| {
+        metrics::record_login("password", "failure");
+        audit::record(
+            conn,
+            audit::LOGIN_FAILED,
+            None,
+            user_id,
+            &client,
+            json!({ "method": "password", "email": req_email, "reason": reason }),
+        )
+    };
+
+    if let Err(err) = lockout::check_ip(conn, ip) {
+        record_failure(None, "ip_locked")?;
+        return Err(err);
+    }
+
+    let res = users
+        .filter(lower(email).eq(&req_email))
+        .first::<User>(conn);
 
     match res {
         Err(diesel::result::Error::NotFound) => {
-            return Err(GlobalServiceError::Unauthorized(
+            record_failure(None, "unknown_email")?;
+            lockout::record_ip_failure(conn, ip, throttle_config)?;
+            Err(GlobalServiceError::Unauthorized(
                 ServiceError::EmailOrPasswordMismatch,
             ))
         }
-        Err(_) => return Err(GlobalServiceError::InternalServerError),
-        Ok(mut current_users) => {
-            if let Some(user) = current_users.pop() {
-                match verify_password(&req.password, &user.hashed_password) {
-                    Ok(_) => {
-                        let jwt_token = generate_jwt(&req.email)?;
+        Err(_) => Err(GlobalServiceError::InternalServerError),
+        Ok(user) => {
+            // Answered like an unknown email, a distinct response would tell
+            // which emails have accounts. The owner learns about the lock by
+            // email.
+            if lockout::account_locked(&user) {
+                record_failure(Some(user.id), "account_locked")?;
+                lockout::record_ip_failure(conn, ip, throttle_config)?;
+                return Err(GlobalServiceError::Unauthorized(
+                    ServiceError::EmailOrPasswordMismatch,
+                ));
+            }
+
+            match verify_password(&req.password, &user.hashed_password) {
+                Ok(_) => {
+                    if user.disabled_at.is_some() {
+                        record_failure(Some(user.id), "account_disabled")?;
+                        return Err(GlobalServiceError::Forbidden(ServiceError::AccountDisabled));
+                    }
+
+                    if user.failed_login_attempts > 0 {
+                        lockout::reset_account(conn, user.id)?;
+                    }
+
+                    if password_needs_rehash(&user.hashed_password, argon2_config) {
+                        // A failed upgrade must not fail the login, it is retried next time
+                        if let Ok(new_hash) = hash_password(&req.password, argon2_config) {
+                            let _ = diesel::update(&user)
+                                .set(hashed_password.eq(new_hash))
+                                .execute(conn);
+                        }
+                    }
+
+                    if has_passkeys(conn, user.id)? {
+                        metrics::record_login("password", "second_factor");
+                        let second_factor = request_options(
+                            conn,
+                            Some(user.id),
+                            PURPOSE_SECOND_FACTOR,
+                            webauthn_config,
+                        )?;
 
                         return Ok(ResponseBody::new(
-                            MESSAGE_LOGIN_SUCCESS,
+                            MESSAGE_SECOND_FACTOR_REQUIRED,
                             Some(LoginResponse {
-                                token: jwt_token,
+                                token: None,
                                 email: user.email,
                                 full_name: user.full_name.unwrap_or_default(),
+                                second_factor: Some(second_factor),
                             }),
                             None,
                         ));
                     }
-                    Err(_) => {
-                        return Err(GlobalServiceError::Unauthorized(
-                            ServiceError::EmailOrPasswordMismatch,
-                        ))
+
+                    let jwt_token = create_session_token(conn, &user, &client, "password")?;
+                    metrics::record_login("password", "success");
+
+                    Ok(ResponseBody::new(
+                        MESSAGE_LOGIN_SUCCESS,
+                        Some(LoginResponse {
+                            token: Some(jwt_token),
+                            email: user.email,
+                            full_name: user.full_name.unwrap_or_default(),
+                            second_factor: None,
+                        }),
+                        None,
+                    ))
+                }
+                Err(_) => {
+                    record_failure(Some(user.id), "wrong_password")?;
+                    lockout::record_ip_failure(conn, ip, throttle_config)?;
+                    let (failed_attempts, locked_until) =
+                        lockout::record_account_failure(conn, &user, throttle_config)?;
+
+                    if let Some(locked_until) = locked_until {
+                        // The owner should know, but the login response must not wait on it
+                        send_email_in_background(
+                            mail_config,
+                            user.email.clone(),
+                            "Your account has been locked",
+                            format!(
+                                "We noticed {} failed sign-in attempts on your account, \
+                                 the last one from {}.\n\n\
+                                 Sign-in is locked until {}. If this wasn't you, \
+                                 consider changing your password once the lock expires.",
+                                failed_attempts,
+                                ip,
+                                locked_until.to_rfc2822()
+                            ),
+                        );
                     }
+
+                    Err(GlobalServiceError::Unauthorized(
+                        ServiceError::EmailOrPasswordMismatch,
+                    ))
                 }
             }
-
-            return Err(GlobalServiceError::InternalServerError);
         }
     }
 }
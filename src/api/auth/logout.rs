@@ -0,0 +1,52 @@
+use crate::api::auth::cookie::expired_session_cookies;
+use crate::audit;
+use crate::config::AuthCookieConfig;
+use crate::constants::MESSAGE_LOGOUT_SUCCESS;
+use crate::extractor::auth::AuthExtractor;
+use crate::model::{db::Pool, errors::GlobalServiceError, response::ResponseBody};
+use crate::schema::sessions;
+use crate::session::ClientInfo;
+use crate::telemetry;
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use diesel::{OptionalExtension, PgConnection, QueryDsl, RunQueryDsl};
+use serde_json::json;
+use tracing::instrument;
+
+// Ends the session behind the token, cookie clients also get their cookies
+// cleared
+#[instrument(skip_all)]
+pub async fn logout_handler(
+    http_req: HttpRequest,
+    pool: web::Data<Pool>,
+    cookie_config: web::Data<AuthCookieConfig>,
+    auth_data: AuthExtractor,
+) -> Result<HttpResponse, GlobalServiceError> {
+    if let Some(session_id) = auth_data.as_ref().and_then(|x| x.session_id) {
+        let client = ClientInfo::from_request(&http_req);
+        telemetry::block(move || {
+            let conn: &PgConnection = &pool.get().unwrap();
+            let user_id = diesel::delete(sessions::table.find(session_id))
+                .returning(sessions::user_id)
+                .get_result::<i32>(conn)
+                .optional()?;
+
+            audit::record(
+                conn,
+                audit::SESSION_REVOKED,
+                user_id,
+                user_id,
+                &client,
+                json!({ "session_id": session_id, "reason": "logout" }),
+            )
+        })
+        .await
+        .map_err(|_err| GlobalServiceError::InternalServerError)?;
+    }
+
+    let mut response = HttpResponse::Ok();
+    for cookie in expired_session_cookies(&cookie_config) {
+        response.cookie(cookie);
+    }
+
+    Ok(response.json(ResponseBody::<()>::new(MESSAGE_LOGOUT_SUCCESS, None, None)))
+}
@@ -0,0 +1,223 @@
+use crate::api::auth::login::{login_http_response, LoginResponse};
+use crate::audit;
+use crate::config::{AuthCookieConfig, MagicLinkConfig, MailConfig};
+use crate::constants::{MESSAGE_LOGIN_SUCCESS, MESSAGE_MAGIC_LINK_SENT};
+use crate::mailer::send_email_in_background;
+use crate::metrics;
+use crate::model::errors::ServiceError;
+use crate::model::magic_link::NewMagicLink;
+use crate::model::response::ResponseBody;
+use crate::model::user::User;
+use crate::schema::{magic_links, users};
+use crate::session::{create_session_token, ClientInfo};
+use crate::telemetry;
+use crate::utils::{constant_time_eq, generate_random_token, normalize_email, sha256_hex};
+use crate::{model::db::lower, model::db::Pool, model::errors::GlobalServiceError};
+use actix_web::error::BlockingError;
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use chrono::{Duration, Utc};
+use diesel::{ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::instrument;
+use url::Url;
+
+#[derive(Deserialize)]
+pub struct MagicLinkRequest {
+    email: String,
+}
+
+// The nonce has to be kept by the client and sent along with the token from
+// the email, so the link only works on the device that asked for it
+#[derive(Serialize)]
+pub struct MagicLinkResponse {
+    nonce: String,
+}
+
+#[derive(Deserialize)]
+pub struct VerifyMagicLinkRequest {
+    token: String,
+    nonce: String,
+    #[serde(default)]
+    cookie: bool,
+}
+
+#[instrument(skip_all)]
+pub async fn request_magic_link_handler(
+    req: web::Json<MagicLinkRequest>,
+    pool: web::Data<Pool>,
+    config: web::Data<MagicLinkConfig>,
+    mail_config: web::Data<MailConfig>,
+) -> Result<HttpResponse, GlobalServiceError> {
+    let res =
+        telemetry::block(move || request_query(req.into_inner(), pool, &config, &mail_config))
+            .await;
+
+    match res {
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
+        Err(err) => match err {
+            BlockingError::Error(service_error) => Err(service_error),
+            BlockingError::Canceled => Err(GlobalServiceError::InternalServerError),
+        },
+    }
+}
+
+#[instrument(skip_all)]
+pub async fn verify_magic_link_handler(
+    http_req: HttpRequest,
+    req: web::Json<VerifyMagicLinkRequest>,
+    pool: web::Data<Pool>,
+    cookie_config: web::Data<AuthCookieConfig>,
+) -> Result<HttpResponse, GlobalServiceError> {
+    let use_cookie = req.cookie;
+    let client = ClientInfo::from_request(&http_req);
+    let res = telemetry::block(move || verify_query(req.into_inner(), client, pool)).await;
+
+    match res {
+        Ok(login_response) => Ok(login_http_response(
+            login_response,
+            use_cookie,
+            &cookie_config,
+        )),
+        Err(err) => match err {
+            BlockingError::Error(service_error) => Err(service_error),
+            BlockingError::Canceled => Err(GlobalServiceError::InternalServerError),
+        },
+    }
+}
+
+fn request_query(
+    req: MagicLinkRequest,
+    pool: web::Data<Pool>,
+    config: &MagicLinkConfig,
+    mail_config: &MailConfig,
+) -> Result<ResponseBody<MagicLinkResponse>, GlobalServiceError> {
+    let conn: &PgConnection = &pool.get().unwrap();
+
+    // Unknown emails get the same answer, so this can't be used to find out
+    // who has an account. That includes how long it takes, so the email is
+    // sent in the background and a broken link url fails for everyone.
+    let mut link =
+        Url::parse(&config.url).map_err(|_err| GlobalServiceError::InternalServerError)?;
+    let nonce = generate_random_token(32);
+    let response = ResponseBody::new(
+        MESSAGE_MAGIC_LINK_SENT,
+        Some(MagicLinkResponse {
+            nonce: nonce.clone(),
+        }),
+        None,
+    );
+
+    let user = users::table
+        .filter(lower(users::email).eq(normalize_email(&req.email)))
+        .first::<User>(conn)
+        .optional()?;
+    let user = match user {
+        Some(user) => user,
+        None => return Ok(response),
+    };
+
+    let token = generate_random_token(32);
+    diesel::insert_into(magic_links::table)
+        .values(&NewMagicLink {
+            user_id: user.id,
+            token_hash: &sha256_hex(&token),
+            nonce_hash: &sha256_hex(&nonce),
+            expires_at: Utc::now() + Duration::minutes(config.lifetime_minutes),
+        })
+        .execute(conn)?;
+
+    link.query_pairs_mut().append_pair("token", &token);
+
+    send_email_in_background(
+        mail_config,
+        user.email,
+        "Your sign-in link",
+        format!(
+            "Use this link to sign in, it works once within the next {} minutes and only \
+             in the browser where you asked for it:\n\n{}\n\n\
+             If you didn't ask for it you can ignore this email.",
+            config.lifetime_minutes, link
+        ),
+    );
+
+    Ok(response)
+}
+
+fn verify_query(
+    req: VerifyMagicLinkRequest,
+    client: ClientInfo,
+    pool: web::Data<Pool>,
+) -> Result<ResponseBody<LoginResponse>, GlobalServiceError> {
+    let conn: &PgConnection = &pool.get().unwrap();
+
+    let user_id = match claim_link(conn, &req) {
+        Ok(user_id) => user_id,
+        Err(err) => {
+            metrics::record_login("magic_link", "failure");
+            audit::record(
+                conn,
+                audit::LOGIN_FAILED,
+                None,
+                None,
+                &client,
+                json!({ "method": "magic_link" }),
+            )?;
+            return Err(err);
+        }
+    };
+
+    let user = users::table.find(user_id).first::<User>(conn)?;
+    let jwt_token = create_session_token(conn, &user, &client, "magic_link")?;
+    metrics::record_login("magic_link", "success");
+
+    Ok(ResponseBody::new(
+        MESSAGE_LOGIN_SUCCESS,
+        Some(LoginResponse {
+            token: Some(jwt_token),
+            email: user.email,
+            full_name: user.full_name.unwrap_or_default(),
+            second_factor: None,
+        }),
+        None,
+    ))
+}
+
+// Marks the link used and returns whose it is
+fn claim_link(
+    conn: &PgConnection,
+    req: &VerifyMagicLinkRequest,
+) -> Result<i32, GlobalServiceError> {
+    let invalid_link = || GlobalServiceError::Unauthorized(ServiceError::InvalidMagicLink);
+
+    let (link_id, user_id, nonce_hash) = magic_links::table
+        .filter(magic_links::token_hash.eq(sha256_hex(&req.token)))
+        .filter(magic_links::used_at.is_null())
+        .filter(magic_links::expires_at.gt(Utc::now()))
+        .select((
+            magic_links::id,
+            magic_links::user_id,
+            magic_links::nonce_hash,
+        ))
+        .first::<(i32, i32, String)>(conn)
+        .optional()?
+        .ok_or_else(invalid_link)?;
+
+    if !constant_time_eq(nonce_hash.as_bytes(), sha256_hex(&req.nonce).as_bytes()) {
+        return Err(invalid_link());
+    }
+
+    // Only one of two concurrent exchanges gets to mark it used
+    let claimed = diesel::update(
+        magic_links::table
+            .filter(magic_links::id.eq(link_id))
+            .filter(magic_links::used_at.is_null()),
+    )
+    .set(magic_links::used_at.eq(Utc::now()))
+    .execute(conn)?;
+    if claimed == 0 {
+        return Err(invalid_link());
+    }
+
+    Ok(user_id)
+}
@@ -1,2 +1,9 @@
+pub mod cookie;
+pub mod lockout;
 pub mod login;
+pub mod logout;
+pub mod magic_link;
+pub mod oauth;
+pub mod password_reset;
 pub mod register;
+pub mod webauthn;
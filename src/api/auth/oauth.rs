@@ -0,0 +1,381 @@
+use crate::api::auth::login::{login_http_response, LoginResponse};
+use crate::audit;
+use crate::config::{
+    Argon2Config, AuthCookieConfig, OAuthConfig, OAuthProviderConfig, RegistrationConfig,
+    RegistrationMode,
+};
+use crate::constants::{MESSAGE_LOGIN_SUCCESS, MESSAGE_OAUTH_AUTHORIZE, ROLE_USER};
+use crate::metrics;
+use crate::model::errors::ServiceError;
+use crate::model::identity::{NewOAuthState, NewUserIdentity, OAuthState, UserIdentity};
+use crate::model::response::ResponseBody;
+use crate::model::user::{NewUser, User};
+use crate::oauth::{self, ExternalProfile};
+use crate::schema::{oauth_states, user_identities, users};
+use crate::session::{create_session_token, ClientInfo};
+use crate::telemetry;
+use crate::utils::{
+    constant_time_eq, generate_random_token, hash_password, normalize_email, sha256_hex,
+};
+use crate::{model::db::lower, model::db::Pool, model::errors::GlobalServiceError};
+use actix_web::error::BlockingError;
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use chrono::{Duration, Utc};
+use diesel::{
+    Connection, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, RunQueryDsl,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::instrument;
+
+const STATE_LIFETIME_MINUTES: i64 = 10;
+
+// The nonce has to be kept by the client and sent along with the callback,
+// so a flow can only be finished in the browser that started it
+#[derive(Serialize)]
+pub struct AuthorizeResponse {
+    authorize_url: String,
+    nonce: String,
+}
+
+#[derive(Deserialize)]
+pub struct CallbackRequest {
+    code: String,
+    state: String,
+    nonce: String,
+    #[serde(default)]
+    cookie: bool,
+}
+
+fn blocking_error(err: BlockingError<GlobalServiceError>) -> GlobalServiceError {
+    match err {
+        BlockingError::Error(service_error) => service_error,
+        BlockingError::Canceled => GlobalServiceError::InternalServerError,
+    }
+}
+
+pub fn provider_config<'a>(
+    oauth_config: &'a OAuthConfig,
+    provider: &str,
+) -> Result<&'a OAuthProviderConfig, GlobalServiceError> {
+    oauth_config
+        .providers
+        .get(provider)
+        .ok_or_else(|| GlobalServiceError::BadRequest("Unknown provider".to_string()))
+}
+
+// Stores the state and PKCE verifier and returns where to send the browser.
+// `link_email` is the logged in user that the identity will be linked to.
+#[instrument(skip_all)]
+pub async fn start_authorization(
+    pool: web::Data<Pool>,
+    oauth_config: &OAuthConfig,
+    provider: String,
+    link_email: Option<String>,
+) -> Result<HttpResponse, GlobalServiceError> {
+    let config = provider_config(oauth_config, &provider)?.clone();
+
+    let state = generate_random_token(32);
+    let code_verifier = generate_random_token(32);
+    let nonce = generate_random_token(32);
+    let nonce_hash = sha256_hex(&nonce);
+    let authorize_url = oauth::authorize_url(&config, &state, &code_verifier)?;
+
+    telemetry::block(move || -> Result<(), GlobalServiceError> {
+        let conn: &PgConnection = &pool.get().unwrap();
+
+        let link_user_id = match link_email {
+            Some(link_email) => Some(
+                users::table
+                    .filter(lower(users::email).eq(&link_email))
+                    .select(users::id)
+                    .first::<i32>(conn)
+                    .map_err(|_err| GlobalServiceError::NotFound(ServiceError::UserNotFound))?,
+            ),
+            None => None,
+        };
+
+        // forget abandoned attempts while we're here
+        diesel::delete(oauth_states::table.filter(
+            oauth_states::created_at.lt(Utc::now() - Duration::minutes(STATE_LIFETIME_MINUTES)),
+        ))
+        .execute(conn)
+        .map_err(|_err| GlobalServiceError::InternalServerError)?;
+
+        diesel::insert_into(oauth_states::table)
+            .values(&NewOAuthState {
+                state: &state,
+                provider: &provider,
+                code_verifier: &code_verifier,
+                link_user_id,
+                nonce_hash: &nonce_hash,
+            })
+            .execute(conn)
+            .map(|_| ())
+            .map_err(|_err| GlobalServiceError::InternalServerError)
+    })
+    .await
+    .map_err(blocking_error)?;
+
+    Ok(HttpResponse::Ok().json(ResponseBody::new(
+        MESSAGE_OAUTH_AUTHORIZE,
+        Some(AuthorizeResponse {
+            authorize_url,
+            nonce,
+        }),
+        None,
+    )))
+}
+
+#[instrument(skip_all)]
+pub async fn authorize_handler(
+    provider: web::Path<String>,
+    pool: web::Data<Pool>,
+    oauth_config: web::Data<OAuthConfig>,
+) -> Result<HttpResponse, GlobalServiceError> {
+    start_authorization(pool, &oauth_config, provider.into_inner(), None).await
+}
+
+#[instrument(skip_all)]
+#[allow(clippy::too_many_arguments)]
+pub async fn callback_handler(
+    http_req: HttpRequest,
+    provider: web::Path<String>,
+    req: web::Json<CallbackRequest>,
+    pool: web::Data<Pool>,
+    oauth_config: web::Data<OAuthConfig>,
+    cookie_config: web::Data<AuthCookieConfig>,
+    registration_config: web::Data<RegistrationConfig>,
+    argon2_config: web::Data<Argon2Config>,
+) -> Result<HttpResponse, GlobalServiceError> {
+    let provider = provider.into_inner();
+    let config = provider_config(&oauth_config, &provider)?;
+    let req = req.into_inner();
+    let login_failed = |err: GlobalServiceError| {
+        metrics::record_login("oauth", "failure");
+        err
+    };
+
+    let state = {
+        let pool = pool.clone();
+        let provider = provider.clone();
+        let state = req.state.clone();
+        let nonce = req.nonce.clone();
+        telemetry::block(move || consume_state(&pool.get().unwrap(), &state, &nonce, &provider))
+            .await
+            .map_err(blocking_error)
+            .map_err(login_failed)?
+    };
+
+    let profile = oauth::fetch_profile(config, &req.code, &state.code_verifier)
+        .await
+        .map_err(login_failed)?;
+
+    let client = ClientInfo::from_request(&http_req);
+    let (user, jwt_token) = telemetry::block(move || {
+        let conn: &PgConnection = &pool.get().unwrap();
+        let user = find_or_create_user(
+            conn,
+            &provider,
+            profile,
+            state.link_user_id,
+            &client,
+            &registration_config,
+            &argon2_config,
+        )?;
+        let jwt_token = create_session_token(conn, &user, &client, &format!("oauth:{}", provider))?;
+        Ok((user, jwt_token))
+    })
+    .await
+    .map_err(blocking_error)
+    .map_err(login_failed)?;
+    metrics::record_login("oauth", "success");
+
+    Ok(login_http_response(
+        ResponseBody::new(
+            MESSAGE_LOGIN_SUCCESS,
+            Some(LoginResponse {
+                token: Some(jwt_token),
+                email: user.email,
+                full_name: user.full_name.unwrap_or_default(),
+                second_factor: None,
+            }),
+            None,
+        ),
+        req.cookie,
+        &cookie_config,
+    ))
+}
+
+// A state can only be used once, for the provider it was issued for and
+// together with the nonce handed to the browser that asked for it
+fn consume_state(
+    conn: &PgConnection,
+    state: &str,
+    nonce: &str,
+    provider: &str,
+) -> Result<OAuthState, GlobalServiceError> {
+    let invalid_state = || GlobalServiceError::BadRequest("Invalid OAuth state".to_string());
+
+    // someone else's state is left alone, its owner can still finish the flow
+    let nonce_hash = oauth_states::table
+        .find(state)
+        .select(oauth_states::nonce_hash)
+        .first::<String>(conn)
+        .optional()
+        .map_err(|_err| GlobalServiceError::InternalServerError)?
+        .ok_or_else(invalid_state)?;
+    if !constant_time_eq(nonce_hash.as_bytes(), sha256_hex(nonce).as_bytes()) {
+        return Err(invalid_state());
+    }
+
+    let oauth_state = diesel::delete(oauth_states::table.find(state))
+        .returning((
+            oauth_states::provider,
+            oauth_states::code_verifier,
+            oauth_states::link_user_id,
+            oauth_states::created_at,
+        ))
+        .get_result::<OAuthState>(conn)
+        .optional()
+        .map_err(|_err| GlobalServiceError::InternalServerError)?
+        .ok_or_else(invalid_state)?;
+
+    if oauth_state.provider != provider
+        || Utc::now() - oauth_state.created_at > Duration::minutes(STATE_LIFETIME_MINUTES)
+    {
+        return Err(invalid_state());
+    }
+
+    Ok(oauth_state)
+}
+
+fn find_or_create_user(
+    conn: &PgConnection,
+    provider: &str,
+    profile: ExternalProfile,
+    link_user_id: Option<i32>,
+    client: &ClientInfo,
+    registration_config: &RegistrationConfig,
+    argon2_config: &Argon2Config,
+) -> Result<User, GlobalServiceError> {
+    conn.transaction(|| {
+        let identity = user_identities::table
+            .filter(user_identities::provider.eq(provider))
+            .filter(user_identities::subject.eq(&profile.subject))
+            .first::<UserIdentity>(conn)
+            .optional()
+            .map_err(|_err| GlobalServiceError::InternalServerError)?;
+
+        let user_id = match (identity, link_user_id) {
+            (Some(identity), Some(link_user_id)) if identity.user_id != link_user_id => {
+                return Err(GlobalServiceError::Conflict(
+                    ServiceError::IdentityAlreadyLinked,
+                ))
+            }
+            (Some(identity), _) => identity.user_id,
+            (None, Some(link_user_id)) => {
+                insert_identity(conn, link_user_id, provider, &profile, client)?;
+                link_user_id
+            }
+            (None, None) => {
+                let user_id = create_user(conn, &profile, registration_config, argon2_config)?;
+                audit::record(
+                    conn,
+                    audit::USER_REGISTERED,
+                    Some(user_id),
+                    Some(user_id),
+                    client,
+                    json!({ "method": format!("oauth:{}", provider) }),
+                )?;
+                insert_identity(conn, user_id, provider, &profile, client)?;
+                user_id
+            }
+        };
+
+        users::table
+            .find(user_id)
+            .first::<User>(conn)
+            .map_err(|_err| GlobalServiceError::InternalServerError)
+    })
+}
+
+fn insert_identity(
+    conn: &PgConnection,
+    user_id: i32,
+    provider: &str,
+    profile: &ExternalProfile,
+    client: &ClientInfo,
+) -> Result<(), GlobalServiceError> {
+    diesel::insert_into(user_identities::table)
+        .values(&NewUserIdentity {
+            user_id,
+            provider,
+            subject: &profile.subject,
+            email: profile.email.as_deref(),
+        })
+        .execute(conn)
+        .map_err(|_err| GlobalServiceError::InternalServerError)?;
+
+    audit::record(
+        conn,
+        audit::IDENTITY_LINKED,
+        Some(user_id),
+        Some(user_id),
+        client,
+        json!({ "provider": provider, "subject": profile.subject }),
+    )
+}
+
+fn create_user(
+    conn: &PgConnection,
+    profile: &ExternalProfile,
+    registration_config: &RegistrationConfig,
+    argon2_config: &Argon2Config,
+) -> Result<i32, GlobalServiceError> {
+    // There is no place for an invite code in the provider round trip, so
+    // unless sign-ups are open only existing accounts can use a provider
+    if registration_config.mode != RegistrationMode::Open {
+        return Err(GlobalServiceError::Forbidden(
+            ServiceError::RegistrationClosed,
+        ));
+    }
+
+    let user_email = match profile.email {
+        Some(ref user_email) => normalize_email(user_email),
+        None => {
+            return Err(GlobalServiceError::BadRequest(
+                "The provider did not share a verified email".to_string(),
+            ))
+        }
+    };
+
+    // Taking over an existing account by email would let anyone controlling
+    // the provider account in, its owner has to log in and link instead
+    let existing_user = users::table
+        .filter(lower(users::email).eq(&user_email))
+        .select(users::id)
+        .first::<i32>(conn)
+        .optional()
+        .map_err(|_err| GlobalServiceError::InternalServerError)?;
+    if existing_user.is_some() {
+        return Err(GlobalServiceError::Conflict(
+            ServiceError::EmailAlreadyExists,
+        ));
+    }
+
+    // nobody knows this password, the account signs in through the provider
+    let unusable_password = hash_password(&generate_random_token(32), argon2_config)?;
+
+    diesel::insert_into(users::table)
+        .values(&NewUser {
+            email: &user_email,
+            hashed_password: &unusable_password,
+            full_name: profile.name.as_deref().unwrap_or_default(),
+            username: None,
+            role: ROLE_USER,
+        })
+        .returning(users::id)
+        .get_result::<i32>(conn)
+        .map_err(|_err| GlobalServiceError::InternalServerError)
+}
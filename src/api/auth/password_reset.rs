@@ -0,0 +1,97 @@
+use crate::api::auth::lockout;
+use crate::audit;
+use crate::config::Argon2Config;
+use crate::constants::MESSAGE_PASSWORD_CHANGED;
+use crate::model::errors::ServiceError;
+use crate::model::response::ResponseBody;
+use crate::schema::{password_resets, sessions, users};
+use crate::session::ClientInfo;
+use crate::telemetry;
+use crate::utils::{hash_password, sha256_hex};
+use crate::{model::db::Pool, model::errors::GlobalServiceError};
+use actix_web::error::BlockingError;
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use chrono::Utc;
+use diesel::{ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, RunQueryDsl};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::instrument;
+
+#[derive(Deserialize)]
+pub struct ResetPasswordRequest {
+    token: String,
+    new_password: String,
+}
+
+#[instrument(skip_all)]
+pub async fn reset_password_handler(
+    http_req: HttpRequest,
+    req: web::Json<ResetPasswordRequest>,
+    pool: web::Data<Pool>,
+    argon2_config: web::Data<Argon2Config>,
+) -> Result<HttpResponse, GlobalServiceError> {
+    let client = ClientInfo::from_request(&http_req);
+    let res = telemetry::block(move || query(req.into_inner(), client, pool, &argon2_config)).await;
+
+    match res {
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
+        Err(err) => match err {
+            BlockingError::Error(service_error) => Err(service_error),
+            BlockingError::Canceled => Err(GlobalServiceError::InternalServerError),
+        },
+    }
+}
+
+fn query(
+    req: ResetPasswordRequest,
+    client: ClientInfo,
+    pool: web::Data<Pool>,
+    argon2_config: &Argon2Config,
+) -> Result<ResponseBody<()>, GlobalServiceError> {
+    let conn: &PgConnection = &pool.get().unwrap();
+
+    if req.new_password.is_empty() {
+        return Err(GlobalServiceError::BadRequest(
+            "New password is required".to_string(),
+        ));
+    }
+
+    let user_id = claim_reset(conn, &req.token)?;
+    let hashed_password = hash_password(&req.new_password, argon2_config)?;
+    diesel::update(users::table.find(user_id))
+        .set(users::hashed_password.eq(hashed_password))
+        .execute(conn)?;
+    lockout::reset_account(conn, user_id)?;
+    diesel::delete(sessions::table.filter(sessions::user_id.eq(user_id))).execute(conn)?;
+
+    audit::record(
+        conn,
+        audit::PASSWORD_CHANGED,
+        Some(user_id),
+        Some(user_id),
+        &client,
+        json!({ "method": "reset_link" }),
+    )?;
+
+    Ok(ResponseBody::new(MESSAGE_PASSWORD_CHANGED, None, None))
+}
+
+// Marks the reset used and returns whose it is
+fn claim_reset(conn: &PgConnection, token: &str) -> Result<i32, GlobalServiceError> {
+    let now = Utc::now();
+
+    // Only one of two concurrent requests gets to use it
+    diesel::update(
+        password_resets::table
+            .filter(password_resets::token_hash.eq(sha256_hex(token)))
+            .filter(password_resets::used_at.is_null())
+            .filter(password_resets::expires_at.gt(now)),
+    )
+    .set(password_resets::used_at.eq(now))
+    .returning(password_resets::user_id)
+    .get_result::<i32>(conn)
+    .optional()?
+    .ok_or(GlobalServiceError::Unauthorized(
+        ServiceError::InvalidPasswordReset,
+    ))
+}
@@ -1,30 +1,63 @@
+use crate::api::profile::update_profile::parse_username;
+use crate::audit;
+use crate::config::{Argon2Config, RegistrationConfig, RegistrationMode};
+use crate::constants::ROLE_USER;
+use crate::model::errors::ServiceError;
 use crate::model::user::{NewUser, User};
+use crate::schema::invites;
 use crate::schema::users::dsl::{email, users};
-use crate::utils::{hash_password, validate_email};
-use crate::{model::db::Pool, model::errors::GlobalServiceError};
+use crate::session::ClientInfo;
+use crate::telemetry;
+use crate::utils::{hash_password, normalize_email, sha256_hex, validate_email};
+use crate::{model::db::lower, model::db::Pool, model::errors::GlobalServiceError};
 use actix_web::error::BlockingError;
-use actix_web::{web, HttpResponse, Result};
-use diesel::{PgConnection, QueryDsl, RunQueryDsl};
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use chrono::Utc;
+use diesel::result::{DatabaseErrorKind, Error::DatabaseError};
+use diesel::{
+    BoolExpressionMethods, Connection, OptionalExtension, PgConnection, QueryDsl, RunQueryDsl,
+};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::instrument;
 
 #[derive(Deserialize)]
 pub struct RegisterRequest {
     email: String,
     password: String,
     full_name: String,
+    // can also be picked later from the profile
+    username: Option<String>,
+    // required in invite-only mode, in open mode it can still grant a role
+    invite_code: Option<String>,
 }
 
 #[derive(Serialize)]
 struct RegisterResponse {
     email: String,
     full_name: String,
+    username: Option<String>,
 }
 
+#[instrument(skip_all)]
 pub async fn register_handler(
+    http_req: HttpRequest,
     req: web::Json<RegisterRequest>,
     pool: web::Data<Pool>,
+    registration_config: web::Data<RegistrationConfig>,
+    argon2_config: web::Data<Argon2Config>,
 ) -> Result<HttpResponse, GlobalServiceError> {
-    let res = web::block(move || query(req.into_inner(), pool)).await;
+    let client = ClientInfo::from_request(&http_req);
+    let res = telemetry::block(move || {
+        query(
+            req.into_inner(),
+            client,
+            pool,
+            &registration_config,
+            &argon2_config,
+        )
+    })
+    .await;
 
     match res {
         Ok(user) => Ok(HttpResponse::Ok().json(user)),
@@ -37,43 +70,138 @@ pub async fn register_handler(
 
 fn query(
     data: RegisterRequest,
+    client: ClientInfo,
     pool: web::Data<Pool>,
+    registration_config: &RegistrationConfig,
+    argon2_config: &Argon2Config,
 ) -> Result<RegisterResponse, GlobalServiceError> {
     let conn: &PgConnection = &pool.get().unwrap();
     use crate::diesel::ExpressionMethods;
 
-    if !validate_email(&data.email) {
+    let data_email = normalize_email(&data.email);
+
+    if !validate_email(&data_email) {
         return Err(GlobalServiceError::BadRequest(
             "Wrong E-mail Format".to_string(),
         ));
     }
+    let username = match data.username {
+        Some(ref username) => Some(parse_username(username)?),
+        None => None,
+    };
+    let invite_code = data
+        .invite_code
+        .as_deref()
+        .map(str::trim)
+        .filter(|invite_code| !invite_code.is_empty())
+        .map(|invite_code| invite_code.to_string());
+    match (registration_config.mode, &invite_code) {
+        (RegistrationMode::Closed, _) => {
+            return Err(GlobalServiceError::Forbidden(
+                ServiceError::RegistrationClosed,
+            ))
+        }
+        (RegistrationMode::InviteOnly, None) => {
+            return Err(GlobalServiceError::Forbidden(
+                ServiceError::InvalidInviteCode,
+            ))
+        }
+        _ => {}
+    }
 
     users
-        .filter(email.eq(&data.email))
-        .load::<User>(conn)
+        .filter(lower(email).eq(&data_email))
+        .first::<User>(conn)
+        .optional()
         .map_err(|_db_error| GlobalServiceError::BadRequest("Invalid Data".into()))
-        .and_then(|mut _result| {
-            let password_and_salt = hash_password(&data.password)?;
-
-            let hashed_password = password_and_salt.hashed_password;
-            let salt = password_and_salt.salt;
-            let new_user = NewUser {
-                email: &data.email,
-                hashed_password: &hashed_password,
-                salt: &salt,
-                full_name: &data.full_name,
-            };
-
-            let inserted_user: Result<usize, diesel::result::Error> = diesel::insert_into(users)
-                .values(&vec![new_user])
-                .execute(conn);
-
-            match inserted_user {
-                Err(_) => Err(GlobalServiceError::InternalServerError),
-                Ok(_) => Ok(RegisterResponse {
-                    email: data.email,
-                    full_name: data.full_name,
-                }),
+        .and_then(|existing_user| {
+            if existing_user.is_some() {
+                return Err(GlobalServiceError::Conflict(
+                    ServiceError::EmailAlreadyExists,
+                ));
             }
+
+            let hashed_password = hash_password(&data.password, argon2_config)?;
+
+            // The invite is only used up when the account is really created
+            conn.transaction(|| {
+                let invite = match invite_code {
+                    Some(ref invite_code) => Some(redeem_invite(conn, invite_code)?),
+                    None => None,
+                };
+
+                let new_user = NewUser {
+                    email: &data_email,
+                    hashed_password: &hashed_password,
+                    full_name: &data.full_name,
+                    username: username.as_deref(),
+                    role: invite.as_ref().map_or(ROLE_USER, |(_, role)| role.as_str()),
+                };
+
+                let inserted_user: Result<User, diesel::result::Error> = diesel::insert_into(users)
+                    .values(&new_user)
+                    .get_result(conn);
+
+                match inserted_user {
+                    Err(DatabaseError(DatabaseErrorKind::UniqueViolation, ref info))
+                        if info.constraint_name() == Some("users_username_key") =>
+                    {
+                        Err(GlobalServiceError::Conflict(
+                            ServiceError::UsernameAlreadyExists,
+                        ))
+                    }
+                    // Lost a race against a concurrent registration with the same email
+                    Err(DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => Err(
+                        GlobalServiceError::Conflict(ServiceError::EmailAlreadyExists),
+                    ),
+                    Err(_) => Err(GlobalServiceError::InternalServerError),
+                    Ok(user) => {
+                        audit::record(
+                            conn,
+                            audit::USER_REGISTERED,
+                            Some(user.id),
+                            Some(user.id),
+                            &client,
+                            json!({
+                                "method": "password",
+                                "invite_id": invite.as_ref().map(|(invite_id, _)| invite_id),
+                            }),
+                        )?;
+
+                        Ok(RegisterResponse {
+                            email: data_email,
+                            full_name: data.full_name,
+                            username,
+                        })
+                    }
+                }
+            })
         })
 }
+
+// Counts one use of the invite and returns its id and the role it grants
+fn redeem_invite(
+    conn: &PgConnection,
+    invite_code: &str,
+) -> Result<(i32, String), GlobalServiceError> {
+    use crate::diesel::ExpressionMethods;
+
+    // A single update, so concurrent sign-ups can't go past max_uses
+    diesel::update(
+        invites::table
+            .filter(invites::code_hash.eq(sha256_hex(invite_code)))
+            .filter(invites::uses.lt(invites::max_uses))
+            .filter(
+                invites::expires_at
+                    .is_null()
+                    .or(invites::expires_at.gt(Utc::now())),
+            ),
+    )
+    .set(invites::uses.eq(invites::uses + 1))
+    .returning((invites::id, invites::role))
+    .get_result::<(i32, String)>(conn)
+    .optional()?
+    .ok_or(GlobalServiceError::Forbidden(
+        ServiceError::InvalidInviteCode,
+    ))
+}
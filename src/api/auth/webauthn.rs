@@ -0,0 +1,348 @@
+use crate::api::auth::login::{login_http_response, LoginResponse};
+use crate::audit;
+use crate::config::{AuthCookieConfig, WebauthnConfig};
+use crate::constants::{MESSAGE_LOGIN_SUCCESS, MESSAGE_PASSKEY_CHALLENGE};
+use crate::metrics;
+use crate::model::errors::ServiceError;
+use crate::model::response::ResponseBody;
+use crate::model::user::User;
+use crate::model::webauthn::{
+    NewWebauthnChallenge, WebauthnChallenge, WebauthnCredential, PURPOSE_AUTHENTICATION,
+    PURPOSE_SECOND_FACTOR,
+};
+use crate::schema::{users, webauthn_challenges, webauthn_credentials};
+use crate::session::{create_session_token, ClientInfo};
+use crate::telemetry;
+use crate::webauthn::{
+    base64url_decode, check_sign_count, generate_challenge, parse_authenticator_data,
+    verify_client_data, verify_signature,
+};
+use crate::{model::db::Pool, model::errors::GlobalServiceError};
+use actix_web::error::BlockingError;
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use chrono::{Duration, Utc};
+use diesel::{ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::instrument;
+
+#[derive(Serialize)]
+pub struct CredentialDescriptor {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub id: String,
+}
+
+impl CredentialDescriptor {
+    pub fn new(credential_id: String) -> CredentialDescriptor {
+        CredentialDescriptor {
+            kind: "public-key",
+            id: credential_id,
+        }
+    }
+}
+
+// PublicKeyCredentialRequestOptions for navigator.credentials.get()
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestOptions {
+    challenge: String,
+    timeout: i64,
+    rp_id: String,
+    allow_credentials: Vec<CredentialDescriptor>,
+    user_verification: &'static str,
+}
+
+// The client sends challenge_id back with the browser's answer
+#[derive(Serialize)]
+pub struct PasskeyChallenge<T> {
+    pub challenge_id: i32,
+    pub public_key: T,
+}
+
+#[derive(Deserialize)]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    client_data_json: String,
+    #[serde(rename = "authenticatorData")]
+    authenticator_data: String,
+    signature: String,
+}
+
+#[derive(Deserialize)]
+pub struct AssertionCredential {
+    id: String,
+    response: AssertionResponse,
+}
+
+#[derive(Deserialize)]
+pub struct LoginFinishRequest {
+    challenge_id: i32,
+    credential: AssertionCredential,
+    #[serde(default)]
+    cookie: bool,
+}
+
+pub fn create_challenge(
+    conn: &PgConnection,
+    user_id: Option<i32>,
+    purpose: &str,
+    config: &WebauthnConfig,
+) -> Result<(i32, String), GlobalServiceError> {
+    // Ceremonies that were never finished
+    diesel::delete(
+        webauthn_challenges::table.filter(
+            webauthn_challenges::created_at
+                .lt(Utc::now() - Duration::seconds(config.challenge_lifetime_seconds)),
+        ),
+    )
+    .execute(conn)?;
+
+    let challenge = generate_challenge();
+    let challenge_id = diesel::insert_into(webauthn_challenges::table)
+        .values(&NewWebauthnChallenge {
+            user_id,
+            challenge: &challenge,
+            purpose,
+        })
+        .returning(webauthn_challenges::id)
+        .get_result::<i32>(conn)?;
+
+    Ok((challenge_id, challenge))
+}
+
+// Challenges are single use, a second finish with the same id fails
+pub fn consume_challenge(
+    conn: &PgConnection,
+    challenge_id: i32,
+    purposes: &[&str],
+    config: &WebauthnConfig,
+) -> Result<Option<WebauthnChallenge>, GlobalServiceError> {
+    let challenge = diesel::delete(
+        webauthn_challenges::table
+            .filter(webauthn_challenges::id.eq(challenge_id))
+            .filter(webauthn_challenges::purpose.eq_any(purposes)),
+    )
+    .returning((
+        webauthn_challenges::user_id,
+        webauthn_challenges::challenge,
+        webauthn_challenges::purpose,
+        webauthn_challenges::created_at,
+    ))
+    .get_result::<WebauthnChallenge>(conn)
+    .optional()?;
+
+    Ok(challenge.filter(|challenge| {
+        Utc::now() - challenge.created_at <= Duration::seconds(config.challenge_lifetime_seconds)
+    }))
+}
+
+// With a user the browser is told which of their passkeys to use, without one
+// it offers the discoverable credentials it has for this site
+pub fn request_options(
+    conn: &PgConnection,
+    user_id: Option<i32>,
+    purpose: &str,
+    config: &WebauthnConfig,
+) -> Result<PasskeyChallenge<RequestOptions>, GlobalServiceError> {
+    let allow_credentials = match user_id {
+        Some(user_id) => webauthn_credentials::table
+            .filter(webauthn_credentials::user_id.eq(user_id))
+            .select(webauthn_credentials::credential_id)
+            .load::<String>(conn)?
+            .into_iter()
+            .map(CredentialDescriptor::new)
+            .collect(),
+        None => Vec::new(),
+    };
+    let (challenge_id, challenge) = create_challenge(conn, user_id, purpose, config)?;
+
+    Ok(PasskeyChallenge {
+        challenge_id,
+        public_key: RequestOptions {
+            challenge,
+            timeout: config.challenge_lifetime_seconds * 1000,
+            rp_id: config.rp_id.clone(),
+            allow_credentials,
+            user_verification: if purpose == PURPOSE_AUTHENTICATION {
+                "required"
+            } else {
+                "preferred"
+            },
+        },
+    })
+}
+
+pub fn has_passkeys(conn: &PgConnection, user_id: i32) -> Result<bool, GlobalServiceError> {
+    let count = webauthn_credentials::table
+        .filter(webauthn_credentials::user_id.eq(user_id))
+        .count()
+        .get_result::<i64>(conn)?;
+
+    Ok(count > 0)
+}
+
+#[instrument(skip_all)]
+pub async fn login_start_handler(
+    pool: web::Data<Pool>,
+    webauthn_config: web::Data<WebauthnConfig>,
+) -> Result<HttpResponse, GlobalServiceError> {
+    let res = telemetry::block(move || {
+        let conn: &PgConnection = &pool.get().unwrap();
+        request_options(conn, None, PURPOSE_AUTHENTICATION, &webauthn_config)
+    })
+    .await;
+
+    match res {
+        Ok(options) => Ok(HttpResponse::Ok().json(ResponseBody::new(
+            MESSAGE_PASSKEY_CHALLENGE,
+            Some(options),
+            None,
+        ))),
+        Err(err) => match err {
+            BlockingError::Error(service_error) => Err(service_error),
+            BlockingError::Canceled => Err(GlobalServiceError::InternalServerError),
+        },
+    }
+}
+
+// Finishes both a passkey login and the second step of a password login
+#[instrument(skip_all)]
+pub async fn login_finish_handler(
+    http_req: HttpRequest,
+    req: web::Json<LoginFinishRequest>,
+    pool: web::Data<Pool>,
+    cookie_config: web::Data<AuthCookieConfig>,
+    webauthn_config: web::Data<WebauthnConfig>,
+) -> Result<HttpResponse, GlobalServiceError> {
+    let use_cookie = req.cookie;
+    let client = ClientInfo::from_request(&http_req);
+    let res =
+        telemetry::block(move || finish_query(req.into_inner(), client, pool, &webauthn_config))
+            .await;
+
+    match res {
+        Ok(login_response) => Ok(login_http_response(
+            login_response,
+            use_cookie,
+            &cookie_config,
+        )),
+        Err(err) => match err {
+            BlockingError::Error(service_error) => Err(service_error),
+            BlockingError::Canceled => Err(GlobalServiceError::InternalServerError),
+        },
+    }
+}
+
+fn finish_query(
+    req: LoginFinishRequest,
+    client: ClientInfo,
+    pool: web::Data<Pool>,
+    config: &WebauthnConfig,
+) -> Result<ResponseBody<LoginResponse>, GlobalServiceError> {
+    let conn: &PgConnection = &pool.get().unwrap();
+
+    let (credential, purpose) = match verify_assertion(conn, &req, config) {
+        Ok(verified) => verified,
+        Err(err) => {
+            metrics::record_login("passkey", "failure");
+            audit::record(
+                conn,
+                audit::LOGIN_FAILED,
+                None,
+                None,
+                &client,
+                json!({ "method": "passkey", "credential_id": req.credential.id }),
+            )?;
+            return Err(err);
+        }
+    };
+
+    let method = if purpose == PURPOSE_SECOND_FACTOR {
+        "password+passkey"
+    } else {
+        "passkey"
+    };
+    let user = users::table.find(credential.user_id).first::<User>(conn)?;
+    let jwt_token = create_session_token(conn, &user, &client, method)?;
+    metrics::record_login(method, "success");
+
+    Ok(ResponseBody::new(
+        MESSAGE_LOGIN_SUCCESS,
+        Some(LoginResponse {
+            token: Some(jwt_token),
+            email: user.email,
+            full_name: user.full_name.unwrap_or_default(),
+            second_factor: None,
+        }),
+        None,
+    ))
+}
+
+// Returns the credential that signed along with the purpose of the challenge
+fn verify_assertion(
+    conn: &PgConnection,
+    req: &LoginFinishRequest,
+    config: &WebauthnConfig,
+) -> Result<(WebauthnCredential, String), GlobalServiceError> {
+    let invalid_passkey = || GlobalServiceError::Unauthorized(ServiceError::InvalidPasskey);
+
+    let challenge = consume_challenge(
+        conn,
+        req.challenge_id,
+        &[PURPOSE_AUTHENTICATION, PURPOSE_SECOND_FACTOR],
+        config,
+    )?
+    .ok_or_else(invalid_passkey)?;
+
+    let credential = webauthn_credentials::table
+        .filter(webauthn_credentials::credential_id.eq(&req.credential.id))
+        .first::<WebauthnCredential>(conn)
+        .optional()?
+        .ok_or_else(invalid_passkey)?;
+    // A second factor challenge belongs to the user who entered the password
+    if let Some(user_id) = challenge.user_id {
+        if credential.user_id != user_id {
+            return Err(invalid_passkey());
+        }
+    }
+
+    let response = &req.credential.response;
+    let client_data_json =
+        base64url_decode(&response.client_data_json).map_err(|_err| invalid_passkey())?;
+    let authenticator_data =
+        base64url_decode(&response.authenticator_data).map_err(|_err| invalid_passkey())?;
+    let signature = base64url_decode(&response.signature).map_err(|_err| invalid_passkey())?;
+
+    let client_data_hash = verify_client_data(
+        &client_data_json,
+        "webauthn.get",
+        &challenge.challenge,
+        config,
+    )
+    .map_err(|_err| invalid_passkey())?;
+    let auth_data =
+        parse_authenticator_data(&authenticator_data, config).map_err(|_err| invalid_passkey())?;
+    // On its own the passkey has to stand in for the password as well
+    if challenge.purpose == PURPOSE_AUTHENTICATION && !auth_data.user_verified {
+        return Err(invalid_passkey());
+    }
+    verify_signature(
+        &credential.public_key,
+        &authenticator_data,
+        &client_data_hash,
+        &signature,
+    )
+    .map_err(|_err| invalid_passkey())?;
+
+    let sign_count = i64::from(auth_data.sign_count);
+    check_sign_count(credential.sign_count, sign_count).map_err(|_err| invalid_passkey())?;
+    diesel::update(&credential)
+        .set((
+            webauthn_credentials::sign_count.eq(sign_count),
+            webauthn_credentials::last_used_at.eq(Utc::now()),
+        ))
+        .execute(conn)?;
+
+    Ok((credential, challenge.purpose))
+}
@@ -1,2 +1,5 @@
+pub mod admin;
 pub mod auth;
+pub mod ops;
 pub mod profile;
+pub mod users;
@@ -0,0 +1,109 @@
+use crate::constants::{
+    MESSAGE_ALIVE, MESSAGE_NOT_READY, MESSAGE_READY, READINESS_DB_TIMEOUT_SECONDS,
+};
+use crate::migrations;
+use crate::model::{db::Pool, response::ResponseBody};
+use crate::telemetry;
+use actix_web::{web, HttpResponse};
+use diesel::{sql_query, RunQueryDsl};
+use serde::Serialize;
+use std::{collections::BTreeMap, time::Duration};
+
+#[derive(Serialize)]
+pub struct Check {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+}
+
+impl Check {
+    fn ok() -> Check {
+        Check {
+            ok: true,
+            detail: None,
+        }
+    }
+
+    fn failed(detail: impl Into<String>) -> Check {
+        Check {
+            ok: false,
+            detail: Some(detail.into()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Readiness {
+    ready: bool,
+    checks: BTreeMap<&'static str, Check>,
+}
+
+// Liveness, the process is up and serving requests
+pub async fn healthz_handler() -> HttpResponse {
+    HttpResponse::Ok().json(ResponseBody::new(MESSAGE_ALIVE, None::<()>, None))
+}
+
+// Readiness, the instance can serve the API. 503 when any check fails.
+pub async fn readyz_handler(pool: web::Data<Pool>) -> HttpResponse {
+    let res = telemetry::block(move || -> Result<_, ()> { Ok(database_checks(&pool)) }).await;
+
+    // the configuration is checked once in `main`, the server doesn't start
+    // with an invalid one
+    let checks = match res {
+        Ok(checks) => checks,
+        Err(_) => {
+            let mut checks = BTreeMap::new();
+            checks.insert("database", Check::failed("check did not complete"));
+            checks
+        }
+    };
+
+    let ready = checks.values().all(|check| check.ok);
+    let readiness = Readiness { ready, checks };
+    if ready {
+        HttpResponse::Ok().json(ResponseBody::new(MESSAGE_READY, Some(readiness), None))
+    } else {
+        HttpResponse::ServiceUnavailable().json(ResponseBody::new(
+            MESSAGE_NOT_READY,
+            Some(readiness),
+            None,
+        ))
+    }
+}
+
+// Probes are unauthenticated, details of database errors go to the log only
+fn database_checks(pool: &Pool) -> BTreeMap<&'static str, Check> {
+    let mut checks = BTreeMap::new();
+
+    let conn = match pool.get_timeout(Duration::from_secs(READINESS_DB_TIMEOUT_SECONDS)) {
+        Ok(conn) => conn,
+        Err(err) => {
+            tracing::warn!(error = %err, "readiness: no database connection");
+            checks.insert("database", Check::failed("no connection available"));
+            checks.insert("migrations", Check::failed("database unavailable"));
+            return checks;
+        }
+    };
+
+    match sql_query("SELECT 1").execute(&conn) {
+        Ok(_) => checks.insert("database", Check::ok()),
+        Err(err) => {
+            tracing::warn!(error = %err, "readiness: database query failed");
+            checks.insert("database", Check::failed("query failed"))
+        }
+    };
+
+    let migrations = match migrations::pending_count(&conn) {
+        Ok(0) => Check::ok(),
+        // which ones is for `migrate status`, not for anyone who can reach
+        // the probe
+        Ok(pending) => Check::failed(format!("{} pending", pending)),
+        Err(err) => {
+            tracing::warn!(error = %err, "readiness: reading applied migrations failed");
+            Check::failed("could not read applied migrations")
+        }
+    };
+    checks.insert("migrations", migrations);
+
+    checks
+}
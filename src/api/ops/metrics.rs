@@ -0,0 +1,10 @@
+use crate::metrics;
+use crate::model::db::Pool;
+use actix_web::{web, HttpResponse};
+
+// Scrapers don't need a token, so this is only served on METRICS_LISTEN
+pub async fn metrics_handler(pool: web::Data<Pool>) -> HttpResponse {
+    let (content_type, body) = metrics::render(&pool);
+
+    HttpResponse::Ok().content_type(content_type).body(body)
+}
@@ -0,0 +1,232 @@
+use crate::api::profile::session_email;
+use crate::api_key::generate_api_key;
+use crate::audit;
+use crate::constants::{
+    API_KEY_SCOPES, API_KEY_SCOPE_ADMIN, MESSAGE_CREATE_API_KEY_SUCCESS,
+    MESSAGE_GET_API_KEYS_SUCCESS, MESSAGE_REVOKE_API_KEY_SUCCESS, ROLE_ADMIN,
+};
+use crate::extractor::auth::AuthExtractor;
+use crate::model::api_key::{ApiKey, NewApiKey};
+use crate::model::errors::ServiceError;
+use crate::model::user::find_by_email;
+use crate::model::{db::Pool, errors::GlobalServiceError, response::ResponseBody};
+use crate::schema::api_keys;
+use crate::session::ClientInfo;
+use crate::telemetry;
+use crate::utils::sha256_hex;
+use actix_web::error::BlockingError;
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use chrono::{Duration, Utc};
+use diesel::{ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::instrument;
+
+#[derive(Deserialize)]
+pub struct CreateApiKeyRequest {
+    name: String,
+    scopes: Vec<String>,
+    // never expires when left out
+    expires_in_days: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct CreateApiKeyResponse {
+    #[serde(flatten)]
+    api_key: ApiKey,
+    // only ever returned here
+    key: String,
+}
+
+#[instrument(skip_all)]
+pub async fn create_api_key_handler(
+    http_req: HttpRequest,
+    req: web::Json<CreateApiKeyRequest>,
+    pool: web::Data<Pool>,
+    auth_data: AuthExtractor,
+) -> Result<HttpResponse, GlobalServiceError> {
+    let user_email = session_email(&auth_data)?;
+    let client = ClientInfo::from_request(&http_req);
+    let res =
+        telemetry::block(move || create_query(user_email, req.into_inner(), client, pool)).await;
+
+    match res {
+        Ok(response) => Ok(HttpResponse::Created().json(response)),
+        Err(err) => match err {
+            BlockingError::Error(service_error) => Err(service_error),
+            BlockingError::Canceled => Err(GlobalServiceError::InternalServerError),
+        },
+    }
+}
+
+#[instrument(skip_all)]
+pub async fn list_api_keys_handler(
+    pool: web::Data<Pool>,
+    auth_data: AuthExtractor,
+) -> Result<HttpResponse, GlobalServiceError> {
+    let user_email = session_email(&auth_data)?;
+    let res = telemetry::block(move || list_query(user_email, pool)).await;
+
+    match res {
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
+        Err(err) => match err {
+            BlockingError::Error(service_error) => Err(service_error),
+            BlockingError::Canceled => Err(GlobalServiceError::InternalServerError),
+        },
+    }
+}
+
+#[instrument(skip_all)]
+pub async fn revoke_api_key_handler(
+    http_req: HttpRequest,
+    api_key_id: web::Path<i32>,
+    pool: web::Data<Pool>,
+    auth_data: AuthExtractor,
+) -> Result<HttpResponse, GlobalServiceError> {
+    let user_email = session_email(&auth_data)?;
+    let client = ClientInfo::from_request(&http_req);
+    let res =
+        telemetry::block(move || revoke_query(user_email, api_key_id.into_inner(), client, pool))
+            .await;
+
+    match res {
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
+        Err(err) => match err {
+            BlockingError::Error(service_error) => Err(service_error),
+            BlockingError::Canceled => Err(GlobalServiceError::InternalServerError),
+        },
+    }
+}
+
+fn create_query(
+    user_email: String,
+    req: CreateApiKeyRequest,
+    client: ClientInfo,
+    pool: web::Data<Pool>,
+) -> Result<ResponseBody<CreateApiKeyResponse>, GlobalServiceError> {
+    let conn: &PgConnection = &pool.get().unwrap();
+    let user = find_by_email(conn, &user_email)?;
+
+    let name = req.name.trim();
+    if name.is_empty() {
+        return Err(GlobalServiceError::BadRequest(
+            "API key name is required".to_string(),
+        ));
+    }
+
+    let mut scopes = req.scopes;
+    scopes.sort();
+    scopes.dedup();
+    if scopes.is_empty() {
+        return Err(GlobalServiceError::BadRequest(
+            "At least one scope is required".to_string(),
+        ));
+    }
+    if let Some(unknown) = scopes
+        .iter()
+        .find(|scope| !API_KEY_SCOPES.contains(&scope.as_str()))
+    {
+        return Err(GlobalServiceError::BadRequest(format!(
+            "Unknown scope {}",
+            unknown
+        )));
+    }
+    if scopes.iter().any(|scope| scope == API_KEY_SCOPE_ADMIN) && user.role != ROLE_ADMIN {
+        return Err(GlobalServiceError::Forbidden(
+            ServiceError::PermissionDenied,
+        ));
+    }
+
+    let expires_at = match req.expires_in_days {
+        Some(days) if days <= 0 => {
+            return Err(GlobalServiceError::BadRequest(
+                "expires_in_days must be positive".to_string(),
+            ))
+        }
+        Some(days) => Some(Utc::now() + Duration::days(days)),
+        None => None,
+    };
+
+    let (prefix, key) = generate_api_key();
+    let api_key = diesel::insert_into(api_keys::table)
+        .values(&NewApiKey {
+            user_id: user.id,
+            name,
+            prefix: &prefix,
+            key_hash: &sha256_hex(&key),
+            scopes: &scopes,
+            expires_at,
+        })
+        .get_result::<ApiKey>(conn)?;
+
+    audit::record(
+        conn,
+        audit::API_KEY_CREATED,
+        Some(user.id),
+        Some(user.id),
+        &client,
+        json!({ "api_key_id": api_key.id, "name": api_key.name, "scopes": api_key.scopes }),
+    )?;
+
+    Ok(ResponseBody::new(
+        MESSAGE_CREATE_API_KEY_SUCCESS,
+        Some(CreateApiKeyResponse { api_key, key }),
+        None,
+    ))
+}
+
+fn list_query(
+    user_email: String,
+    pool: web::Data<Pool>,
+) -> Result<ResponseBody<Vec<ApiKey>>, GlobalServiceError> {
+    let conn: &PgConnection = &pool.get().unwrap();
+    let user = find_by_email(conn, &user_email)?;
+
+    let keys = api_keys::table
+        .filter(api_keys::user_id.eq(user.id))
+        .order(api_keys::created_at)
+        .load::<ApiKey>(conn)?;
+
+    Ok(ResponseBody::new(
+        MESSAGE_GET_API_KEYS_SUCCESS,
+        Some(keys),
+        None,
+    ))
+}
+
+fn revoke_query(
+    user_email: String,
+    api_key_id: i32,
+    client: ClientInfo,
+    pool: web::Data<Pool>,
+) -> Result<ResponseBody<()>, GlobalServiceError> {
+    let conn: &PgConnection = &pool.get().unwrap();
+    let user = find_by_email(conn, &user_email)?;
+
+    let deleted = diesel::delete(
+        api_keys::table
+            .filter(api_keys::id.eq(api_key_id))
+            .filter(api_keys::user_id.eq(user.id)),
+    )
+    .execute(conn)?;
+
+    match deleted {
+        0 => Err(GlobalServiceError::NotFound(ServiceError::ApiKeyNotFound)),
+        _ => {
+            audit::record(
+                conn,
+                audit::API_KEY_REVOKED,
+                Some(user.id),
+                Some(user.id),
+                &client,
+                json!({ "api_key_id": api_key_id }),
+            )?;
+
+            Ok(ResponseBody::new(
+                MESSAGE_REVOKE_API_KEY_SUCCESS,
+                None,
+                None,
+            ))
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use crate::api::auth::oauth::start_authorization;
+use crate::api::profile::session_email;
+use crate::audit;
+use crate::config::OAuthConfig;
+use crate::constants::{MESSAGE_GET_IDENTITIES_SUCCESS, MESSAGE_UNLINK_IDENTITY_SUCCESS};
+use crate::extractor::auth::AuthExtractor;
+use crate::model::errors::ServiceError;
+use crate::model::identity::UserIdentity;
+use crate::model::user::find_by_email;
+use crate::model::{db::Pool, errors::GlobalServiceError, response::ResponseBody};
+use crate::schema::user_identities;
+use crate::session::ClientInfo;
+use crate::telemetry;
+use actix_web::error::BlockingError;
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use diesel::{ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use serde_json::json;
+use tracing::instrument;
+
+#[instrument(skip_all)]
+pub async fn list_identities_handler(
+    pool: web::Data<Pool>,
+    auth_data: AuthExtractor,
+) -> Result<HttpResponse, GlobalServiceError> {
+    let user_email = session_email(&auth_data)?;
+    let res = telemetry::block(move || list_query(user_email, pool)).await;
+
+    match res {
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
+        Err(err) => match err {
+            BlockingError::Error(service_error) => Err(service_error),
+            BlockingError::Canceled => Err(GlobalServiceError::InternalServerError),
+        },
+    }
+}
+
+// Answers with the provider's authorization url, the callback then links the
+// external account to the logged in user
+#[instrument(skip_all)]
+pub async fn link_identity_handler(
+    provider: web::Path<String>,
+    pool: web::Data<Pool>,
+    oauth_config: web::Data<OAuthConfig>,
+    auth_data: AuthExtractor,
+) -> Result<HttpResponse, GlobalServiceError> {
+    let user_email = session_email(&auth_data)?;
+    start_authorization(pool, &oauth_config, provider.into_inner(), Some(user_email)).await
+}
+
+#[instrument(skip_all)]
+pub async fn unlink_identity_handler(
+    http_req: HttpRequest,
+    identity_id: web::Path<i32>,
+    pool: web::Data<Pool>,
+    auth_data: AuthExtractor,
+) -> Result<HttpResponse, GlobalServiceError> {
+    let user_email = session_email(&auth_data)?;
+    let client = ClientInfo::from_request(&http_req);
+    let res =
+        telemetry::block(move || unlink_query(user_email, identity_id.into_inner(), client, pool))
+            .await;
+
+    match res {
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
+        Err(err) => match err {
+            BlockingError::Error(service_error) => Err(service_error),
+            BlockingError::Canceled => Err(GlobalServiceError::InternalServerError),
+        },
+    }
+}
+
+fn list_query(
+    user_email: String,
+    pool: web::Data<Pool>,
+) -> Result<ResponseBody<Vec<UserIdentity>>, GlobalServiceError> {
+    let conn: &PgConnection = &pool.get().unwrap();
+    let user_id = find_by_email(conn, &user_email)?.id;
+
+    user_identities::table
+        .filter(user_identities::user_id.eq(user_id))
+        .order(user_identities::created_at)
+        .load::<UserIdentity>(conn)
+        .map(|identities| ResponseBody::new(MESSAGE_GET_IDENTITIES_SUCCESS, Some(identities), None))
+        .map_err(|_err| GlobalServiceError::InternalServerError)
+}
+
+fn unlink_query(
+    user_email: String,
+    identity_id: i32,
+    client: ClientInfo,
+    pool: web::Data<Pool>,
+) -> Result<ResponseBody<()>, GlobalServiceError> {
+    let conn: &PgConnection = &pool.get().unwrap();
+    let user_id = find_by_email(conn, &user_email)?.id;
+
+    let deleted = diesel::delete(
+        user_identities::table
+            .filter(user_identities::id.eq(identity_id))
+            .filter(user_identities::user_id.eq(user_id)),
+    )
+    .execute(conn)
+    .map_err(|_err| GlobalServiceError::InternalServerError)?;
+
+    match deleted {
+        0 => Err(GlobalServiceError::NotFound(ServiceError::IdentityNotFound)),
+        _ => {
+            audit::record(
+                conn,
+                audit::IDENTITY_UNLINKED,
+                Some(user_id),
+                Some(user_id),
+                &client,
+                json!({ "identity_id": identity_id }),
+            )?;
+
+            Ok(ResponseBody::new(
+                MESSAGE_UNLINK_IDENTITY_SUCCESS,
+                None,
+                None,
+            ))
+        }
+    }
+}
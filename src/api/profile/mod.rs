@@ -1 +1,28 @@
+pub mod api_keys;
+pub mod identities;
 pub mod my_profile;
+pub mod sessions;
+pub mod update_profile;
+pub mod webauthn;
+
+use crate::extractor::auth::AuthExtractor;
+use crate::model::auth::AuthMiddlewareData;
+use crate::model::errors::{GlobalServiceError, ServiceError};
+
+// For endpoints that manage credentials: they need a real session, a leaked
+// API key must not be able to mint new keys or end the owner's sessions, and
+// neither can an admin impersonating the owner
+pub fn session_email(auth_data: &AuthExtractor) -> Result<String, GlobalServiceError> {
+    match auth_data.as_ref() {
+        Some(AuthMiddlewareData {
+            email,
+            api_key_id: None,
+            impersonator_id: None,
+            ..
+        }) => Ok(email.clone()),
+        Some(_) => Err(GlobalServiceError::Forbidden(
+            ServiceError::PermissionDenied,
+        )),
+        None => Err(GlobalServiceError::Unauthorized(ServiceError::InvalidToken)),
+    }
+}
@@ -1,30 +1,47 @@
+use crate::api::users::public_profile::PublicProfile;
 use crate::constants::MESSAGE_GET_PROFILE_SUCCESS;
 use crate::extractor::auth::AuthExtractor;
 use crate::model::user::User;
-use crate::model::{db::Pool, errors::GlobalServiceError, response::ResponseBody};
+use crate::model::{db::lower, db::Pool, errors::GlobalServiceError, response::ResponseBody};
 use crate::schema::users::dsl::{email, users};
+use crate::telemetry;
 use actix_web::error::BlockingError;
 use actix_web::{web, HttpResponse, Result};
 use diesel::result::Error;
 use diesel::PgConnection;
 use diesel::{QueryDsl, RunQueryDsl};
 use serde::Serialize;
+use tracing::instrument;
 
 #[derive(Serialize)]
 pub struct MyProfileResponse {
     email: String,
     full_name: String,
+    // what everyone else sees at /v1/users/{username}
+    #[serde(flatten)]
+    public_profile: PublicProfile,
 }
 
+impl From<User> for MyProfileResponse {
+    fn from(user: User) -> MyProfileResponse {
+        MyProfileResponse {
+            public_profile: PublicProfile::from(&user),
+            email: user.email,
+            full_name: user.full_name.unwrap_or_default(),
+        }
+    }
+}
+
+#[instrument(skip_all)]
 pub async fn my_profile_handler(
     pool: web::Data<Pool>,
     auth_data: AuthExtractor,
 ) -> Result<HttpResponse, GlobalServiceError> {
     let user_email = auth_data.as_ref().map(|x| x.email.clone());
-    let res = web::block(move || query(user_email.unwrap_or_default(), pool)).await;
+    let res = telemetry::block(move || query(user_email.unwrap_or_default(), pool)).await;
 
     match res {
-        Ok(my_profile_response) => return Ok(HttpResponse::Ok().json(my_profile_response)),
+        Ok(my_profile_response) => Ok(HttpResponse::Ok().json(my_profile_response)),
         Err(err) => match err {
             BlockingError::Error(service_error) => Err(service_error),
             BlockingError::Canceled => Err(GlobalServiceError::InternalServerError),
@@ -39,21 +56,15 @@ pub fn query(
     let conn: &PgConnection = &pool.get().unwrap();
     use crate::diesel::ExpressionMethods;
 
-    let res: Result<User, Error> = users.filter(email.eq(&user_email)).first(conn); //.load::<User>(conn);
+    let res: Result<User, Error> = users.filter(lower(email).eq(&user_email)).first(conn);
     match res {
-        Ok(user) => {
-            let response = MyProfileResponse {
-                email: user.email,
-                full_name: user.full_name.unwrap_or_default(),
-            };
-            Ok(ResponseBody::new(
-                MESSAGE_GET_PROFILE_SUCCESS,
-                Some(response),
-                None,
-            ))
-        }
+        Ok(user) => Ok(ResponseBody::new(
+            MESSAGE_GET_PROFILE_SUCCESS,
+            Some(MyProfileResponse::from(user)),
+            None,
+        )),
         Err(e) => {
-            println!("{:?}", e);
+            tracing::error!(error = ?e, "failed to load profile");
             Err(GlobalServiceError::InternalServerError)
         }
     }
@@ -0,0 +1,130 @@
+use crate::api::profile::session_email;
+use crate::audit;
+use crate::constants::{MESSAGE_GET_SESSIONS_SUCCESS, MESSAGE_REVOKE_SESSION_SUCCESS};
+use crate::extractor::auth::AuthExtractor;
+use crate::model::errors::ServiceError;
+use crate::model::session::Session;
+use crate::model::user::find_by_email;
+use crate::model::{db::Pool, errors::GlobalServiceError, response::ResponseBody};
+use crate::schema::sessions;
+use crate::session::ClientInfo;
+use crate::telemetry;
+use actix_web::error::BlockingError;
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use chrono::Utc;
+use diesel::{ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use serde::Serialize;
+use serde_json::json;
+use tracing::instrument;
+
+#[derive(Serialize)]
+pub struct SessionResponse {
+    #[serde(flatten)]
+    session: Session,
+    // the session making this request
+    current: bool,
+}
+
+#[instrument(skip_all)]
+pub async fn list_sessions_handler(
+    pool: web::Data<Pool>,
+    auth_data: AuthExtractor,
+) -> Result<HttpResponse, GlobalServiceError> {
+    let user_email = session_email(&auth_data)?;
+    let current_session_id = auth_data.as_ref().and_then(|x| x.session_id);
+    let res = telemetry::block(move || list_query(user_email, current_session_id, pool)).await;
+
+    match res {
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
+        Err(err) => match err {
+            BlockingError::Error(service_error) => Err(service_error),
+            BlockingError::Canceled => Err(GlobalServiceError::InternalServerError),
+        },
+    }
+}
+
+#[instrument(skip_all)]
+pub async fn revoke_session_handler(
+    http_req: HttpRequest,
+    session_id: web::Path<i32>,
+    pool: web::Data<Pool>,
+    auth_data: AuthExtractor,
+) -> Result<HttpResponse, GlobalServiceError> {
+    let user_email = session_email(&auth_data)?;
+    let client = ClientInfo::from_request(&http_req);
+    let res =
+        telemetry::block(move || revoke_query(user_email, session_id.into_inner(), client, pool))
+            .await;
+
+    match res {
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
+        Err(err) => match err {
+            BlockingError::Error(service_error) => Err(service_error),
+            BlockingError::Canceled => Err(GlobalServiceError::InternalServerError),
+        },
+    }
+}
+
+fn list_query(
+    user_email: String,
+    current_session_id: Option<i32>,
+    pool: web::Data<Pool>,
+) -> Result<ResponseBody<Vec<SessionResponse>>, GlobalServiceError> {
+    let conn: &PgConnection = &pool.get().unwrap();
+    let user_id = find_by_email(conn, &user_email)?.id;
+
+    let sessions = sessions::table
+        .filter(sessions::user_id.eq(user_id))
+        .filter(sessions::expires_at.gt(Utc::now()))
+        .order(sessions::last_seen_at.desc())
+        .load::<Session>(conn)?
+        .into_iter()
+        .map(|session| SessionResponse {
+            current: Some(session.id) == current_session_id,
+            session,
+        })
+        .collect();
+
+    Ok(ResponseBody::new(
+        MESSAGE_GET_SESSIONS_SUCCESS,
+        Some(sessions),
+        None,
+    ))
+}
+
+fn revoke_query(
+    user_email: String,
+    session_id: i32,
+    client: ClientInfo,
+    pool: web::Data<Pool>,
+) -> Result<ResponseBody<()>, GlobalServiceError> {
+    let conn: &PgConnection = &pool.get().unwrap();
+    let user_id = find_by_email(conn, &user_email)?.id;
+
+    let deleted = diesel::delete(
+        sessions::table
+            .filter(sessions::id.eq(session_id))
+            .filter(sessions::user_id.eq(user_id)),
+    )
+    .execute(conn)?;
+
+    match deleted {
+        0 => Err(GlobalServiceError::NotFound(ServiceError::SessionNotFound)),
+        _ => {
+            audit::record(
+                conn,
+                audit::SESSION_REVOKED,
+                Some(user_id),
+                Some(user_id),
+                &client,
+                json!({ "session_id": session_id, "reason": "revoked" }),
+            )?;
+
+            Ok(ResponseBody::new(
+                MESSAGE_REVOKE_SESSION_SUCCESS,
+                None,
+                None,
+            ))
+        }
+    }
+}
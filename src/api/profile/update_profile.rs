@@ -0,0 +1,181 @@
+use crate::api::profile::my_profile::MyProfileResponse;
+use crate::constants::{
+    BIO_MAX_LENGTH, DISPLAY_NAME_MAX_LENGTH, MESSAGE_UPDATE_PROFILE_SUCCESS, SOCIAL_LINKS_MAX,
+};
+use crate::extractor::auth::AuthExtractor;
+use crate::model::errors::ServiceError;
+use crate::model::user::{ProfileChanges, User};
+use crate::model::{db::lower, db::Pool, errors::GlobalServiceError, response::ResponseBody};
+use crate::schema::users;
+use crate::telemetry;
+use crate::utils::{normalize_username, validate_username, validate_web_url};
+use actix_web::error::BlockingError;
+use actix_web::{web, HttpResponse, Result};
+use diesel::result::{DatabaseErrorKind, Error::DatabaseError};
+use diesel::{ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use tracing::instrument;
+
+// Left out fields stay as they are, an empty string clears one
+#[derive(Deserialize)]
+pub struct UpdateProfileRequest {
+    username: Option<String>,
+    display_name: Option<String>,
+    bio: Option<String>,
+    website: Option<String>,
+    // network name to profile URL, replaces all links when given
+    social_links: Option<BTreeMap<String, String>>,
+    avatar_url: Option<String>,
+}
+
+#[instrument(skip_all)]
+pub async fn update_profile_handler(
+    req: web::Json<UpdateProfileRequest>,
+    pool: web::Data<Pool>,
+    auth_data: AuthExtractor,
+) -> Result<HttpResponse, GlobalServiceError> {
+    let user_email = auth_data.as_ref().map(|x| x.email.clone());
+    let res =
+        telemetry::block(move || query(user_email.unwrap_or_default(), req.into_inner(), pool))
+            .await;
+
+    match res {
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
+        Err(err) => match err {
+            BlockingError::Error(service_error) => Err(service_error),
+            BlockingError::Canceled => Err(GlobalServiceError::InternalServerError),
+        },
+    }
+}
+
+// The username used by registration and profile updates, normalized
+pub fn parse_username(username: &str) -> Result<String, GlobalServiceError> {
+    let username = normalize_username(username);
+    if !validate_username(&username) {
+        return Err(GlobalServiceError::BadRequest(
+            "Username must be 3 to 30 lowercase letters, digits, _ or -".to_string(),
+        ));
+    }
+
+    Ok(username)
+}
+
+// The username is unique, losing a race for it is a conflict
+fn username_conflict(err: diesel::result::Error) -> GlobalServiceError {
+    match err {
+        DatabaseError(DatabaseErrorKind::UniqueViolation, ref info)
+            if info.constraint_name() == Some("users_username_key") =>
+        {
+            GlobalServiceError::Conflict(ServiceError::UsernameAlreadyExists)
+        }
+        err => GlobalServiceError::from(err),
+    }
+}
+
+fn text_field(
+    name: &str,
+    value: Option<String>,
+    max_length: usize,
+) -> Result<Option<Option<String>>, GlobalServiceError> {
+    match value.as_deref().map(str::trim) {
+        None => Ok(None),
+        Some("") => Ok(Some(None)),
+        Some(value) if value.chars().count() > max_length => Err(GlobalServiceError::BadRequest(
+            format!("{} can be at most {} characters", name, max_length),
+        )),
+        Some(value) => Ok(Some(Some(value.to_string()))),
+    }
+}
+
+fn url_field(
+    name: &str,
+    value: Option<String>,
+) -> Result<Option<Option<String>>, GlobalServiceError> {
+    match value.as_deref().map(str::trim) {
+        None => Ok(None),
+        Some("") => Ok(Some(None)),
+        Some(value) if !validate_web_url(value) => Err(GlobalServiceError::BadRequest(format!(
+            "{} must be an http or https URL",
+            name
+        ))),
+        Some(value) => Ok(Some(Some(value.to_string()))),
+    }
+}
+
+fn profile_changes(req: UpdateProfileRequest) -> Result<ProfileChanges, GlobalServiceError> {
+    let username = match req.username {
+        Some(ref username) => Some(parse_username(username)?),
+        None => None,
+    };
+
+    let social_links = match req.social_links {
+        Some(links) if links.len() > SOCIAL_LINKS_MAX => {
+            return Err(GlobalServiceError::BadRequest(format!(
+                "At most {} social links are allowed",
+                SOCIAL_LINKS_MAX
+            )))
+        }
+        Some(links) => {
+            let mut cleaned = BTreeMap::new();
+            for (network, url) in links {
+                let network = network.trim().to_lowercase();
+                let url = url.trim().to_string();
+                if network.is_empty() || network.len() > 30 || !validate_web_url(&url) {
+                    return Err(GlobalServiceError::BadRequest(format!(
+                        "Invalid social link {}",
+                        network
+                    )));
+                }
+                cleaned.insert(network, url);
+            }
+            Some(json!(cleaned))
+        }
+        None => None,
+    };
+
+    Ok(ProfileChanges {
+        username,
+        display_name: text_field("display_name", req.display_name, DISPLAY_NAME_MAX_LENGTH)?,
+        bio: text_field("bio", req.bio, BIO_MAX_LENGTH)?,
+        website: url_field("website", req.website)?,
+        social_links,
+        avatar_url: url_field("avatar_url", req.avatar_url)?,
+    })
+}
+
+fn query(
+    user_email: String,
+    req: UpdateProfileRequest,
+    pool: web::Data<Pool>,
+) -> Result<ResponseBody<MyProfileResponse>, GlobalServiceError> {
+    let conn: &PgConnection = &pool.get().unwrap();
+    let changes = profile_changes(req)?;
+
+    let user = users::table
+        .filter(lower(users::email).eq(&user_email))
+        .first::<User>(conn)
+        .map_err(|_err| GlobalServiceError::NotFound(ServiceError::UserNotFound))?;
+    // Nothing to update makes diesel build an empty SET
+    let user = match changes {
+        ProfileChanges {
+            username: None,
+            display_name: None,
+            bio: None,
+            website: None,
+            social_links: None,
+            avatar_url: None,
+        } => user,
+        changes => diesel::update(&user)
+            .set(&changes)
+            .get_result::<User>(conn)
+            .map_err(username_conflict)?,
+    };
+
+    Ok(ResponseBody::new(
+        MESSAGE_UPDATE_PROFILE_SUCCESS,
+        Some(MyProfileResponse::from(user)),
+        None,
+    ))
+}
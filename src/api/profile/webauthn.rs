@@ -0,0 +1,370 @@
+use crate::api::auth::webauthn::{
+    consume_challenge, create_challenge, CredentialDescriptor, PasskeyChallenge,
+};
+use crate::api::profile::session_email;
+use crate::audit;
+use crate::config::WebauthnConfig;
+use crate::constants::{
+    MESSAGE_GET_PASSKEYS_SUCCESS, MESSAGE_PASSKEY_CHALLENGE, MESSAGE_REGISTER_PASSKEY_SUCCESS,
+    MESSAGE_REVOKE_PASSKEY_SUCCESS,
+};
+use crate::extractor::auth::AuthExtractor;
+use crate::model::errors::ServiceError;
+use crate::model::user::find_by_email;
+use crate::model::webauthn::{NewWebauthnCredential, WebauthnCredential, PURPOSE_REGISTRATION};
+use crate::model::{db::Pool, errors::GlobalServiceError, response::ResponseBody};
+use crate::schema::webauthn_credentials;
+use crate::session::ClientInfo;
+use crate::telemetry;
+use crate::webauthn::{
+    base64url_decode, base64url_encode, cose_algorithm, parse_attestation_object,
+    parse_authenticator_data, verify_client_data, COSE_ALG_EDDSA, COSE_ALG_ES256, COSE_ALG_RS256,
+};
+use actix_web::error::BlockingError;
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use diesel::{ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::instrument;
+
+#[derive(Serialize)]
+pub struct RelyingParty {
+    id: String,
+    name: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserEntity {
+    id: String,
+    name: String,
+    display_name: String,
+}
+
+#[derive(Serialize)]
+pub struct CredentialParameters {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    alg: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticatorSelection {
+    resident_key: &'static str,
+    user_verification: &'static str,
+}
+
+// PublicKeyCredentialCreationOptions for navigator.credentials.create()
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreationOptions {
+    rp: RelyingParty,
+    user: UserEntity,
+    challenge: String,
+    pub_key_cred_params: Vec<CredentialParameters>,
+    timeout: i64,
+    exclude_credentials: Vec<CredentialDescriptor>,
+    authenticator_selection: AuthenticatorSelection,
+    attestation: &'static str,
+}
+
+#[derive(Deserialize)]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    client_data_json: String,
+    #[serde(rename = "attestationObject")]
+    attestation_object: String,
+}
+
+#[derive(Deserialize)]
+pub struct AttestationCredential {
+    id: String,
+    response: AttestationResponse,
+}
+
+#[derive(Deserialize)]
+pub struct RegisterFinishRequest {
+    challenge_id: i32,
+    // so the owner can tell their passkeys apart, e.g. "Laptop"
+    name: String,
+    credential: AttestationCredential,
+}
+
+#[instrument(skip_all)]
+pub async fn register_start_handler(
+    pool: web::Data<Pool>,
+    webauthn_config: web::Data<WebauthnConfig>,
+    auth_data: AuthExtractor,
+) -> Result<HttpResponse, GlobalServiceError> {
+    let user_email = session_email(&auth_data)?;
+    let res =
+        telemetry::block(move || register_start_query(user_email, pool, &webauthn_config)).await;
+
+    match res {
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
+        Err(err) => match err {
+            BlockingError::Error(service_error) => Err(service_error),
+            BlockingError::Canceled => Err(GlobalServiceError::InternalServerError),
+        },
+    }
+}
+
+#[instrument(skip_all)]
+pub async fn register_finish_handler(
+    http_req: HttpRequest,
+    req: web::Json<RegisterFinishRequest>,
+    pool: web::Data<Pool>,
+    webauthn_config: web::Data<WebauthnConfig>,
+    auth_data: AuthExtractor,
+) -> Result<HttpResponse, GlobalServiceError> {
+    let user_email = session_email(&auth_data)?;
+    let client = ClientInfo::from_request(&http_req);
+    let res = telemetry::block(move || {
+        register_finish_query(user_email, req.into_inner(), client, pool, &webauthn_config)
+    })
+    .await;
+
+    match res {
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
+        Err(err) => match err {
+            BlockingError::Error(service_error) => Err(service_error),
+            BlockingError::Canceled => Err(GlobalServiceError::InternalServerError),
+        },
+    }
+}
+
+#[instrument(skip_all)]
+pub async fn list_credentials_handler(
+    pool: web::Data<Pool>,
+    auth_data: AuthExtractor,
+) -> Result<HttpResponse, GlobalServiceError> {
+    let user_email = session_email(&auth_data)?;
+    let res = telemetry::block(move || list_query(user_email, pool)).await;
+
+    match res {
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
+        Err(err) => match err {
+            BlockingError::Error(service_error) => Err(service_error),
+            BlockingError::Canceled => Err(GlobalServiceError::InternalServerError),
+        },
+    }
+}
+
+#[instrument(skip_all)]
+pub async fn revoke_credential_handler(
+    http_req: HttpRequest,
+    credential_id: web::Path<i32>,
+    pool: web::Data<Pool>,
+    auth_data: AuthExtractor,
+) -> Result<HttpResponse, GlobalServiceError> {
+    let user_email = session_email(&auth_data)?;
+    let client = ClientInfo::from_request(&http_req);
+    let res = telemetry::block(move || {
+        revoke_query(user_email, credential_id.into_inner(), client, pool)
+    })
+    .await;
+
+    match res {
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
+        Err(err) => match err {
+            BlockingError::Error(service_error) => Err(service_error),
+            BlockingError::Canceled => Err(GlobalServiceError::InternalServerError),
+        },
+    }
+}
+
+fn register_start_query(
+    user_email: String,
+    pool: web::Data<Pool>,
+    config: &WebauthnConfig,
+) -> Result<ResponseBody<PasskeyChallenge<CreationOptions>>, GlobalServiceError> {
+    let conn: &PgConnection = &pool.get().unwrap();
+    let user = find_by_email(conn, &user_email)?;
+
+    // Keeps the same authenticator from being registered twice
+    let exclude_credentials = webauthn_credentials::table
+        .filter(webauthn_credentials::user_id.eq(user.id))
+        .select(webauthn_credentials::credential_id)
+        .load::<String>(conn)?
+        .into_iter()
+        .map(CredentialDescriptor::new)
+        .collect();
+    let (challenge_id, challenge) =
+        create_challenge(conn, Some(user.id), PURPOSE_REGISTRATION, config)?;
+
+    let options = CreationOptions {
+        rp: RelyingParty {
+            id: config.rp_id.clone(),
+            name: config.rp_name.clone(),
+        },
+        user: UserEntity {
+            id: base64url_encode(user.id.to_string().as_bytes()),
+            display_name: user.full_name.clone().unwrap_or_else(|| user.email.clone()),
+            name: user.email,
+        },
+        challenge,
+        pub_key_cred_params: [COSE_ALG_ES256, COSE_ALG_EDDSA, COSE_ALG_RS256]
+            .iter()
+            .map(|&alg| CredentialParameters {
+                kind: "public-key",
+                alg,
+            })
+            .collect(),
+        timeout: config.challenge_lifetime_seconds * 1000,
+        exclude_credentials,
+        authenticator_selection: AuthenticatorSelection {
+            resident_key: "required",
+            user_verification: "preferred",
+        },
+        attestation: "none",
+    };
+
+    Ok(ResponseBody::new(
+        MESSAGE_PASSKEY_CHALLENGE,
+        Some(PasskeyChallenge {
+            challenge_id,
+            public_key: options,
+        }),
+        None,
+    ))
+}
+
+fn register_finish_query(
+    user_email: String,
+    req: RegisterFinishRequest,
+    client: ClientInfo,
+    pool: web::Data<Pool>,
+    config: &WebauthnConfig,
+) -> Result<ResponseBody<WebauthnCredential>, GlobalServiceError> {
+    let conn: &PgConnection = &pool.get().unwrap();
+    let user = find_by_email(conn, &user_email)?;
+    let bad_request = |message: &str| GlobalServiceError::BadRequest(message.to_string());
+
+    if req.name.trim().is_empty() {
+        return Err(bad_request("Passkey name is required"));
+    }
+
+    let challenge = consume_challenge(conn, req.challenge_id, &[PURPOSE_REGISTRATION], config)?
+        .filter(|challenge| challenge.user_id == Some(user.id))
+        .ok_or_else(|| bad_request("Invalid passkey challenge"))?;
+
+    let response = &req.credential.response;
+    let client_data_json =
+        base64url_decode(&response.client_data_json).map_err(|err| bad_request(err.0))?;
+    let attestation_object =
+        base64url_decode(&response.attestation_object).map_err(|err| bad_request(err.0))?;
+
+    verify_client_data(
+        &client_data_json,
+        "webauthn.create",
+        &challenge.challenge,
+        config,
+    )
+    .map_err(|err| bad_request(err.0))?;
+    let authenticator_data =
+        parse_attestation_object(&attestation_object).map_err(|err| bad_request(err.0))?;
+    let auth_data =
+        parse_authenticator_data(&authenticator_data, config).map_err(|err| bad_request(err.0))?;
+
+    let (credential_id, public_key) =
+        match (auth_data.credential_id, auth_data.credential_public_key) {
+            (Some(credential_id), Some(public_key)) => {
+                (base64url_encode(&credential_id), public_key)
+            }
+            _ => return Err(bad_request("missing attested credential data")),
+        };
+    if credential_id != req.credential.id.trim_end_matches('=') {
+        return Err(bad_request("credential id mismatch"));
+    }
+    let algorithm = cose_algorithm(&public_key).map_err(|err| bad_request(err.0))?;
+
+    let credential = diesel::insert_into(webauthn_credentials::table)
+        .values(&NewWebauthnCredential {
+            user_id: user.id,
+            credential_id: &credential_id,
+            public_key: &public_key,
+            algorithm: algorithm as i32,
+            sign_count: i64::from(auth_data.sign_count),
+            name: req.name.trim(),
+        })
+        .get_result::<WebauthnCredential>(conn)
+        .map_err(|err| match err {
+            DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                bad_request("Passkey is already registered")
+            }
+            _ => GlobalServiceError::InternalServerError,
+        })?;
+
+    audit::record(
+        conn,
+        audit::PASSKEY_REGISTERED,
+        Some(user.id),
+        Some(user.id),
+        &client,
+        json!({ "passkey_id": credential.id, "name": credential.name }),
+    )?;
+
+    Ok(ResponseBody::new(
+        MESSAGE_REGISTER_PASSKEY_SUCCESS,
+        Some(credential),
+        None,
+    ))
+}
+
+fn list_query(
+    user_email: String,
+    pool: web::Data<Pool>,
+) -> Result<ResponseBody<Vec<WebauthnCredential>>, GlobalServiceError> {
+    let conn: &PgConnection = &pool.get().unwrap();
+    let user = find_by_email(conn, &user_email)?;
+
+    let credentials = webauthn_credentials::table
+        .filter(webauthn_credentials::user_id.eq(user.id))
+        .order(webauthn_credentials::created_at)
+        .load::<WebauthnCredential>(conn)?;
+
+    Ok(ResponseBody::new(
+        MESSAGE_GET_PASSKEYS_SUCCESS,
+        Some(credentials),
+        None,
+    ))
+}
+
+fn revoke_query(
+    user_email: String,
+    credential_id: i32,
+    client: ClientInfo,
+    pool: web::Data<Pool>,
+) -> Result<ResponseBody<()>, GlobalServiceError> {
+    let conn: &PgConnection = &pool.get().unwrap();
+    let user = find_by_email(conn, &user_email)?;
+
+    let deleted = diesel::delete(
+        webauthn_credentials::table
+            .filter(webauthn_credentials::id.eq(credential_id))
+            .filter(webauthn_credentials::user_id.eq(user.id)),
+    )
+    .execute(conn)?;
+
+    match deleted {
+        0 => Err(GlobalServiceError::NotFound(ServiceError::PasskeyNotFound)),
+        _ => {
+            audit::record(
+                conn,
+                audit::PASSKEY_REVOKED,
+                Some(user.id),
+                Some(user.id),
+                &client,
+                json!({ "passkey_id": credential_id }),
+            )?;
+
+            Ok(ResponseBody::new(
+                MESSAGE_REVOKE_PASSKEY_SUCCESS,
+                None,
+                None,
+            ))
+        }
+    }
+}
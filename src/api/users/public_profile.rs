@@ -0,0 +1,75 @@
+use crate::constants::MESSAGE_GET_PUBLIC_PROFILE_SUCCESS;
+use crate::model::errors::ServiceError;
+use crate::model::user::User;
+use crate::model::{db::Pool, errors::GlobalServiceError, response::ResponseBody};
+use crate::schema::users;
+use crate::telemetry;
+use crate::utils::normalize_username;
+use actix_web::error::BlockingError;
+use actix_web::{web, HttpResponse, Result};
+use diesel::{ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, RunQueryDsl};
+use serde::Serialize;
+use serde_json::Value;
+use tracing::instrument;
+
+// Everything here is visible to anyone, keep the email and anything else
+// private out of it
+#[derive(Serialize)]
+pub struct PublicProfile {
+    username: Option<String>,
+    display_name: Option<String>,
+    bio: Option<String>,
+    website: Option<String>,
+    social_links: Value,
+    avatar_url: Option<String>,
+}
+
+impl From<&User> for PublicProfile {
+    fn from(user: &User) -> PublicProfile {
+        PublicProfile {
+            username: user.username.clone(),
+            display_name: user.display_name.clone(),
+            bio: user.bio.clone(),
+            website: user.website.clone(),
+            social_links: user.social_links.clone(),
+            avatar_url: user.avatar_url.clone(),
+        }
+    }
+}
+
+#[instrument(skip_all)]
+pub async fn get_public_profile_handler(
+    username: web::Path<String>,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, GlobalServiceError> {
+    let res = telemetry::block(move || query(username.into_inner(), pool)).await;
+
+    match res {
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
+        Err(err) => match err {
+            BlockingError::Error(service_error) => Err(service_error),
+            BlockingError::Canceled => Err(GlobalServiceError::InternalServerError),
+        },
+    }
+}
+
+fn query(
+    username: String,
+    pool: web::Data<Pool>,
+) -> Result<ResponseBody<PublicProfile>, GlobalServiceError> {
+    let conn: &PgConnection = &pool.get().unwrap();
+
+    // Disabled accounts look the same as ones that don't exist
+    let user = users::table
+        .filter(users::username.eq(normalize_username(&username)))
+        .filter(users::disabled_at.is_null())
+        .first::<User>(conn)
+        .optional()?
+        .ok_or(GlobalServiceError::NotFound(ServiceError::UserNotFound))?;
+
+    Ok(ResponseBody::new(
+        MESSAGE_GET_PUBLIC_PROFILE_SUCCESS,
+        Some(PublicProfile::from(&user)),
+        None,
+    ))
+}
@@ -0,0 +1,77 @@
+// Personal API keys look like `fk_<prefix>_<secret>`. The prefix is stored in
+// the clear to find the key again, of the whole key only a hash is kept.
+
+use chrono::{Duration, Utc};
+use diesel::{
+    BoolExpressionMethods, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl,
+    RunQueryDsl,
+};
+
+use crate::model::api_key::ApiKey;
+use crate::schema::{api_keys, users};
+use crate::utils::{constant_time_eq, generate_random_token, sha256_hex};
+
+const KEY_START: &str = "fk_";
+
+// Returns the lookup prefix and the full key
+pub fn generate_api_key() -> (String, String) {
+    let prefix = generate_random_token(6);
+    let key = format!("{}{}_{}", KEY_START, prefix, generate_random_token(32));
+
+    (prefix, key)
+}
+
+fn key_prefix(key: &str) -> Option<&str> {
+    let (prefix, secret) = key.strip_prefix(KEY_START)?.split_once('_')?;
+
+    if prefix.is_empty() || secret.is_empty() {
+        None
+    } else {
+        Some(prefix)
+    }
+}
+
+// Returns the key with its owner's email, or None for unknown and expired keys
+pub fn find_api_key(
+    conn: &PgConnection,
+    key: &str,
+) -> Result<Option<(ApiKey, String)>, diesel::result::Error> {
+    let prefix = match key_prefix(key) {
+        Some(prefix) => prefix,
+        None => return Ok(None),
+    };
+
+    let found = api_keys::table
+        .inner_join(users::table)
+        .filter(api_keys::prefix.eq(prefix))
+        .filter(users::disabled_at.is_null())
+        .select((api_keys::all_columns, users::email))
+        .first::<(ApiKey, String)>(conn)
+        .optional()?;
+    let (api_key, email) = match found {
+        Some(found) => found,
+        None => return Ok(None),
+    };
+
+    if !constant_time_eq(api_key.key_hash.as_bytes(), sha256_hex(key).as_bytes())
+        || api_key
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= Utc::now())
+    {
+        return Ok(None);
+    }
+
+    // At most once a minute, scripts can be chatty
+    let now = Utc::now();
+    diesel::update(
+        api_keys::table.find(api_key.id).filter(
+            api_keys::last_used_at
+                .is_null()
+                .or(api_keys::last_used_at.lt(now - Duration::minutes(1))),
+        ),
+    )
+    .set(api_keys::last_used_at.eq(now))
+    .execute(conn)?;
+
+    Ok(Some((api_key, email)))
+}
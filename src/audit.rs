@@ -0,0 +1,54 @@
+// Security relevant events, written on the same connection as the change they
+// describe. The table itself rejects updates and deletes.
+
+use diesel::{PgConnection, RunQueryDsl};
+use serde_json::Value;
+
+use crate::model::audit::NewAuditEvent;
+use crate::model::errors::GlobalServiceError;
+use crate::schema::audit_events;
+use crate::session::ClientInfo;
+
+pub const LOGIN_SUCCEEDED: &str = "login.succeeded";
+pub const LOGIN_FAILED: &str = "login.failed";
+pub const USER_REGISTERED: &str = "user.registered";
+pub const SESSION_REVOKED: &str = "session.revoked";
+pub const API_KEY_CREATED: &str = "api_key.created";
+pub const API_KEY_REVOKED: &str = "api_key.revoked";
+pub const PASSKEY_REGISTERED: &str = "passkey.registered";
+pub const PASSKEY_REVOKED: &str = "passkey.revoked";
+pub const IDENTITY_LINKED: &str = "identity.linked";
+pub const IDENTITY_UNLINKED: &str = "identity.unlinked";
+pub const ADMIN_USER_UNLOCKED: &str = "admin.user_unlocked";
+pub const ADMIN_ROLE_CHANGED: &str = "admin.role_changed";
+pub const ADMIN_USER_DISABLED: &str = "admin.user_disabled";
+pub const ADMIN_USER_ENABLED: &str = "admin.user_enabled";
+pub const ADMIN_PASSWORD_RESET: &str = "admin.password_reset";
+pub const ADMIN_IMPERSONATED: &str = "admin.impersonated";
+pub const ADMIN_INVITE_CREATED: &str = "admin.invite_created";
+pub const ADMIN_INVITE_REVOKED: &str = "admin.invite_revoked";
+pub const ADMIN_TOKENS_REVOKED: &str = "admin.tokens_revoked";
+pub const PASSWORD_CHANGED: &str = "user.password_changed";
+
+// `actor` did something to `target`, for self-service both are the same user
+pub fn record(
+    conn: &PgConnection,
+    event: &str,
+    actor_user_id: Option<i32>,
+    target_user_id: Option<i32>,
+    client: &ClientInfo,
+    details: Value,
+) -> Result<(), GlobalServiceError> {
+    diesel::insert_into(audit_events::table)
+        .values(&NewAuditEvent {
+            event,
+            actor_user_id,
+            target_user_id,
+            ip: Some(&client.ip),
+            user_agent: client.user_agent.as_deref(),
+            details,
+        })
+        .execute(conn)?;
+
+    Ok(())
+}
@@ -0,0 +1,245 @@
+// Operational tasks run directly against the configured database, for when
+// the API can't be used, like creating the first admin.
+//
+//     cargo run --bin admin -- create-admin <email> [full name]
+//     cargo run --bin admin -- reset-password <email>
+//     cargo run --bin admin -- revoke-tokens <email>
+//
+// Passwords are read from stdin so they don't end up in the shell history.
+
+use diesel::{
+    Connection, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, RunQueryDsl,
+};
+use dotenv::dotenv;
+use fakhrusy_com_backend::api::auth::lockout;
+use fakhrusy_com_backend::audit;
+use fakhrusy_com_backend::config::Argon2Config;
+use fakhrusy_com_backend::constants::ROLE_ADMIN;
+use fakhrusy_com_backend::model::db::lower;
+use fakhrusy_com_backend::model::errors::GlobalServiceError;
+use fakhrusy_com_backend::model::user::{NewUser, User};
+use fakhrusy_com_backend::schema::{api_keys, sessions, users};
+use fakhrusy_com_backend::session::ClientInfo;
+use fakhrusy_com_backend::utils::{hash_password, normalize_email, validate_email};
+use serde_json::json;
+use std::io::{self, IsTerminal, Write};
+use std::{env, process};
+
+const USAGE: &str = "Usage: admin <command>
+
+  create-admin <email> [full name]  create an admin account
+  reset-password <email>            set a new password and end all sessions
+  revoke-tokens <email>             end all sessions and delete all API keys";
+
+// What the audit log shows as the client for changes made here
+fn cli_client() -> ClientInfo {
+    ClientInfo {
+        ip: "local".to_string(),
+        user_agent: Some("admin-cli".to_string()),
+    }
+}
+
+fn database_error(_err: GlobalServiceError) -> String {
+    "Failed to write to the database".to_string()
+}
+
+// Turns terminal echo off until dropped
+struct EchoOff(libc::termios);
+
+impl EchoOff {
+    fn new() -> io::Result<EchoOff> {
+        let mut termios = unsafe { std::mem::zeroed::<libc::termios>() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut termios) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let original = termios;
+        termios.c_lflag &= !libc::ECHO;
+        // keep the newline echoed so the prompt still ends where it should
+        termios.c_lflag |= libc::ECHONL;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(EchoOff(original))
+    }
+}
+
+impl Drop for EchoOff {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.0);
+        }
+    }
+}
+
+fn read_password() -> Result<String, String> {
+    let stdin = io::stdin();
+    let _echo_off = if stdin.is_terminal() {
+        eprint!("New password: ");
+        io::stderr().flush().ok();
+        Some(EchoOff::new().map_err(|err| format!("Failed to turn off echo: {}", err))?)
+    } else {
+        None
+    };
+
+    let mut password = String::new();
+    stdin
+        .read_line(&mut password)
+        .map_err(|err| format!("Failed to read the password: {}", err))?;
+    let password = password.trim_end_matches(['\r', '\n']).to_string();
+    if password.is_empty() {
+        return Err("The password can't be empty".to_string());
+    }
+
+    Ok(password)
+}
+
+fn find_user(conn: &PgConnection, email: &str) -> Result<Option<User>, String> {
+    users::table
+        .filter(lower(users::email).eq(normalize_email(email)))
+        .first::<User>(conn)
+        .optional()
+        .map_err(|err| format!("Failed to look up {}: {}", email, err))
+}
+
+fn existing_user(conn: &PgConnection, email: &str) -> Result<User, String> {
+    find_user(conn, email)?.ok_or(format!("No user with email {}", email))
+}
+
+fn create_admin(conn: &PgConnection, email: &str, full_name: &str) -> Result<(), String> {
+    let email = normalize_email(email);
+    if !validate_email(&email) {
+        return Err(format!("{} is not a valid email", email));
+    }
+    if find_user(conn, &email)?.is_some() {
+        return Err(format!("A user with email {} already exists", email));
+    }
+
+    let argon2_config = Argon2Config::from_env()?;
+    let hashed_password =
+        hash_password(&read_password()?, &argon2_config).map_err(database_error)?;
+    let user = conn
+        .transaction::<_, GlobalServiceError, _>(|| {
+            let user = diesel::insert_into(users::table)
+                .values(&NewUser {
+                    email: &email,
+                    hashed_password: &hashed_password,
+                    full_name,
+                    username: None,
+                    role: ROLE_ADMIN,
+                })
+                .get_result::<User>(conn)?;
+            audit::record(
+                conn,
+                audit::USER_REGISTERED,
+                None,
+                Some(user.id),
+                &cli_client(),
+                json!({ "method": "admin_cli", "role": ROLE_ADMIN }),
+            )?;
+
+            Ok(user)
+        })
+        .map_err(database_error)?;
+
+    println!("Created admin {} with id {}", user.email, user.id);
+    Ok(())
+}
+
+fn reset_password(conn: &PgConnection, email: &str) -> Result<(), String> {
+    let user = existing_user(conn, email)?;
+    let argon2_config = Argon2Config::from_env()?;
+    let hashed_password =
+        hash_password(&read_password()?, &argon2_config).map_err(database_error)?;
+
+    let ended_sessions = conn
+        .transaction::<_, GlobalServiceError, _>(|| {
+            diesel::update(users::table.find(user.id))
+                .set(users::hashed_password.eq(&hashed_password))
+                .execute(conn)?;
+            lockout::reset_account(conn, user.id)?;
+            let ended_sessions =
+                diesel::delete(sessions::table.filter(sessions::user_id.eq(user.id)))
+                    .execute(conn)?;
+            audit::record(
+                conn,
+                audit::PASSWORD_CHANGED,
+                None,
+                Some(user.id),
+                &cli_client(),
+                json!({ "method": "admin_cli" }),
+            )?;
+
+            Ok(ended_sessions)
+        })
+        .map_err(database_error)?;
+
+    println!(
+        "Password of {} changed, {} sessions ended",
+        user.email, ended_sessions
+    );
+    Ok(())
+}
+
+// Tokens are bound to sessions, ending those signs the user out everywhere
+fn revoke_tokens(conn: &PgConnection, email: &str) -> Result<(), String> {
+    let user = existing_user(conn, email)?;
+
+    let (ended_sessions, deleted_api_keys) = conn
+        .transaction::<_, GlobalServiceError, _>(|| {
+            let ended_sessions =
+                diesel::delete(sessions::table.filter(sessions::user_id.eq(user.id)))
+                    .execute(conn)?;
+            let deleted_api_keys =
+                diesel::delete(api_keys::table.filter(api_keys::user_id.eq(user.id)))
+                    .execute(conn)?;
+            audit::record(
+                conn,
+                audit::ADMIN_TOKENS_REVOKED,
+                None,
+                Some(user.id),
+                &cli_client(),
+                json!({
+                    "method": "admin_cli",
+                    "sessions": ended_sessions,
+                    "api_keys": deleted_api_keys,
+                }),
+            )?;
+
+            Ok((ended_sessions, deleted_api_keys))
+        })
+        .map_err(database_error)?;
+
+    println!(
+        "{} sessions ended and {} API keys deleted for {}",
+        ended_sessions, deleted_api_keys, user.email
+    );
+    Ok(())
+}
+
+fn main() {
+    dotenv().ok();
+    let connect = || {
+        let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        PgConnection::establish(&database_url).expect("Failed to connect to database")
+    };
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args.as_slice() {
+        ["create-admin", email] => create_admin(&connect(), email, ""),
+        ["create-admin", email, full_name] => create_admin(&connect(), email, full_name),
+        ["reset-password", email] => reset_password(&connect(), email),
+        ["revoke-tokens", email] => revoke_tokens(&connect(), email),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(message) = result {
+        eprintln!("{}", message);
+        process::exit(1);
+    }
+}
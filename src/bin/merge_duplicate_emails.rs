@@ -0,0 +1,85 @@
+// One-off tool to run before the `unique_user_email` migration.
+//
+// Finds accounts whose emails only differ by case or surrounding whitespace and
+// merges each group into the oldest account (lowest id). Without `--apply` it
+// only prints what it would do.
+//
+//     cargo run --bin merge_duplicate_emails [-- --apply]
+
+#![allow(non_local_definitions)]
+
+#[macro_use]
+extern crate diesel;
+
+use diesel::pg::PgConnection;
+use diesel::sql_types::{Array, Int4, Text};
+use diesel::{sql_query, Connection, RunQueryDsl};
+use dotenv::dotenv;
+use std::env;
+
+#[derive(QueryableByName)]
+struct DuplicateEmail {
+    #[sql_type = "Text"]
+    normalized_email: String,
+    #[sql_type = "Array<Int4>"]
+    ids: Vec<i32>,
+}
+
+fn main() -> Result<(), diesel::result::Error> {
+    dotenv().ok();
+    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let apply = env::args().any(|arg| arg == "--apply");
+
+    let conn = PgConnection::establish(&database_url).expect("Failed to connect to database");
+
+    let duplicates = sql_query(
+        "SELECT lower(trim(email)) AS normalized_email, array_agg(id ORDER BY id) AS ids \
+         FROM users GROUP BY lower(trim(email)) HAVING count(*) > 1",
+    )
+    .load::<DuplicateEmail>(&conn)?;
+
+    if duplicates.is_empty() {
+        println!("No duplicate emails found");
+        return Ok(());
+    }
+
+    for duplicate in duplicates.iter() {
+        let (keep, merged) = duplicate.ids.split_first().unwrap();
+        println!(
+            "{}: keeping user {}, merging {:?}",
+            duplicate.normalized_email, keep, merged
+        );
+    }
+
+    if !apply {
+        println!("Dry run, re-run with --apply to merge");
+        return Ok(());
+    }
+
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        for duplicate in duplicates.iter() {
+            let (keep, merged) = duplicate.ids.split_first().unwrap();
+
+            // Fill in what the kept account is missing from the merged ones
+            sql_query(
+                "UPDATE users SET full_name = COALESCE(full_name, ( \
+                     SELECT full_name FROM users \
+                     WHERE id = ANY($2) AND full_name IS NOT NULL \
+                     ORDER BY id LIMIT 1 \
+                 )) WHERE id = $1",
+            )
+            .bind::<Int4, _>(keep)
+            .bind::<Array<Int4>, _>(merged)
+            .execute(&conn)?;
+
+            sql_query("DELETE FROM users WHERE id = ANY($1)")
+                .bind::<Array<Int4>, _>(merged)
+                .execute(&conn)?;
+        }
+
+        Ok(())
+    })?;
+
+    println!("Merged {} duplicate email(s)", duplicates.len());
+    Ok(())
+}
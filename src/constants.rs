@@ -1,7 +1,82 @@
 pub const MESSAGE_LOGIN_SUCCESS: &str = "Logged in successfully";
 pub const MESSAGE_GET_PROFILE_SUCCESS: &str = "Get profile success";
+pub const MESSAGE_UPDATE_PROFILE_SUCCESS: &str = "Profile updated";
+pub const MESSAGE_GET_PUBLIC_PROFILE_SUCCESS: &str = "Get public profile success";
 pub const MESSAGE_INVALID_TOKEN: &str = "Invalid token";
+pub const MESSAGE_UNLOCK_USER_SUCCESS: &str = "User unlocked";
+pub const MESSAGE_RATE_LIMITED: &str = "Too many requests";
+pub const MESSAGE_LOGOUT_SUCCESS: &str = "Logged out successfully";
+pub const MESSAGE_INVALID_CSRF_TOKEN: &str = "Invalid CSRF token";
+pub const MESSAGE_OAUTH_AUTHORIZE: &str = "Continue at the provider";
+pub const MESSAGE_GET_IDENTITIES_SUCCESS: &str = "Get identities success";
+pub const MESSAGE_UNLINK_IDENTITY_SUCCESS: &str = "Identity unlinked";
+pub const MESSAGE_MAGIC_LINK_SENT: &str =
+    "If the email is registered, a sign-in link is on its way";
+pub const MESSAGE_SECOND_FACTOR_REQUIRED: &str = "Confirm the login with your passkey";
+pub const MESSAGE_PASSKEY_CHALLENGE: &str = "Continue with your passkey";
+pub const MESSAGE_REGISTER_PASSKEY_SUCCESS: &str = "Passkey registered";
+pub const MESSAGE_GET_PASSKEYS_SUCCESS: &str = "Get passkeys success";
+pub const MESSAGE_REVOKE_PASSKEY_SUCCESS: &str = "Passkey revoked";
+pub const MESSAGE_CREATE_API_KEY_SUCCESS: &str =
+    "API key created, copy it now as it won't be shown again";
+pub const MESSAGE_GET_API_KEYS_SUCCESS: &str = "Get API keys success";
+pub const MESSAGE_REVOKE_API_KEY_SUCCESS: &str = "API key revoked";
+pub const MESSAGE_GET_SESSIONS_SUCCESS: &str = "Get sessions success";
+pub const MESSAGE_REVOKE_SESSION_SUCCESS: &str = "Session revoked";
+pub const MESSAGE_GET_AUDIT_EVENTS_SUCCESS: &str = "Get audit events success";
+pub const MESSAGE_GET_USERS_SUCCESS: &str = "Get users success";
+pub const MESSAGE_GET_USER_SUCCESS: &str = "Get user success";
+pub const MESSAGE_CHANGE_ROLE_SUCCESS: &str = "Role changed";
+pub const MESSAGE_DISABLE_USER_SUCCESS: &str = "User disabled";
+pub const MESSAGE_ENABLE_USER_SUCCESS: &str = "User enabled";
+pub const MESSAGE_PASSWORD_RESET_SENT: &str = "Password reset, the user was emailed a link";
+pub const MESSAGE_PASSWORD_CHANGED: &str = "Password changed";
+pub const MESSAGE_IMPERSONATION_STARTED: &str = "Impersonating user";
+pub const MESSAGE_CREATE_INVITE_SUCCESS: &str = "Invite created";
+pub const MESSAGE_GET_INVITES_SUCCESS: &str = "Get invites success";
+pub const MESSAGE_REVOKE_INVITE_SUCCESS: &str = "Invite revoked";
+pub const MESSAGE_ALIVE: &str = "Alive";
+pub const MESSAGE_READY: &str = "Ready";
+pub const MESSAGE_NOT_READY: &str = "Not ready";
+pub const MESSAGE_INSUFFICIENT_SCOPE: &str = "Insufficient scope";
 
 pub const AUTHORIZATION: &str = "Authorization";
+pub const CSRF_TOKEN_HEADER: &str = "X-CSRF-Token";
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
 
-pub const AUTH_ROUTES: [&str; 2] = ["/v1/auth/login", "/v1/auth/register"];
+pub const JWT_EXPIRATION_SECONDS: i64 = 60 * 60 * 24 * 7;
+
+pub const ROLE_ADMIN: &str = "admin";
+pub const ROLE_USER: &str = "user";
+pub const ROLES: [&str; 2] = [ROLE_USER, ROLE_ADMIN];
+
+// impersonation tokens are short lived
+pub const IMPERSONATION_EXPIRATION_SECONDS: i64 = 60 * 60;
+// a probe waiting longer than this on the pool counts as not ready
+pub const READINESS_DB_TIMEOUT_SECONDS: u64 = 2;
+
+// read for GET/HEAD, write for everything else, admin for /v1/admin
+pub const API_KEY_SCOPE_READ: &str = "read";
+pub const API_KEY_SCOPE_WRITE: &str = "write";
+pub const API_KEY_SCOPE_ADMIN: &str = "admin";
+pub const API_KEY_SCOPES: [&str; 3] =
+    [API_KEY_SCOPE_READ, API_KEY_SCOPE_WRITE, API_KEY_SCOPE_ADMIN];
+
+// lowercase letters, digits, `_` and `-`, between 3 and 30 characters
+pub const RESERVED_USERNAMES: [&str; 8] = [
+    "admin", "api", "auth", "me", "profile", "root", "support", "users",
+];
+pub const DISPLAY_NAME_MAX_LENGTH: usize = 100;
+pub const BIO_MAX_LENGTH: usize = 1000;
+pub const SOCIAL_LINKS_MAX: usize = 10;
+
+pub const AUTH_ROUTES: [&str; 7] = [
+    "/v1/auth/login",
+    "/v1/auth/register",
+    "/v1/auth/oauth",
+    "/v1/auth/magic-link",
+    "/v1/auth/webauthn",
+    "/v1/auth/password",
+    // public author profiles
+    "/v1/users/",
+];
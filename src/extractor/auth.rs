@@ -16,7 +16,7 @@ impl FromRequest for AuthExtractor {
     ) -> Self::Future {
         let value = req.extensions().get::<AuthMiddlewareData>().cloned();
 
-        return ready(Ok(AuthExtractor(value)));
+        ready(Ok(AuthExtractor(value)))
     }
 }
 
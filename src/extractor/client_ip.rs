@@ -0,0 +1,350 @@
+use actix_web::{
+    dev::{Payload, ServiceRequest},
+    http::HeaderMap,
+    web, FromRequest, HttpRequest,
+};
+use futures::future::{ready, Ready};
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+};
+
+use crate::config::{ForwardedHeader, ProxyConfig};
+use crate::model::errors::GlobalServiceError;
+
+// The address of the client. Forwarding headers are only believed when they
+// come from a trusted proxy, and the chain in them only as far back as it
+// passed through trusted proxies, anything before that could be made up by
+// the client. `None` when it can't be told, e.g. a unix socket without a
+// proxy header.
+#[derive(Clone, Copy, PartialEq)]
+pub struct ClientIp(pub Option<IpAddr>);
+
+impl ClientIp {
+    pub fn from_request(req: &HttpRequest) -> ClientIp {
+        ClientIp::resolve(
+            req.app_data::<web::Data<ProxyConfig>>(),
+            req.headers(),
+            req.peer_addr(),
+        )
+    }
+
+    // For middleware, which sees requests before they become `HttpRequest`s
+    pub fn from_service_request(req: &ServiceRequest) -> ClientIp {
+        ClientIp::resolve(
+            req.app_data::<web::Data<ProxyConfig>>(),
+            req.headers(),
+            req.peer_addr(),
+        )
+    }
+
+    fn resolve(
+        config: Option<&web::Data<ProxyConfig>>,
+        headers: &HeaderMap,
+        peer: Option<SocketAddr>,
+    ) -> ClientIp {
+        let peer = peer.map(|addr| addr.ip().to_canonical());
+        let config = match config {
+            Some(config) => config,
+            None => return ClientIp(peer),
+        };
+
+        // only a local process, i.e. the proxy, can reach a unix socket
+        let peer_trusted = peer.is_none_or(|ip| config.is_trusted(ip));
+        if !peer_trusted {
+            return ClientIp(peer);
+        }
+
+        // walk back from the hop closest to us, the first address that isn't
+        // one of our proxies is the client
+        let mut client = peer;
+        for hop in forwarded_chain(config.header, headers).into_iter().rev() {
+            match hop {
+                Some(ip) => {
+                    client = Some(ip);
+                    if !config.is_trusted(ip) {
+                        break;
+                    }
+                }
+                // "unknown" or an obfuscated name, nothing further back can
+                // be checked
+                None => break,
+            }
+        }
+
+        ClientIp(client)
+    }
+}
+
+// Addresses in the order the proxies added them, every instance of the header
+// taken together
+fn forwarded_chain(header: ForwardedHeader, headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let name = match header {
+        ForwardedHeader::Forwarded => "forwarded",
+        ForwardedHeader::XForwardedFor => "x-forwarded-for",
+        ForwardedHeader::XRealIp => "x-real-ip",
+    };
+    let mut instances: Vec<_> = headers.get_all(name).collect();
+    // actix-http 2.2's `HeaderMap::append`, which requests are parsed with,
+    // puts the second instance of a header ahead of the first, later ones
+    // follow in order. The version is pinned in Cargo.toml for this.
+    if instances.len() > 1 {
+        instances.swap(0, 1);
+    }
+    let values = instances
+        .into_iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','));
+
+    match header {
+        ForwardedHeader::Forwarded => values
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+                    .and_then(|(_, node)| parse_node(node))
+            })
+            .collect(),
+        ForwardedHeader::XForwardedFor | ForwardedHeader::XRealIp => {
+            values.map(parse_node).collect()
+        }
+    }
+}
+
+// "192.0.2.1", "192.0.2.1:443", "[2001:db8::1]:443" and "2001:db8::1",
+// quoted or not
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+
+    let ip = if let Ok(ip) = node.parse::<IpAddr>() {
+        ip
+    } else if let Some(rest) = node.strip_prefix('[') {
+        rest.split_once(']')?.0.parse().ok()?
+    } else {
+        IpAddr::V4(node.split_once(':')?.0.parse::<Ipv4Addr>().ok()?)
+    };
+
+    Some(ip.to_canonical())
+}
+
+// Empty when unknown, as stored for sessions and audit events
+impl fmt::Display for ClientIp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(ip) => write!(f, "{}", ip),
+            None => Ok(()),
+        }
+    }
+}
+
+impl FromRequest for ClientIp {
+    type Error = GlobalServiceError;
+    type Future = Ready<Result<Self, GlobalServiceError>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(Ok(ClientIp::from_request(req)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::{HeaderName, HeaderValue};
+    use actix_web::test;
+
+    fn config(trusted: &[&str], header: ForwardedHeader) -> web::Data<ProxyConfig> {
+        web::Data::new(ProxyConfig {
+            trusted_proxies: trusted.iter().map(|net| net.parse().unwrap()).collect(),
+            header,
+        })
+    }
+
+    fn headers(entries: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in entries {
+            headers.append(
+                HeaderName::from_static(name),
+                HeaderValue::from_static(value),
+            );
+        }
+        headers
+    }
+
+    fn resolve(
+        config: &web::Data<ProxyConfig>,
+        entries: &[(&'static str, &'static str)],
+        peer: Option<&str>,
+    ) -> Option<String> {
+        let peer = peer.map(|peer| peer.parse().unwrap());
+        ClientIp::resolve(Some(config), &headers(entries), peer)
+            .0
+            .map(|ip| ip.to_string())
+    }
+
+    #[test]
+    fn ignores_headers_from_untrusted_peers() {
+        let config = config(&["10.0.0.0/8"], ForwardedHeader::XForwardedFor);
+        let spoofed = [("x-forwarded-for", "203.0.113.9")];
+
+        assert_eq!(
+            resolve(&config, &spoofed, Some("198.51.100.1:1234")),
+            Some("198.51.100.1".to_string())
+        );
+        // without any config the peer is all there is
+        let peer = "198.51.100.1:1234".parse().unwrap();
+        assert_eq!(
+            ClientIp::resolve(None, &headers(&spoofed), Some(peer)).0,
+            Some("198.51.100.1".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn picks_the_rightmost_untrusted_address() {
+        let config = config(&["10.0.0.0/8"], ForwardedHeader::XForwardedFor);
+        let peer = Some("10.0.0.1:1234");
+
+        // the client made up the first entry, our proxies added the others
+        let chain = [("x-forwarded-for", "1.2.3.4, 203.0.113.9, 10.1.1.1")];
+        assert_eq!(
+            resolve(&config, &chain, peer),
+            Some("203.0.113.9".to_string())
+        );
+
+        // only proxies in the chain, the first one is as far as it goes
+        let chain = [("x-forwarded-for", "10.2.2.2, 10.1.1.1")];
+        assert_eq!(resolve(&config, &chain, peer), Some("10.2.2.2".to_string()));
+
+        // a trusted proxy without the header is the client itself
+        assert_eq!(resolve(&config, &[], peer), Some("10.0.0.1".to_string()));
+    }
+
+    #[test]
+    fn joins_multiple_header_instances_in_order() {
+        let config = config(&["10.0.0.0/8"], ForwardedHeader::XForwardedFor);
+        let chain = [
+            ("x-forwarded-for", "203.0.113.9"),
+            ("x-forwarded-for", "198.51.100.7, 10.0.0.2"),
+        ];
+
+        assert_eq!(
+            resolve(&config, &chain, Some("10.0.0.1:1234")),
+            Some("198.51.100.7".to_string())
+        );
+
+        let chain = [
+            ("x-forwarded-for", "203.0.113.9"),
+            ("x-forwarded-for", "198.51.100.7"),
+            ("x-forwarded-for", "10.0.0.3, 10.0.0.2"),
+        ];
+        assert_eq!(
+            resolve(&config, &chain, Some("10.0.0.1:1234")),
+            Some("198.51.100.7".to_string())
+        );
+    }
+
+    // Through the same `HeaderMap::append` calls a parsed request makes, so a
+    // change in the order they leave behind fails here
+    #[test]
+    fn joins_header_instances_of_a_request_in_order() {
+        let req = test::TestRequest::default()
+            .header("x-forwarded-for", "203.0.113.9")
+            .header("x-forwarded-for", "198.51.100.7")
+            .header("x-forwarded-for", "10.0.0.3, 10.0.0.2")
+            .peer_addr("10.0.0.1:1234".parse().unwrap())
+            .app_data(config(&["10.0.0.0/8"], ForwardedHeader::XForwardedFor))
+            .to_http_request();
+
+        assert_eq!(
+            ClientIp::from_request(&req).0,
+            Some("198.51.100.7".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn parses_forwarded_nodes() {
+        let config = config(&["10.0.0.0/8"], ForwardedHeader::Forwarded);
+        let peer = Some("10.0.0.1:1234");
+
+        let chain = [(
+            "forwarded",
+            "for=\"[2001:db8::1]:4711\";proto=https, For=10.2.2.2;by=10.0.0.1",
+        )];
+        assert_eq!(
+            resolve(&config, &chain, peer),
+            Some("2001:db8::1".to_string())
+        );
+
+        let chain = [("forwarded", "proto=https;for=192.0.2.60:8080")];
+        assert_eq!(
+            resolve(&config, &chain, peer),
+            Some("192.0.2.60".to_string())
+        );
+
+        // other headers aren't read when Forwarded is configured
+        let chain = [("x-forwarded-for", "203.0.113.9")];
+        assert_eq!(resolve(&config, &chain, peer), Some("10.0.0.1".to_string()));
+    }
+
+    #[test]
+    fn stops_at_unknown_and_obfuscated_forwarded_nodes() {
+        let config = config(&["10.0.0.0/8"], ForwardedHeader::Forwarded);
+        let peer = Some("10.0.0.1:1234");
+
+        // the last address that could be checked is the proxy that hid it
+        let chain = [("forwarded", "for=203.0.113.9, for=unknown, for=10.2.2.2")];
+        assert_eq!(resolve(&config, &chain, peer), Some("10.2.2.2".to_string()));
+
+        let chain = [("forwarded", "for=203.0.113.9, for=_hidden")];
+        assert_eq!(resolve(&config, &chain, peer), Some("10.0.0.1".to_string()));
+
+        // an element without `for` can't be checked either
+        let chain = [("forwarded", "for=203.0.113.9, proto=https")];
+        assert_eq!(resolve(&config, &chain, peer), Some("10.0.0.1".to_string()));
+    }
+
+    #[test]
+    fn reads_x_real_ip() {
+        let config = config(&["10.0.0.0/8"], ForwardedHeader::XRealIp);
+        let chain = [("x-real-ip", "203.0.113.9")];
+
+        assert_eq!(
+            resolve(&config, &chain, Some("10.0.0.1:1234")),
+            Some("203.0.113.9".to_string())
+        );
+    }
+
+    #[test]
+    fn trusts_unix_socket_peers() {
+        let config = config(&[], ForwardedHeader::XForwardedFor);
+
+        let chain = [("x-forwarded-for", "203.0.113.9")];
+        assert_eq!(
+            resolve(&config, &chain, None),
+            Some("203.0.113.9".to_string())
+        );
+        assert_eq!(resolve(&config, &[], None), None);
+        assert_eq!(ClientIp(None).to_string(), "");
+    }
+
+    #[test]
+    fn parses_node_formats() {
+        let parse = |node| parse_node(node).map(|ip| ip.to_string());
+
+        assert_eq!(parse(" 192.0.2.1 "), Some("192.0.2.1".to_string()));
+        assert_eq!(parse("192.0.2.1:443"), Some("192.0.2.1".to_string()));
+        assert_eq!(
+            parse("\"[2001:db8::1]:443\""),
+            Some("2001:db8::1".to_string())
+        );
+        assert_eq!(parse("[2001:db8::1]"), Some("2001:db8::1".to_string()));
+        assert_eq!(parse("2001:db8::1"), Some("2001:db8::1".to_string()));
+        // IPv4 mapped addresses compare against IPv4 ranges
+        assert_eq!(parse("::ffff:192.0.2.1"), Some("192.0.2.1".to_string()));
+        assert_eq!(parse("unknown"), None);
+        assert_eq!(parse("_hidden:443"), None);
+        assert_eq!(parse("[2001:db8::1"), None);
+        assert_eq!(parse(""), None);
+    }
+}
@@ -0,0 +1,30 @@
+// diesel 1.4's `table!` and derive macros emit impls that newer compilers flag
+#![allow(non_local_definitions)]
+
+// Shared by the server in main.rs and the tools in src/bin
+
+#[macro_use]
+extern crate diesel;
+#[macro_use]
+extern crate diesel_migrations;
+extern crate dotenv;
+
+pub mod api;
+pub mod api_key;
+pub mod audit;
+pub mod config;
+pub mod constants;
+pub mod extractor;
+pub mod mailer;
+pub mod metrics;
+pub mod middleware;
+pub mod migrations;
+pub mod model;
+pub mod oauth;
+pub mod rate_limit;
+pub mod schema;
+pub mod session;
+pub mod telemetry;
+pub mod tls;
+pub mod utils;
+pub mod webauthn;
@@ -0,0 +1,97 @@
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use lettre::{message::Mailbox, Message, SmtpTransport, Transport};
+
+use crate::{config::MailConfig, model::errors::GlobalServiceError};
+
+// Blocking, call it from inside `web::block`
+pub fn send_email(
+    config: &MailConfig,
+    to: &str,
+    subject: &str,
+    body: String,
+) -> Result<(), GlobalServiceError> {
+    let smtp_url = match &config.smtp_url {
+        Some(smtp_url) => smtp_url,
+        None => {
+            // no SMTP server in development, the email goes to the log
+            tracing::info!(to, subject, "email not sent, SMTP_URL is not set");
+            if config.log_bodies {
+                tracing::debug!(to, subject, body = %body, "unsent email");
+            }
+            return Ok(());
+        }
+    };
+
+    let from: Mailbox = config
+        .from
+        .parse()
+        .map_err(|_err| GlobalServiceError::InternalServerError)?;
+    let to: Mailbox = to
+        .parse()
+        .map_err(|_err| GlobalServiceError::InternalServerError)?;
+
+    let message = Message::builder()
+        .from(from)
+        .to(to)
+        .subject(subject)
+        .body(body)
+        .map_err(|_err| GlobalServiceError::InternalServerError)?;
+
+    SmtpTransport::from_url(smtp_url)
+        .map_err(|_err| GlobalServiceError::InternalServerError)?
+        .build()
+        .send(&message)
+        .map(|_response| ())
+        .map_err(|_err| GlobalServiceError::InternalServerError)
+}
+
+// Emails sent in the background, waited for on shutdown
+static BACKGROUND_EMAILS: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+
+// For emails the response must neither wait on nor depend on, e.g. when how
+// long a request takes would tell whether an account exists. Sent from a
+// thread of its own, failures are only logged.
+pub fn send_email_in_background(
+    config: &MailConfig,
+    to: String,
+    subject: &'static str,
+    body: String,
+) {
+    let config = config.clone();
+    let span = tracing::Span::current();
+    let handle = thread::spawn(move || {
+        let _entered = span.enter();
+        if let Err(err) = send_email(&config, &to, subject, body) {
+            tracing::warn!(error = ?err, subject, "failed to send email");
+        }
+    });
+
+    let mut handles = BACKGROUND_EMAILS.lock().unwrap();
+    handles.retain(|handle| !handle.is_finished());
+    handles.push(handle);
+}
+
+// Waits up to `timeout` for the emails sent in the background and returns
+// how many were still being sent when it ran out
+pub fn wait_for_background_emails(timeout: Duration) -> usize {
+    let deadline = Instant::now() + timeout;
+    let mut handles = std::mem::take(&mut *BACKGROUND_EMAILS.lock().unwrap());
+
+    loop {
+        let (finished, running): (Vec<_>, Vec<_>) =
+            handles.into_iter().partition(|handle| handle.is_finished());
+        for handle in finished {
+            // a panic was already reported by the thread itself
+            handle.join().ok();
+        }
+        if running.is_empty() || Instant::now() >= deadline {
+            return running.len();
+        }
+
+        handles = running;
+        thread::sleep(Duration::from_millis(50));
+    }
+}
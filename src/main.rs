@@ -1,63 +1,524 @@
-use actix_web::{error, web, App, HttpResponse, HttpServer};
-mod api;
-mod constants;
-mod extractor;
-mod middleware;
-mod model;
-mod schema;
-mod utils;
-
-#[macro_use]
-extern crate diesel;
-extern crate dotenv;
-
+use actix_web::{error, error::JsonPayloadError, web, App, HttpResponse, HttpServer};
+use diesel::Connection;
 use diesel::{
     pg::PgConnection,
     r2d2::{self, ConnectionManager},
 };
+use diesel_migrations::RunMigrationsError;
 use dotenv::dotenv;
-use std::env;
+use futures::future;
+use std::{env, fs, io, iter, os::unix::fs::FileTypeExt, path::Path, process, time::Duration};
+
+use fakhrusy_com_backend::api::admin::accounts::{
+    change_role_handler, disable_user_handler, enable_user_handler, force_password_reset_handler,
+    get_user_handler, impersonate_user_handler, list_users_handler,
+};
+use fakhrusy_com_backend::api::admin::audit_events::list_audit_events_handler;
+use fakhrusy_com_backend::api::admin::invites::{
+    create_invite_handler, list_invites_handler, revoke_invite_handler,
+};
+use fakhrusy_com_backend::api::admin::unlock_user::unlock_user_handler;
+use fakhrusy_com_backend::api::auth::login::login_handler;
+use fakhrusy_com_backend::api::auth::logout::logout_handler;
+use fakhrusy_com_backend::api::auth::magic_link::{
+    request_magic_link_handler, verify_magic_link_handler,
+};
+use fakhrusy_com_backend::api::auth::oauth::{authorize_handler, callback_handler};
+use fakhrusy_com_backend::api::auth::password_reset::reset_password_handler;
+use fakhrusy_com_backend::api::auth::register::register_handler;
+use fakhrusy_com_backend::api::auth::webauthn::{login_finish_handler, login_start_handler};
+use fakhrusy_com_backend::api::ops::health::{healthz_handler, readyz_handler};
+use fakhrusy_com_backend::api::ops::metrics::metrics_handler;
+use fakhrusy_com_backend::api::profile::api_keys::{
+    create_api_key_handler, list_api_keys_handler, revoke_api_key_handler,
+};
+use fakhrusy_com_backend::api::profile::identities::{
+    link_identity_handler, list_identities_handler, unlink_identity_handler,
+};
+use fakhrusy_com_backend::api::profile::my_profile::my_profile_handler;
+use fakhrusy_com_backend::api::profile::sessions::{list_sessions_handler, revoke_session_handler};
+use fakhrusy_com_backend::api::profile::update_profile::update_profile_handler;
+use fakhrusy_com_backend::api::profile::webauthn::{
+    list_credentials_handler, register_finish_handler, register_start_handler,
+    revoke_credential_handler,
+};
+use fakhrusy_com_backend::api::users::public_profile::get_public_profile_handler;
+use fakhrusy_com_backend::config::{
+    Argon2Config, AuthCookieConfig, CorsConfig, ListenAddr, LogConfig, LoginThrottleConfig,
+    MagicLinkConfig, MailConfig, MigrationConfig, OAuthConfig, PasswordResetConfig, ProxyConfig,
+    RateLimitConfig, RegistrationConfig, SecurityHeadersConfig, ServerConfig, TlsConfig,
+    TracingConfig, WebauthnConfig,
+};
+use fakhrusy_com_backend::middleware::cors::Cors;
+use fakhrusy_com_backend::middleware::hsts::Hsts;
+use fakhrusy_com_backend::middleware::rate_limit::{RateLimit, RateLimitKey};
+use fakhrusy_com_backend::middleware::request_id::RequestIdentifier;
+use fakhrusy_com_backend::middleware::security_headers::SecurityHeaders;
+use fakhrusy_com_backend::{
+    config, mailer, metrics, migrations, model, rate_limit, telemetry, tls,
+};
+
+const USAGE: &str = "Usage: fakhrusy-com-backend [migrate up|down|status]
+
+Without a command the server is started.
+
+  migrate up      apply all pending migrations
+  migrate down    revert the latest applied migration
+  migrate status  list migrations and whether they are applied
+
+down and status read migrations/ in or above the working directory.";
+
+fn migration_status(conn: &PgConnection) -> Result<(), RunMigrationsError> {
+    let applied = migrations::applied_versions(conn)?;
+    let known = migrations::in_directory()?;
+
+    for (version, name) in known.iter() {
+        match applied.iter().find(|(applied, _)| applied == version) {
+            Some((_, run_on)) => println!("[x] {}  {}", name, run_on),
+            None => println!("[ ] {}", name),
+        }
+    }
+    for (version, run_on) in applied.iter() {
+        if !known.iter().any(|(known, _)| known == version) {
+            println!("[?] {}  {}  not in migrations/", version, run_on);
+        }
+    }
+
+    Ok(())
+}
+
+// Exit code of the `migrate` command
+fn migrate_command(database_url: &str, command: &str) -> i32 {
+    let conn = match PgConnection::establish(database_url) {
+        Ok(conn) => conn,
+        Err(err) => {
+            eprintln!("Failed to connect to the database: {}", err);
+            return 1;
+        }
+    };
+
+    let result = match command {
+        "up" => migrations::run_pending(&conn).map(|ran| {
+            for line in ran.iter() {
+                println!("{}", line);
+            }
+            if ran.is_empty() {
+                println!("No pending migrations");
+            }
+        }),
+        "down" => migrations::revert_latest(&conn).map(|reverted| match reverted {
+            Some(version) => println!("Reverted {}", version),
+            None => println!("No applied migrations"),
+        }),
+        "status" => migration_status(&conn),
+        _ => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("Migration failed: {}", err);
+            1
+        }
+    }
+}
+
+// A setting that doesn't parse stops the server with what is wrong
+fn config_or_exit<T>(config: Result<T, String>) -> T {
+    config.unwrap_or_else(|err| {
+        eprintln!("Invalid configuration: {}", err);
+        process::exit(2);
+    })
+}
 
-use crate::api::auth::login::login_handler;
-use crate::api::auth::register::register_handler;
-use crate::api::profile::my_profile::my_profile_handler;
+// A socket file left by an earlier run would make binding fail
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path),
+        _ => Ok(()),
+    }
+}
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenv().ok();
+    let tracer_provider = telemetry::init(
+        &config_or_exit(LogConfig::from_env()),
+        &config_or_exit(TracingConfig::from_env()),
+    );
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let migrate = match args.as_slice() {
+        [] => None,
+        [name, command] if name == "migrate" => Some(command.clone()),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
 
+    if let Some(command) = migrate {
+        let code = migrate_command(&database_url, &command);
+        telemetry::shutdown(tracer_provider);
+        process::exit(code);
+    }
+
+    // every setting is checked here, before migrations run or anything is
+    // bound, instead of failing on the first request that needs it
+    config_or_exit(config::check_jwt_secret());
+    let auth_cookie_config = config_or_exit(AuthCookieConfig::from_env());
+    let cors_config = config_or_exit(CorsConfig::from_env());
+    let oauth_config = config_or_exit(OAuthConfig::from_env());
+    let rate_limit_config = config_or_exit(RateLimitConfig::from_env());
+    let webauthn_config = config_or_exit(WebauthnConfig::from_env());
+    let security_headers_config = config_or_exit(SecurityHeadersConfig::from_env());
+    let proxy_config = config_or_exit(ProxyConfig::from_env());
+    let argon2_config = config_or_exit(Argon2Config::from_env());
+    let login_throttle_config = config_or_exit(LoginThrottleConfig::from_env());
+    let mail_config = config_or_exit(MailConfig::from_env());
+    let magic_link_config = config_or_exit(MagicLinkConfig::from_env());
+    let password_reset_config = config_or_exit(PasswordResetConfig::from_env());
+    let registration_config = config_or_exit(RegistrationConfig::from_env());
+    let server_config = config_or_exit(ServerConfig::from_env());
+    let max_payload_bytes = server_config.max_payload_bytes;
+    let max_json_bytes = server_config.max_json_bytes;
+    let shutdown_timeout = Duration::from_secs(server_config.shutdown_timeout_seconds);
+    let tls_config = config_or_exit(TlsConfig::from_env());
+    let hsts_header = tls_config.as_ref().and_then(TlsConfig::hsts_header);
+    let migration_config = config_or_exit(MigrationConfig::from_env());
+
+    if migration_config.run_on_startup {
+        let conn = PgConnection::establish(&database_url)
+            .unwrap_or_else(|err| panic!("Failed to connect to the database: {}", err));
+        match migrations::run_pending(&conn) {
+            Ok(ran) => {
+                for line in ran.iter() {
+                    tracing::info!("{}", line);
+                }
+            }
+            Err(err) => panic!("Failed to run migrations: {}", err),
+        }
+    }
+
     let manager = ConnectionManager::<PgConnection>::new(database_url);
     let pool: model::db::Pool = r2d2::Pool::builder()
+        .event_handler(Box::new(metrics::PoolEventHandler))
         .build(manager)
         .expect("Failed to create pool");
 
-    HttpServer::new(move || {
+    // shared by all workers
+    let rate_limit_store = rate_limit::store_from_config(&rate_limit_config);
+    let metrics_pool = pool.clone();
+
+    let mut server = HttpServer::new(move || {
         App::new()
             .data(pool.clone())
-            .wrap(actix_web::middleware::Logger::default())
-            .wrap(crate::middleware::auth::Authentication)
+            .data(auth_cookie_config.clone())
+            .data(oauth_config.clone())
+            .data(webauthn_config.clone())
+            .data(proxy_config.clone())
+            .data(argon2_config.clone())
+            .data(login_throttle_config.clone())
+            .data(mail_config.clone())
+            .data(magic_link_config.clone())
+            .data(password_reset_config.clone())
+            .data(registration_config.clone())
+            .wrap(SecurityHeaders::new(&security_headers_config.default))
+            .wrap(Cors::new(cors_config.clone()))
+            .wrap(Hsts::new(hsts_header.as_deref()))
+            .wrap(RequestIdentifier)
+            .app_data(web::PayloadConfig::new(max_payload_bytes))
             .app_data(
                 // Json extractor configuration for resources.
-                web::JsonConfig::default().error_handler(|err, _req| {
-                    let e = format!("{:?}", err);
-                    error::InternalError::from_response(err, HttpResponse::BadRequest().body(e))
-                        .into()
-                }),
+                web::JsonConfig::default()
+                    .limit(max_json_bytes)
+                    .error_handler(|err, _req| {
+                        let e = format!("{:?}", err);
+                        let mut response = match err {
+                            JsonPayloadError::Overflow => HttpResponse::PayloadTooLarge(),
+                            _ => HttpResponse::BadRequest(),
+                        };
+                        error::InternalError::from_response(err, response.body(e)).into()
+                    }),
             )
+            .service(web::resource("/healthz").route(web::get().to(healthz_handler)))
+            .service(web::resource("/readyz").route(web::get().to(readyz_handler)))
             .service(
+                // Only the API is authenticated, operational endpoints
+                // outside it are not
                 web::scope("/v1")
+                    .wrap(RateLimit::new(
+                        "api",
+                        rate_limit_store.clone(),
+                        rate_limit_config.api,
+                        RateLimitKey::User,
+                    ))
+                    .wrap(fakhrusy_com_backend::middleware::auth::Authentication)
                     .service(
                         web::scope("/auth")
+                            .wrap(SecurityHeaders::new(&security_headers_config.auth))
+                            .service(
+                                web::resource("/register")
+                                    .wrap(RateLimit::new(
+                                        "register_total",
+                                        rate_limit_store.clone(),
+                                        rate_limit_config.register_total,
+                                        RateLimitKey::Route,
+                                    ))
+                                    .wrap(RateLimit::new(
+                                        "register",
+                                        rate_limit_store.clone(),
+                                        rate_limit_config.register,
+                                        RateLimitKey::Ip,
+                                    ))
+                                    .route(web::post().to(register_handler)),
+                            )
+                            .service(
+                                web::resource("/login")
+                                    .wrap(RateLimit::new(
+                                        "login",
+                                        rate_limit_store.clone(),
+                                        rate_limit_config.login,
+                                        RateLimitKey::Ip,
+                                    ))
+                                    .route(web::post().to(login_handler)),
+                            )
+                            .service(web::resource("/logout").route(web::post().to(logout_handler)))
+                            .service(
+                                web::resource("/magic-link")
+                                    .wrap(RateLimit::new(
+                                        "magic_link",
+                                        rate_limit_store.clone(),
+                                        rate_limit_config.magic_link,
+                                        RateLimitKey::Ip,
+                                    ))
+                                    .route(web::post().to(request_magic_link_handler)),
+                            )
                             .service(
-                                web::resource("/register").route(web::post().to(register_handler)),
+                                web::resource("/magic-link/verify")
+                                    .route(web::post().to(verify_magic_link_handler)),
                             )
-                            .service(web::resource("/login").route(web::post().to(login_handler))),
+                            .service(
+                                web::resource("/oauth/{provider}")
+                                    .route(web::get().to(authorize_handler)),
+                            )
+                            .service(
+                                web::resource("/oauth/{provider}/callback")
+                                    .route(web::post().to(callback_handler)),
+                            )
+                            .service(
+                                web::resource("/password/reset")
+                                    .wrap(RateLimit::new(
+                                        "password_reset",
+                                        rate_limit_store.clone(),
+                                        rate_limit_config.password_reset,
+                                        RateLimitKey::Ip,
+                                    ))
+                                    .route(web::post().to(reset_password_handler)),
+                            )
+                            .service(
+                                web::resource("/webauthn/login/start")
+                                    .route(web::post().to(login_start_handler)),
+                            )
+                            .service(
+                                web::resource("/webauthn/login/finish")
+                                    .wrap(RateLimit::new(
+                                        "passkey_login",
+                                        rate_limit_store.clone(),
+                                        rate_limit_config.passkey_login,
+                                        RateLimitKey::Ip,
+                                    ))
+                                    .route(web::post().to(login_finish_handler)),
+                            ),
+                    )
+                    .service(
+                        web::resource("/profile")
+                            .route(web::get().to(my_profile_handler))
+                            .route(web::put().to(update_profile_handler)),
+                    )
+                    .service(
+                        web::resource("/profile/identities")
+                            .route(web::get().to(list_identities_handler)),
+                    )
+                    .service(
+                        // {provider} to link, {id} of a linked identity to unlink
+                        web::resource("/profile/identities/{key}")
+                            .route(web::post().to(link_identity_handler))
+                            .route(web::delete().to(unlink_identity_handler)),
+                    )
+                    .service(
+                        // hands out a new key, same as the auth endpoints
+                        web::resource("/profile/api-keys")
+                            .wrap(SecurityHeaders::new(&security_headers_config.auth))
+                            .route(web::get().to(list_api_keys_handler))
+                            .route(web::post().to(create_api_key_handler)),
+                    )
+                    .service(
+                        web::resource("/profile/api-keys/{id}")
+                            .route(web::delete().to(revoke_api_key_handler)),
+                    )
+                    .service(
+                        web::resource("/profile/sessions")
+                            .route(web::get().to(list_sessions_handler)),
+                    )
+                    .service(
+                        web::resource("/profile/sessions/{id}")
+                            .route(web::delete().to(revoke_session_handler)),
+                    )
+                    .service(
+                        web::resource("/profile/webauthn/register/start")
+                            .route(web::post().to(register_start_handler)),
                     )
-                    .service(web::resource("/profile").route(web::get().to(my_profile_handler))),
+                    .service(
+                        web::resource("/profile/webauthn/register/finish")
+                            .route(web::post().to(register_finish_handler)),
+                    )
+                    .service(
+                        web::resource("/profile/webauthn/credentials")
+                            .route(web::get().to(list_credentials_handler)),
+                    )
+                    .service(
+                        web::resource("/profile/webauthn/credentials/{id}")
+                            .route(web::delete().to(revoke_credential_handler)),
+                    )
+                    .service(
+                        web::resource("/users/{username}")
+                            .route(web::get().to(get_public_profile_handler)),
+                    )
+                    .service(
+                        web::scope("/admin")
+                            .service(
+                                web::resource("/users").route(web::get().to(list_users_handler)),
+                            )
+                            .service(
+                                web::resource("/users/{id}").route(web::get().to(get_user_handler)),
+                            )
+                            .service(
+                                web::resource("/users/{id}/role")
+                                    .route(web::put().to(change_role_handler)),
+                            )
+                            .service(
+                                web::resource("/users/{id}/disable")
+                                    .route(web::post().to(disable_user_handler)),
+                            )
+                            .service(
+                                web::resource("/users/{id}/enable")
+                                    .route(web::post().to(enable_user_handler)),
+                            )
+                            .service(
+                                web::resource("/users/{id}/password-reset")
+                                    .route(web::post().to(force_password_reset_handler)),
+                            )
+                            .service(
+                                web::resource("/users/{id}/impersonate")
+                                    .wrap(SecurityHeaders::new(&security_headers_config.auth))
+                                    .route(web::post().to(impersonate_user_handler)),
+                            )
+                            .service(
+                                web::resource("/users/{id}/unlock")
+                                    .route(web::post().to(unlock_user_handler)),
+                            )
+                            .service(
+                                web::resource("/invites")
+                                    .route(web::get().to(list_invites_handler))
+                                    .route(web::post().to(create_invite_handler)),
+                            )
+                            .service(
+                                web::resource("/invites/{id}")
+                                    .route(web::delete().to(revoke_invite_handler)),
+                            )
+                            .service(
+                                web::resource("/audit-events")
+                                    .route(web::get().to(list_audit_events_handler)),
+                            ),
+                    ),
             )
     })
-    .bind("127.0.0.1:8080")?
-    .run()
-    .await
+    .keep_alive(server_config.keep_alive_seconds)
+    .client_timeout(server_config.client_timeout_ms)
+    .client_shutdown(server_config.client_shutdown_ms)
+    // SIGTERM stops accepting and waits this long for requests in flight,
+    // emails sent in the background get as long again once they are done
+    .shutdown_timeout(server_config.shutdown_timeout_seconds)
+    // applies to the listeners bound below
+    .backlog(server_config.backlog);
+
+    if server_config.workers > 0 {
+        server = server.workers(server_config.workers);
+    }
+    for addr in server_config.listen.iter() {
+        server = match addr {
+            ListenAddr::Tcp(addr) => server.bind(addr)?,
+            ListenAddr::Unix(path) => {
+                remove_stale_socket(path)?;
+                server.bind_uds(path)?
+            }
+        };
+    }
+
+    let mut metrics_server = None;
+    if !server_config.metrics_listen.is_empty() {
+        let mut metrics = HttpServer::new(move || {
+            App::new()
+                .data(metrics_pool.clone())
+                .service(web::resource("/metrics").route(web::get().to(metrics_handler)))
+        })
+        .workers(1)
+        .shutdown_timeout(server_config.shutdown_timeout_seconds);
+        for addr in server_config.metrics_listen.iter() {
+            metrics = match addr {
+                ListenAddr::Tcp(addr) => metrics.bind(addr)?,
+                ListenAddr::Unix(path) => {
+                    remove_stale_socket(path)?;
+                    metrics.bind_uds(path)?
+                }
+            };
+        }
+        metrics_server = Some(metrics.run());
+    }
+
+    let mut redirect_server = None;
+    if let Some(tls_config) = &tls_config {
+        let rustls_config = tls::server_config(tls_config)
+            .unwrap_or_else(|err| panic!("Failed to load TLS certificate: {}", err));
+        for addr in tls_config.listen.iter() {
+            server = server.bind_rustls(addr, rustls_config.clone())?;
+        }
+
+        if !tls_config.redirect_listen.is_empty() {
+            let port = tls_config.public_port();
+            let mut redirect = HttpServer::new(move || {
+                App::new()
+                    .data(port)
+                    .default_service(web::to(tls::redirect_handler))
+            })
+            .workers(1)
+            .shutdown_timeout(server_config.shutdown_timeout_seconds);
+            for addr in tls_config.redirect_listen.iter() {
+                redirect = redirect.bind(addr)?;
+            }
+            redirect_server = Some(redirect.run());
+        }
+    }
+
+    // all servers stop on the same signal
+    let servers = iter::once(server.run())
+        .chain(redirect_server)
+        .chain(metrics_server);
+    let result = future::try_join_all(servers).await.map(|_| ());
+    tracing::info!("server stopped");
+
+    let unsent = mailer::wait_for_background_emails(shutdown_timeout);
+    if unsent > 0 {
+        tracing::warn!(unsent, "stopped before all emails were sent");
+    }
+
+    // the last traces are sent once no request is left to produce them
+    telemetry::shutdown(tracer_provider);
+    result
 }
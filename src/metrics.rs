@@ -0,0 +1,191 @@
+// Prometheus metrics, served on `/metrics` of METRICS_LISTEN. They live in
+// one process wide registry so code without access to app data, like
+// password hashing or the pool's event handler, can record them too.
+
+use std::{
+    sync::LazyLock,
+    time::{Duration, Instant},
+};
+
+use diesel::r2d2::{
+    event::{CheckoutEvent, TimeoutEvent},
+    HandleEvent,
+};
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder,
+};
+
+use crate::model::db::Pool;
+
+struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    auth_tokens: IntCounterVec,
+    logins: IntCounterVec,
+    password_hash_duration: HistogramVec,
+    db_pool_connections: IntGauge,
+    db_pool_idle_connections: IntGauge,
+    db_pool_wait: Histogram,
+    db_pool_timeouts: IntCounter,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+impl Metrics {
+    fn new() -> Metrics {
+        let registry = Registry::new();
+
+        let metrics = Metrics {
+            http_requests: IntCounterVec::new(
+                Opts::new("http_requests_total", "HTTP requests handled"),
+                &["method", "route", "status"],
+            )
+            .unwrap(),
+            http_request_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "http_request_duration_seconds",
+                    "Time from receiving a request to sending the response headers",
+                ),
+                &["method", "route"],
+            )
+            .unwrap(),
+            auth_tokens: IntCounterVec::new(
+                Opts::new(
+                    "auth_tokens_total",
+                    "Credentials checked by the authentication middleware",
+                ),
+                &["kind", "outcome"],
+            )
+            .unwrap(),
+            logins: IntCounterVec::new(
+                Opts::new("logins_total", "Login attempts by method"),
+                &["method", "result"],
+            )
+            .unwrap(),
+            password_hash_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "password_hash_duration_seconds",
+                    "Time spent hashing and verifying passwords with argon2",
+                )
+                .buckets(vec![0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0]),
+                &["operation"],
+            )
+            .unwrap(),
+            db_pool_connections: IntGauge::new(
+                "db_pool_connections",
+                "Connections managed by the database pool",
+            )
+            .unwrap(),
+            db_pool_idle_connections: IntGauge::new(
+                "db_pool_idle_connections",
+                "Idle connections in the database pool",
+            )
+            .unwrap(),
+            db_pool_wait: Histogram::with_opts(HistogramOpts::new(
+                "db_pool_wait_seconds",
+                "Time spent waiting to check out a database connection",
+            ))
+            .unwrap(),
+            db_pool_timeouts: IntCounter::new(
+                "db_pool_timeouts_total",
+                "Connection checkouts that timed out",
+            )
+            .unwrap(),
+            registry,
+        };
+
+        let collectors: Vec<Box<dyn prometheus::core::Collector>> = vec![
+            Box::new(metrics.http_requests.clone()),
+            Box::new(metrics.http_request_duration.clone()),
+            Box::new(metrics.auth_tokens.clone()),
+            Box::new(metrics.logins.clone()),
+            Box::new(metrics.password_hash_duration.clone()),
+            Box::new(metrics.db_pool_connections.clone()),
+            Box::new(metrics.db_pool_idle_connections.clone()),
+            Box::new(metrics.db_pool_wait.clone()),
+            Box::new(metrics.db_pool_timeouts.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector).unwrap();
+        }
+
+        metrics
+    }
+}
+
+// `route` is the matched pattern, e.g. "/v1/users/{username}", so the number
+// of series doesn't grow with ids in paths
+pub fn record_http_request(method: &str, route: &str, status: u16, duration: Duration) {
+    METRICS
+        .http_requests
+        .with_label_values(&[method, route, &status.to_string()])
+        .inc();
+    METRICS
+        .http_request_duration
+        .with_label_values(&[method, route])
+        .observe(duration.as_secs_f64());
+}
+
+// `kind` is "session" or "api_key", `outcome` one of "valid", "invalid"
+// and "expired"
+pub fn record_auth_token(kind: &str, outcome: &str) {
+    METRICS
+        .auth_tokens
+        .with_label_values(&[kind, outcome])
+        .inc();
+}
+
+// `method` is "password", "password+passkey", "passkey", "magic_link" or
+// "oauth", `result` is "success", "failure", or "second_factor" when a
+// passkey is still required
+pub fn record_login(method: &str, result: &str) {
+    METRICS.logins.with_label_values(&[method, result]).inc();
+}
+
+// Runs an argon2 operation, "hash" or "verify", and records how long it took
+pub fn time_password_hash<T>(operation: &str, f: impl FnOnce() -> T) -> T {
+    let started_at = Instant::now();
+    let result = f();
+    METRICS
+        .password_hash_duration
+        .with_label_values(&[operation])
+        .observe(started_at.elapsed().as_secs_f64());
+
+    result
+}
+
+// Installed on the pool with `event_handler`, r2d2 keeps no wait times itself
+#[derive(Debug)]
+pub struct PoolEventHandler;
+
+impl HandleEvent for PoolEventHandler {
+    fn handle_checkout(&self, event: CheckoutEvent) {
+        METRICS.db_pool_wait.observe(event.duration().as_secs_f64());
+    }
+
+    fn handle_timeout(&self, event: TimeoutEvent) {
+        METRICS.db_pool_timeouts.inc();
+        METRICS.db_pool_wait.observe(event.timeout().as_secs_f64());
+    }
+}
+
+// The text exposition format, with the pool gauges read at scrape time
+pub fn render(pool: &Pool) -> (String, Vec<u8>) {
+    let state = pool.state();
+    METRICS
+        .db_pool_connections
+        .set(i64::from(state.connections));
+    METRICS
+        .db_pool_idle_connections
+        .set(i64::from(state.idle_connections));
+
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    encoder
+        .encode(&METRICS.registry.gather(), &mut buffer)
+        .expect("Failed to encode metrics");
+
+    (encoder.format_type().to_string(), buffer)
+}
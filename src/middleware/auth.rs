@@ -2,9 +2,18 @@
 // copied from ^ with some changes
 
 use crate::{
-    constants,
-    model::{auth::AuthMiddlewareData, db::Pool, errors::ServiceError, response::ResponseBody},
-    utils::decode_jwt,
+    api_key::find_api_key,
+    config::AuthCookieConfig,
+    constants, metrics,
+    model::{
+        auth::AuthMiddlewareData,
+        db::Pool,
+        errors::{GlobalServiceError, ServiceError},
+        response::ResponseBody,
+    },
+    session::touch_session,
+    telemetry,
+    utils::{constant_time_eq, decode_jwt},
 };
 use actix_service::{Service, Transform};
 use actix_web::{
@@ -17,16 +26,20 @@ use futures::{
     future::{ok, Ready},
     Future,
 };
+use jsonwebtoken::errors::ErrorKind;
 use std::{
+    cell::RefCell,
     pin::Pin,
+    rc::Rc,
     task::{Context, Poll},
 };
+use tracing::{field, Instrument, Span};
 
 pub struct Authentication;
 
 impl<S, B> Transform<S> for Authentication
 where
-    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -38,17 +51,103 @@ where
     type Future = Ready<Result<Self::Transform, Self::InitError>>;
 
     fn new_transform(&self, service: S) -> Self::Future {
-        ok(AuthenticationMiddleware { service })
+        ok(AuthenticationMiddleware {
+            service: Rc::new(RefCell::new(service)),
+        })
     }
 }
 
 pub struct AuthenticationMiddleware<S> {
-    service: S,
+    service: Rc<RefCell<S>>,
+}
+
+fn bearer_token(req: &ServiceRequest) -> Option<String> {
+    // Parsing authorization header
+    let auth_str = req.headers().get(constants::AUTHORIZATION)?.to_str().ok()?;
+    if auth_str.starts_with("bearer") || auth_str.starts_with("Bearer") {
+        // Parsing token
+        Some(auth_str[6..auth_str.len()].trim().to_string())
+    } else {
+        None
+    }
+}
+
+fn api_key(req: &ServiceRequest) -> Option<String> {
+    let auth_str = req.headers().get(constants::AUTHORIZATION)?.to_str().ok()?;
+    auth_str
+        .strip_prefix("ApiKey ")
+        .map(|key| key.trim().to_string())
+}
+
+// An API key only gets through with the scope matching the request
+fn required_scope(req: &ServiceRequest) -> &'static str {
+    if req.path().starts_with("/v1/admin") {
+        constants::API_KEY_SCOPE_ADMIN
+    } else if is_safe_method(req.method()) {
+        constants::API_KEY_SCOPE_READ
+    } else {
+        constants::API_KEY_SCOPE_WRITE
+    }
+}
+
+fn is_safe_method(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+// Double submit: the header has to match the CSRF cookie set at login
+fn csrf_token_valid(req: &ServiceRequest, cookie_config: &AuthCookieConfig) -> bool {
+    let cookie = match req.cookie(&cookie_config.csrf_name) {
+        Some(cookie) => cookie,
+        None => return false,
+    };
+    let header = match req.headers().get(constants::CSRF_TOKEN_HEADER) {
+        Some(header) => header,
+        None => return false,
+    };
+
+    !cookie.value().is_empty() && constant_time_eq(cookie.value().as_bytes(), header.as_bytes())
+}
+
+// One span per request recording how it was authenticated, the downstream
+// handler is not run inside it
+fn authenticate_span() -> Span {
+    tracing::info_span!(
+        "authenticate",
+        auth.method = field::Empty,
+        auth.decision = field::Empty,
+    )
+}
+
+fn record_decision(span: &Span, method: &str, decision: &str) {
+    span.record("auth.method", method);
+    span.record("auth.decision", decision);
+}
+
+fn error_response<B>(
+    req: ServiceRequest,
+    mut response: actix_web::dev::HttpResponseBuilder,
+    message: &str,
+    error: ServiceError,
+) -> ServiceResponse<B> {
+    req.into_response(
+        response
+            .json(ResponseBody::error(message, Some(error)))
+            .into_body(),
+    )
+}
+
+fn unauthorized<B>(req: ServiceRequest) -> ServiceResponse<B> {
+    error_response(
+        req,
+        HttpResponse::Unauthorized(),
+        constants::MESSAGE_INVALID_TOKEN,
+        ServiceError::InvalidToken,
+    )
 }
 
 impl<S, B> Service for AuthenticationMiddleware<S>
 where
-    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -58,7 +157,7 @@ where
     type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.service.poll_ready(cx)
+        self.service.borrow_mut().poll_ready(cx)
     }
 
     fn call(&mut self, mut req: ServiceRequest) -> Self::Future {
@@ -70,35 +169,91 @@ where
             HeaderValue::from_static("true"),
         );
 
-        if Method::OPTIONS == *req.method() {
-            authenticate_pass = true;
-        } else {
-            for ignore_route in constants::AUTH_ROUTES.iter() {
-                // Bypass some account routes
-                if req.path().starts_with(ignore_route) {
-                    authenticate_pass = true;
-                    break;
-                }
+        // CORS preflight requests are answered by `middleware::cors::Cors`
+        // before they get here
+        for ignore_route in constants::AUTH_ROUTES.iter() {
+            // Bypass some account routes
+            if req.path().starts_with(ignore_route) {
+                authenticate_pass = true;
+                break;
             }
+        }
+
+        let span = authenticate_span();
+        let mut csrf_rejected = false;
+        let mut token = None;
+
+        if !authenticate_pass {
+            if let Some(pool) = req.app_data::<Data<Pool>>() {
+                if let Some(key) = api_key(&req) {
+                    let pool = pool.clone();
+                    let service = self.service.clone();
+                    let scope = required_scope(&req);
+
+                    return Box::pin(async move {
+                        let found = telemetry::block(move || {
+                            let conn = pool
+                                .get()
+                                .map_err(|_err| GlobalServiceError::InternalServerError)?;
+                            find_api_key(&conn, &key)
+                                .map_err(|_err| GlobalServiceError::InternalServerError)
+                        })
+                        .instrument(span.clone())
+                        .await
+                        .map_err(|_err| {
+                            record_decision(&span, "api_key", "error");
+                            GlobalServiceError::InternalServerError
+                        })?;
+
+                        let decision = match found {
+                            Some((ref api_key, _)) if api_key.scopes.iter().any(|s| s == scope) => {
+                                "accepted"
+                            }
+                            Some(_) => "insufficient_scope",
+                            None => "rejected",
+                        };
+                        record_decision(&span, "api_key", decision);
+                        drop(span);
+                        metrics::record_auth_token(
+                            "api_key",
+                            if found.is_some() { "valid" } else { "invalid" },
+                        );
 
-            if !authenticate_pass {
-                if let Some(_pool) = req.app_data::<Data<Pool>>() {
-                    // Connecting to database
-                    if let Some(auth_header) = req.headers().get(constants::AUTHORIZATION) {
-                        // Parsing authorization header
-                        if let Ok(auth_str) = auth_header.to_str() {
-                            if auth_str.starts_with("bearer") || auth_str.starts_with("Bearer") {
-                                // Parsing token
-                                let token = auth_str[6..auth_str.len()].trim();
-                                if let Ok(token_data) = decode_jwt(token.to_string()) {
-                                    let email = token_data.claims.email;
-                                    req.extensions_mut()
-                                        .insert::<AuthMiddlewareData>(AuthMiddlewareData { email });
-                                    authenticate_pass = true;
-                                } else {
-                                    // Invalid token
-                                }
+                        match found {
+                            Some((api_key, email)) if api_key.scopes.iter().any(|s| s == scope) => {
+                                req.extensions_mut().insert::<AuthMiddlewareData>(
+                                    AuthMiddlewareData {
+                                        email,
+                                        api_key_id: Some(api_key.id),
+                                        session_id: None,
+                                        impersonator_id: None,
+                                    },
+                                );
+                                let fut = service.borrow_mut().call(req);
+                                fut.await
                             }
+                            Some(_) => Ok(error_response(
+                                req,
+                                HttpResponse::Forbidden(),
+                                constants::MESSAGE_INSUFFICIENT_SCOPE,
+                                ServiceError::InsufficientScope,
+                            )),
+                            None => Ok(unauthorized(req)),
+                        }
+                    });
+                }
+
+                if let Some(bearer) = bearer_token(&req) {
+                    token = Some(bearer);
+                } else if let Some(cookie_config) = req.app_data::<Data<AuthCookieConfig>>() {
+                    // Cookie session, the browser sends it on its own so
+                    // state-changing requests must prove they come from our
+                    // frontend
+                    if let Some(cookie) = req.cookie(&cookie_config.name) {
+                        if is_safe_method(req.method()) || csrf_token_valid(&req, cookie_config) {
+                            token = Some(cookie.value().to_string());
+                        } else {
+                            csrf_rejected = true;
                         }
                     }
                 }
@@ -106,23 +261,154 @@ where
         }
 
         if authenticate_pass {
-            let fut = self.service.call(req);
+            record_decision(&span, "none", "bypass");
+            let fut = self.service.borrow_mut().call(req);
             Box::pin(async move {
                 let res = fut.await?;
                 Ok(res)
             })
-        } else {
+        } else if let (Some(token), Some(pool)) = (token, req.app_data::<Data<Pool>>().cloned()) {
+            let service = self.service.clone();
+
             Box::pin(async move {
-                Ok(req.into_response(
-                    HttpResponse::Unauthorized()
-                        .json(ResponseBody::<()>::new(
-                            constants::MESSAGE_INVALID_TOKEN,
-                            None,
-                            Some(ServiceError::InvalidToken),
-                        ))
-                        .into_body(),
+                let claims = match decode_jwt(token) {
+                    Ok(token_data) => token_data.claims,
+                    // Invalid token
+                    Err(err) => {
+                        record_decision(&span, "session", "rejected");
+                        metrics::record_auth_token(
+                            "session",
+                            match err.kind() {
+                                ErrorKind::ExpiredSignature => "expired",
+                                _ => "invalid",
+                            },
+                        );
+                        return Ok(unauthorized(req));
+                    }
+                };
+
+                // The token is only as good as its session
+                let session_id = claims.sid;
+                let active = telemetry::block(move || {
+                    let conn = pool
+                        .get()
+                        .map_err(|_err| GlobalServiceError::InternalServerError)?;
+                    touch_session(&conn, session_id)
+                        .map_err(|_err| GlobalServiceError::InternalServerError)
+                })
+                .instrument(span.clone())
+                .await
+                .map_err(|_err| {
+                    record_decision(&span, "session", "error");
+                    GlobalServiceError::InternalServerError
+                })?;
+                if !active {
+                    record_decision(&span, "session", "session_ended");
+                    // revoked or expired server side, the token itself is fine
+                    metrics::record_auth_token("session", "invalid");
+                    return Ok(unauthorized(req));
+                }
+                record_decision(&span, "session", "accepted");
+                drop(span);
+                metrics::record_auth_token("session", "valid");
+
+                req.extensions_mut()
+                    .insert::<AuthMiddlewareData>(AuthMiddlewareData {
+                        email: claims.email,
+                        api_key_id: None,
+                        session_id: Some(session_id),
+                        impersonator_id: claims.imp,
+                    });
+                let fut = service.borrow_mut().call(req);
+                fut.await
+            })
+        } else if csrf_rejected {
+            record_decision(&span, "session", "csrf_rejected");
+            Box::pin(async move {
+                Ok(error_response(
+                    req,
+                    HttpResponse::Forbidden(),
+                    constants::MESSAGE_INVALID_CSRF_TOKEN,
+                    ServiceError::InvalidCsrfToken,
                 ))
             })
+        } else {
+            record_decision(&span, "none", "rejected");
+            Box::pin(async move { Ok(unauthorized(req)) })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{cookie::Cookie, test, web, App};
+    use diesel::r2d2::{self, ConnectionManager};
+
+    // Requests failing the CSRF check are turned away before the database
+    // is needed, so the pool never connects
+    fn pool() -> Pool {
+        r2d2::Pool::builder().build_unchecked(ConnectionManager::new("postgres://localhost/unused"))
+    }
+
+    fn cookie_config() -> AuthCookieConfig {
+        AuthCookieConfig {
+            name: "auth_token".to_string(),
+            csrf_name: "csrf_token".to_string(),
+            domain: None,
+            secure: true,
+            same_site: "Strict".to_string(),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn rejects_cookie_sessions_without_a_matching_csrf_token() {
+        let mut app = test::init_service(
+            App::new()
+                .data(pool())
+                .data(cookie_config())
+                .wrap(Authentication)
+                .default_service(web::route().to(HttpResponse::Ok)),
+        )
+        .await;
+        let request = |method: Method, csrf_cookie: Option<&str>, csrf_header: Option<&str>| {
+            let mut req = test::TestRequest::with_uri("/v1/profile")
+                .method(method)
+                .cookie(Cookie::new("auth_token", "session.jwt.token"));
+            if let Some(csrf_cookie) = csrf_cookie {
+                req = req.cookie(Cookie::new("csrf_token", csrf_cookie.to_string()));
+            }
+            if let Some(csrf_header) = csrf_header {
+                req = req.header(constants::CSRF_TOKEN_HEADER, csrf_header);
+            }
+            req.to_request()
+        };
+
+        for req in [
+            // no header
+            request(Method::POST, Some("abc123"), None),
+            request(Method::DELETE, Some("abc123"), None),
+            // no cookie to compare with
+            request(Method::PUT, None, Some("abc123")),
+            request(Method::POST, Some("abc123"), Some("abc124")),
+            request(Method::PATCH, Some("abc123"), Some("ABC123")),
+            // an empty token matches an empty header, it still isn't one
+            request(Method::POST, Some(""), Some("")),
+        ] {
+            let res = test::call_service(&mut app, req).await;
+            assert_eq!(res.status(), 403);
+            let body: ResponseBody<()> = test::read_body_json(res).await;
+            assert_eq!(body.message, constants::MESSAGE_INVALID_CSRF_TOKEN);
+        }
+    }
+
+    #[test]
+    fn needs_the_csrf_token_only_for_unsafe_methods() {
+        for method in [Method::GET, Method::HEAD, Method::OPTIONS] {
+            assert!(is_safe_method(&method));
+        }
+        for method in [Method::POST, Method::PUT, Method::PATCH, Method::DELETE] {
+            assert!(!is_safe_method(&method));
         }
     }
 }
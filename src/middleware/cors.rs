@@ -0,0 +1,420 @@
+use crate::config::CorsConfig;
+use actix_service::{Service, Transform};
+use actix_web::{
+    dev::{ServiceRequest, ServiceResponse},
+    error::InternalError,
+    http::{header, HeaderMap, HeaderValue, Method},
+    Error, HttpResponse,
+};
+use futures::{
+    future::{ok, Either, Ready},
+    Future,
+};
+use std::{
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll},
+};
+
+// Must wrap authentication and rate limiting so preflight requests are
+// answered before them, and every response gets the headers. Only the request
+// id middleware sits outside of it.
+pub struct Cors {
+    config: Rc<CorsConfig>,
+}
+
+impl Cors {
+    pub fn new(config: CorsConfig) -> Cors {
+        Cors {
+            config: Rc::new(config),
+        }
+    }
+}
+
+impl<S, B> Transform<S> for Cors
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = CorsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(CorsMiddleware {
+            service,
+            config: self.config.clone(),
+        })
+    }
+}
+
+pub struct CorsMiddleware<S> {
+    service: S,
+    config: Rc<CorsConfig>,
+}
+
+fn origin_matches(allowed: &str, origin: &str) -> bool {
+    if allowed == "*" || allowed.eq_ignore_ascii_case(origin) {
+        return true;
+    }
+
+    // "https://*.example.com" matches "https://blog.example.com" but not
+    // "https://example.com"
+    match allowed.split_once("://*.") {
+        Some((scheme, domain)) => {
+            let origin = origin.to_ascii_lowercase();
+            match origin.strip_prefix(&format!("{}://", scheme.to_ascii_lowercase())) {
+                Some(host) => {
+                    let suffix = format!(".{}", domain.to_ascii_lowercase());
+                    host.len() > suffix.len() && host.ends_with(&suffix)
+                }
+                None => false,
+            }
+        }
+        None => false,
+    }
+}
+
+fn contains_ignore_case(list: &[String], item: &str) -> bool {
+    list.iter().any(|entry| entry.eq_ignore_ascii_case(item))
+}
+
+impl CorsConfig {
+    fn allowed_origin<'a>(&self, headers: &'a HeaderMap) -> Option<&'a HeaderValue> {
+        let origin = headers.get(header::ORIGIN)?;
+        let origin_str = origin.to_str().ok()?;
+
+        if self
+            .allowed_origins
+            .iter()
+            .any(|allowed| origin_matches(allowed, origin_str))
+        {
+            Some(origin)
+        } else {
+            None
+        }
+    }
+
+    fn preflight_allowed(&self, headers: &HeaderMap) -> bool {
+        let method = headers
+            .get(header::ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|value| value.to_str().ok());
+        let method_allowed = match method {
+            Some(method) => contains_ignore_case(&self.allowed_methods, method),
+            None => false,
+        };
+
+        let headers_allowed = match headers.get(header::ACCESS_CONTROL_REQUEST_HEADERS) {
+            None => true,
+            Some(value) => match value.to_str() {
+                Ok(value) => value
+                    .split(',')
+                    .map(|name| name.trim())
+                    .filter(|name| !name.is_empty())
+                    .all(|name| contains_ignore_case(&self.allowed_headers, name)),
+                Err(_) => false,
+            },
+        };
+
+        method_allowed && headers_allowed
+    }
+
+    fn insert_common_headers(&self, headers: &mut HeaderMap, origin: HeaderValue) {
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+        if self.allow_credentials {
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+    }
+
+    // Whether a response gets the headers depends on the origin, including
+    // responses without them, so caches must key on it
+    fn insert_vary(&self, headers: &mut HeaderMap) {
+        if !self.allowed_origins.is_empty() {
+            headers.append(header::VARY, HeaderValue::from_static("Origin"));
+        }
+    }
+}
+
+fn join(list: &[String]) -> HeaderValue {
+    HeaderValue::from_str(&list.join(", ")).unwrap_or_else(|_| HeaderValue::from_static(""))
+}
+
+impl<S, B> Service for CorsMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Either<
+        Ready<Result<Self::Response, Self::Error>>,
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>,
+    >;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let config = self.config.clone();
+        let origin = config.allowed_origin(req.headers()).cloned();
+
+        let is_preflight = Method::OPTIONS == *req.method()
+            && req
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+
+        if is_preflight {
+            let mut response = match origin {
+                Some(origin) if config.preflight_allowed(req.headers()) => {
+                    let mut response = HttpResponse::NoContent().finish();
+                    let headers = response.headers_mut();
+                    config.insert_common_headers(headers, origin);
+                    headers.insert(
+                        header::ACCESS_CONTROL_ALLOW_METHODS,
+                        join(&config.allowed_methods),
+                    );
+                    headers.insert(
+                        header::ACCESS_CONTROL_ALLOW_HEADERS,
+                        join(&config.allowed_headers),
+                    );
+                    headers.insert(
+                        header::ACCESS_CONTROL_MAX_AGE,
+                        HeaderValue::from(config.max_age_seconds),
+                    );
+                    response
+                }
+                // without the headers the browser blocks the actual request
+                _ => HttpResponse::Forbidden().finish(),
+            };
+            config.insert_vary(response.headers_mut());
+
+            return Either::Left(ok(req.into_response(response.into_body())));
+        }
+
+        let fut = self.service.call(req);
+        Either::Right(Box::pin(async move {
+            let add_headers = |headers: &mut HeaderMap| {
+                config.insert_vary(headers);
+                if let Some(origin) = origin {
+                    config.insert_common_headers(headers, origin);
+                    if !config.exposed_headers.is_empty() {
+                        headers.insert(
+                            header::ACCESS_CONTROL_EXPOSE_HEADERS,
+                            join(&config.exposed_headers),
+                        );
+                    }
+                }
+            };
+
+            match fut.await {
+                Ok(mut res) => {
+                    add_headers(res.headers_mut());
+                    Ok(res)
+                }
+                // without the headers the browser hides the error from the
+                // page that made the request
+                Err(err) => {
+                    let mut response = err.as_response_error().error_response();
+                    add_headers(response.headers_mut());
+                    Err(InternalError::from_response(err, response).into())
+                }
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App};
+
+    #[test]
+    fn matches_origins() {
+        assert!(origin_matches("*", "https://anything.test"));
+        assert!(origin_matches("https://example.com", "https://example.com"));
+        assert!(origin_matches("https://example.com", "HTTPS://Example.com"));
+        assert!(!origin_matches("https://example.com", "http://example.com"));
+        assert!(!origin_matches(
+            "https://example.com",
+            "https://example.com.evil.test"
+        ));
+        assert!(!origin_matches(
+            "https://example.com",
+            "https://example.com:8443"
+        ));
+    }
+
+    #[test]
+    fn matches_wildcard_subdomains() {
+        let allowed = "https://*.example.com";
+
+        assert!(origin_matches(allowed, "https://blog.example.com"));
+        assert!(origin_matches(allowed, "https://a.b.example.com"));
+        assert!(origin_matches(allowed, "HTTPS://Blog.Example.COM"));
+        assert!(!origin_matches(allowed, "https://example.com"));
+        assert!(!origin_matches(allowed, "https://.example.com"));
+        assert!(!origin_matches(allowed, "http://blog.example.com"));
+        assert!(!origin_matches(allowed, "https://evilexample.com"));
+        assert!(!origin_matches(allowed, "https://example.com.evil.test"));
+    }
+
+    fn config() -> CorsConfig {
+        CorsConfig {
+            allowed_origins: vec![
+                "https://app.example.com".to_string(),
+                "https://*.example.org".to_string(),
+            ],
+            allowed_methods: vec!["GET".to_string(), "POST".to_string()],
+            allowed_headers: vec!["Content-Type".to_string(), "X-CSRF-Token".to_string()],
+            exposed_headers: vec!["Retry-After".to_string()],
+            allow_credentials: true,
+            max_age_seconds: 600,
+        }
+    }
+
+    fn header_value(res: &ServiceResponse, name: header::HeaderName) -> Option<&str> {
+        res.headers().get(name).map(|value| value.to_str().unwrap())
+    }
+
+    fn vary(res: &ServiceResponse) -> Vec<&str> {
+        res.headers()
+            .get_all(header::VARY)
+            .map(|value| value.to_str().unwrap())
+            .collect()
+    }
+
+    #[actix_rt::test]
+    async fn answers_preflights_before_the_service() {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(Cors::new(config()))
+                // a preflight reaching this would be a bug
+                .default_service(web::route().to(HttpResponse::InternalServerError)),
+        )
+        .await;
+        let preflight = |origin, method, headers| {
+            test::TestRequest::with_uri("/v1/profile")
+                .method(Method::OPTIONS)
+                .header(header::ORIGIN, origin)
+                .header(header::ACCESS_CONTROL_REQUEST_METHOD, method)
+                .header(header::ACCESS_CONTROL_REQUEST_HEADERS, headers)
+                .to_request()
+        };
+
+        let req = preflight(
+            "https://blog.example.org",
+            "POST",
+            "content-type, x-csrf-token",
+        );
+        let res = test::call_service(&mut app, req).await;
+        assert_eq!(res.status(), 204);
+        assert_eq!(
+            header_value(&res, header::ACCESS_CONTROL_ALLOW_ORIGIN),
+            Some("https://blog.example.org")
+        );
+        assert_eq!(
+            header_value(&res, header::ACCESS_CONTROL_ALLOW_CREDENTIALS),
+            Some("true")
+        );
+        assert_eq!(
+            header_value(&res, header::ACCESS_CONTROL_ALLOW_METHODS),
+            Some("GET, POST")
+        );
+        assert_eq!(
+            header_value(&res, header::ACCESS_CONTROL_ALLOW_HEADERS),
+            Some("Content-Type, X-CSRF-Token")
+        );
+        assert_eq!(
+            header_value(&res, header::ACCESS_CONTROL_MAX_AGE),
+            Some("600")
+        );
+        assert_eq!(vary(&res), ["Origin"]);
+
+        for req in [
+            preflight("https://evil.test", "POST", "content-type"),
+            preflight("https://app.example.com", "DELETE", "content-type"),
+            preflight("https://app.example.com", "POST", "authorization"),
+        ] {
+            let res = test::call_service(&mut app, req).await;
+            assert_eq!(res.status(), 403);
+            assert!(!res
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+            assert_eq!(vary(&res), ["Origin"]);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn adds_headers_for_allowed_origins_only() {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(Cors::new(config()))
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .header(header::ORIGIN, "https://app.example.com")
+            .to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(
+            header_value(&res, header::ACCESS_CONTROL_ALLOW_ORIGIN),
+            Some("https://app.example.com")
+        );
+        assert_eq!(
+            header_value(&res, header::ACCESS_CONTROL_EXPOSE_HEADERS),
+            Some("Retry-After")
+        );
+        assert_eq!(vary(&res), ["Origin"]);
+
+        // a cached copy of these must not be served to an allowed origin
+        for req in [
+            test::TestRequest::get()
+                .header(header::ORIGIN, "https://evil.test")
+                .to_request(),
+            test::TestRequest::get().to_request(),
+        ] {
+            let res = test::call_service(&mut app, req).await;
+            assert_eq!(res.status(), 200);
+            assert!(!res
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+            assert_eq!(vary(&res), ["Origin"]);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn stays_out_of_the_way_without_allowed_origins() {
+        let config = CorsConfig {
+            allowed_origins: Vec::new(),
+            ..config()
+        };
+        let mut app = test::init_service(
+            App::new()
+                .wrap(Cors::new(config))
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .header(header::ORIGIN, "https://app.example.com")
+            .to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert_eq!(res.status(), 200);
+        assert!(!res
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+        assert!(!res.headers().contains_key(header::VARY));
+    }
+}
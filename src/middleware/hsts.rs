@@ -0,0 +1,101 @@
+use actix_service::{Service, Transform};
+use actix_web::{
+    dev::{ServiceRequest, ServiceResponse},
+    error::InternalError,
+    http::{header, HeaderValue},
+    Error,
+};
+use futures::{
+    future::{ok, Ready},
+    Future,
+};
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+// Sets Strict-Transport-Security on responses sent over https. Browsers
+// ignore it over plain http, so it's left out there.
+pub struct Hsts {
+    value: Option<HeaderValue>,
+}
+
+impl Hsts {
+    // `None` turns the middleware into a no-op
+    pub fn new(value: Option<&str>) -> Hsts {
+        Hsts {
+            value: value.map(|value| {
+                HeaderValue::from_str(value).expect("Invalid Strict-Transport-Security value")
+            }),
+        }
+    }
+}
+
+impl<S, B> Transform<S> for Hsts
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = HstsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(HstsMiddleware {
+            service,
+            value: self.value.clone(),
+        })
+    }
+}
+
+pub struct HstsMiddleware<S> {
+    service: S,
+    value: Option<HeaderValue>,
+}
+
+impl<S, B> Service for HstsMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let value = self.value.clone().filter(|_| req.app_config().secure());
+
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let value = match value {
+                Some(value) => value,
+                None => return fut.await,
+            };
+
+            match fut.await {
+                Ok(mut res) => {
+                    res.headers_mut()
+                        .insert(header::STRICT_TRANSPORT_SECURITY, value);
+                    Ok(res)
+                }
+                Err(err) => {
+                    let mut response = err.as_response_error().error_response();
+                    response
+                        .headers_mut()
+                        .insert(header::STRICT_TRANSPORT_SECURITY, value);
+                    Err(InternalError::from_response(err, response).into())
+                }
+            }
+        })
+    }
+}
@@ -1 +1,6 @@
 pub mod auth;
+pub mod cors;
+pub mod hsts;
+pub mod rate_limit;
+pub mod request_id;
+pub mod security_headers;
@@ -0,0 +1,193 @@
+use crate::{
+    constants,
+    extractor::client_ip::ClientIp,
+    model::{auth::AuthMiddlewareData, errors::ServiceError, response::ResponseBody},
+    rate_limit::{Quota, RateLimitDecision, RateLimitStore},
+};
+use actix_service::{Service, Transform};
+use actix_web::{
+    dev::{ServiceRequest, ServiceResponse},
+    http::{header, HeaderMap, HeaderName, HeaderValue},
+    web, Error, HttpMessage, HttpResponse,
+};
+use chrono::Utc;
+use futures::{
+    future::{ok, Ready},
+    Future,
+};
+use std::{
+    cell::RefCell,
+    pin::Pin,
+    rc::Rc,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+#[derive(Clone, Copy)]
+pub enum RateLimitKey {
+    // client address
+    Ip,
+    // authenticated user, falling back to the client address
+    User,
+    // one shared bucket for everything behind the middleware
+    Route,
+}
+
+// Must be wrapped inside `middleware::auth::Authentication` (on a scope or
+// resource) for `RateLimitKey::User` to see the authenticated user.
+pub struct RateLimit {
+    name: &'static str,
+    store: Arc<dyn RateLimitStore>,
+    quota: Quota,
+    key: RateLimitKey,
+}
+
+impl RateLimit {
+    pub fn new(
+        name: &'static str,
+        store: Arc<dyn RateLimitStore>,
+        quota: Quota,
+        key: RateLimitKey,
+    ) -> RateLimit {
+        RateLimit {
+            name,
+            store,
+            quota,
+            key,
+        }
+    }
+}
+
+impl<S, B> Transform<S> for RateLimit
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RateLimitMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RateLimitMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            name: self.name,
+            store: self.store.clone(),
+            quota: self.quota,
+            key: self.key,
+        })
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    name: &'static str,
+    store: Arc<dyn RateLimitStore>,
+    quota: Quota,
+    key: RateLimitKey,
+}
+
+impl<S> RateLimitMiddleware<S> {
+    fn bucket_key(&self, req: &ServiceRequest) -> String {
+        // Clients without a known address, e.g. on a unix socket without a
+        // proxy header, share a bucket of their own. Skipping the limit would
+        // lift it for everyone behind a misconfigured proxy.
+        let ip = || match ClientIp::from_service_request(req) {
+            ClientIp(Some(ip)) => ip.to_string(),
+            ClientIp(None) => "unknown".to_string(),
+        };
+
+        match self.key {
+            RateLimitKey::Ip => format!("{}:ip:{}", self.name, ip()),
+            RateLimitKey::User => match req.extensions().get::<AuthMiddlewareData>() {
+                Some(auth_data) => format!("{}:user:{}", self.name, auth_data.email),
+                None => format!("{}:ip:{}", self.name, ip()),
+            },
+            RateLimitKey::Route => format!("{}:route", self.name),
+        }
+    }
+}
+
+// With nested limiters the most restrictive one is reported
+fn insert_headers(headers: &mut HeaderMap, decision: &RateLimitDecision) {
+    let remaining = HeaderName::from_static("ratelimit-remaining");
+    let current_remaining = headers
+        .get(&remaining)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u32>().ok());
+
+    if let Some(current_remaining) = current_remaining {
+        if current_remaining <= decision.remaining {
+            return;
+        }
+    }
+
+    headers.insert(
+        HeaderName::from_static("ratelimit-limit"),
+        HeaderValue::from(decision.limit),
+    );
+    headers.insert(remaining, HeaderValue::from(decision.remaining));
+    headers.insert(
+        HeaderName::from_static("ratelimit-reset"),
+        HeaderValue::from(decision.reset_seconds),
+    );
+}
+
+impl<S, B> Service for RateLimitMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let store = self.store.clone();
+        let quota = self.quota;
+        let key = self.bucket_key(&req);
+
+        Box::pin(async move {
+            let now_ms = Utc::now().timestamp_millis();
+            let decision = web::block(move || store.check(&key, now_ms, &quota)).await;
+
+            let decision = match decision {
+                Ok(decision) => decision,
+                // Fail open, an unreachable store shouldn't take the API down
+                Err(_) => {
+                    let fut = service.borrow_mut().call(req);
+                    return fut.await;
+                }
+            };
+
+            if !decision.allowed {
+                let mut response = HttpResponse::TooManyRequests().json(ResponseBody::error(
+                    constants::MESSAGE_RATE_LIMITED,
+                    Some(ServiceError::RateLimited),
+                ));
+                insert_headers(response.headers_mut(), &decision);
+                response.headers_mut().insert(
+                    header::RETRY_AFTER,
+                    HeaderValue::from(decision.retry_after_seconds.unwrap_or(1)),
+                );
+
+                return Ok(req.into_response(response.into_body()));
+            }
+
+            let fut = service.borrow_mut().call(req);
+            let mut res = fut.await?;
+            insert_headers(res.headers_mut(), &decision);
+            Ok(res)
+        })
+    }
+}
@@ -0,0 +1,197 @@
+use crate::constants::REQUEST_ID_HEADER;
+use crate::metrics;
+use crate::telemetry::HeaderExtractor;
+use crate::utils::generate_random_token;
+use actix_service::{Service, Transform};
+use actix_web::{
+    dev::{ServiceRequest, ServiceResponse},
+    error::InternalError,
+    http::{HeaderName, HeaderValue},
+    Error,
+};
+use futures::{
+    future::{ok, poll_fn, Ready},
+    Future,
+};
+use opentelemetry::{global, trace::TraceContextExt};
+use std::{
+    cell::RefCell,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Instant,
+};
+use tracing::{field, Instrument};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+thread_local! {
+    // A request is polled on the worker thread that accepted it, so this
+    // acts as a task local for the request being handled
+    static CURRENT_REQUEST_ID: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+// The id of the request being handled, for error bodies
+pub fn current_request_id() -> Option<String> {
+    CURRENT_REQUEST_ID.with(|request_id| request_id.borrow().clone())
+}
+
+// Must be the outermost middleware, so everything below runs with the id set
+// and errors from the other middlewares are logged with it too.
+pub struct RequestIdentifier;
+
+impl<S, B> Transform<S> for RequestIdentifier
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestIdentifierMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequestIdentifierMiddleware { service })
+    }
+}
+
+pub struct RequestIdentifierMiddleware<S> {
+    service: S,
+}
+
+// Ids from clients and proxies are kept when they look like ids, anything
+// else could be used to forge log lines
+fn incoming_request_id(req: &ServiceRequest) -> Option<String> {
+    let value = req.headers().get(REQUEST_ID_HEADER)?.to_str().ok()?;
+    let valid = !value.is_empty()
+        && value.len() <= 128
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'));
+
+    if valid {
+        Some(value.to_string())
+    } else {
+        None
+    }
+}
+
+fn request_id_header() -> HeaderName {
+    HeaderName::from_static("x-request-id")
+}
+
+impl<S, B> Service for RequestIdentifierMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let request_id = incoming_request_id(&req).unwrap_or_else(|| generate_random_token(16));
+
+        let span = tracing::info_span!(
+            "request",
+            request_id = %request_id,
+            method = %req.method(),
+            path = %req.path(),
+            otel.name = %req.method(),
+            otel.kind = "server",
+            otel.status_code = field::Empty,
+            http.route = field::Empty,
+            http.response.status_code = field::Empty,
+        );
+        // Continues the caller's trace when it sent a `traceparent`
+        let parent = global::get_text_map_propagator(|propagator| {
+            propagator.extract(&HeaderExtractor(req.headers()))
+        });
+        let _ = span.set_parent(parent);
+        let method = req.method().clone();
+        let started_at = Instant::now();
+        // The middlewares below start their spans when called
+        let fut = span.in_scope(|| self.service.call(req));
+
+        // Errors from the middlewares below get their response built here,
+        // while the request id is still set for the body
+        let mut handled = Box::pin(async move {
+            fut.await.map_err(|err| {
+                let response = err.as_response_error().error_response();
+                (err, response)
+            })
+        });
+        let current_request_id = request_id.clone();
+        let with_request_id = poll_fn(move |cx| {
+            let previous =
+                CURRENT_REQUEST_ID.with(|id| id.replace(Some(current_request_id.clone())));
+            let poll = handled.as_mut().poll(cx);
+            CURRENT_REQUEST_ID.with(|id| id.replace(previous));
+            poll
+        });
+
+        Box::pin(async move {
+            let result = with_request_id.instrument(span.clone()).await;
+
+            let status = match result {
+                Ok(ref res) => res.status(),
+                Err((_, ref response)) => response.status(),
+            };
+            // Traces and metrics are grouped by the route pattern, not the
+            // path with ids
+            let route = match result {
+                Ok(ref res) => res.request().match_pattern(),
+                Err(_) => None,
+            };
+            if let Some(ref route) = route {
+                // `otel.name` can't be recorded once the span started
+                span.context()
+                    .span()
+                    .update_name(format!("{} {}", method, route));
+                span.record("http.route", route.as_str());
+            }
+            span.record("http.response.status_code", status.as_u16());
+            if status.is_server_error() {
+                span.record("otel.status_code", "ERROR");
+            }
+            let latency = started_at.elapsed();
+            metrics::record_http_request(
+                method.as_str(),
+                route.as_deref().unwrap_or("unmatched"),
+                status.as_u16(),
+                latency,
+            );
+            let latency_ms = latency.as_millis() as u64;
+            span.in_scope(|| {
+                if status.is_server_error() {
+                    tracing::error!(status = status.as_u16(), latency_ms, "request failed");
+                } else {
+                    tracing::info!(status = status.as_u16(), latency_ms, "request finished");
+                }
+            });
+
+            let header_value = HeaderValue::from_str(&request_id).ok();
+            match result {
+                Ok(mut res) => {
+                    if let Some(value) = header_value {
+                        res.headers_mut().insert(request_id_header(), value);
+                    }
+                    Ok(res)
+                }
+                Err((err, mut response)) => {
+                    if let Some(value) = header_value {
+                        response.headers_mut().insert(request_id_header(), value);
+                    }
+                    Err(InternalError::from_response(err, response).into())
+                }
+            }
+        })
+    }
+}
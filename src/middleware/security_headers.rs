@@ -0,0 +1,154 @@
+use crate::config::HeaderPolicy;
+use actix_service::{Service, Transform};
+use actix_web::{
+    dev::{ServiceRequest, ServiceResponse},
+    error::InternalError,
+    http::{header, HeaderMap, HeaderName, HeaderValue},
+    Error,
+};
+use futures::{
+    future::{ok, Ready},
+    Future,
+};
+use std::{
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll},
+};
+
+// Headers already on the response are kept, so wrapping a scope or resource
+// with a policy of its own overrides the one wrapping the whole app, and
+// handlers can still set e.g. their own Cache-Control.
+pub struct SecurityHeaders {
+    headers: Rc<Vec<(HeaderName, HeaderValue)>>,
+}
+
+fn header_value(value: &str) -> HeaderValue {
+    // the config only accepts visible ASCII
+    HeaderValue::from_str(value).expect("Invalid security header value")
+}
+
+impl SecurityHeaders {
+    pub fn new(policy: &HeaderPolicy) -> SecurityHeaders {
+        let mut headers = Vec::new();
+
+        let csp = match (&policy.content_security_policy, &policy.frame_ancestors) {
+            (Some(csp), Some(ancestors)) if !csp.contains("frame-ancestors") => {
+                Some(format!("{}; frame-ancestors {}", csp, ancestors))
+            }
+            (Some(csp), _) => Some(csp.clone()),
+            (None, Some(ancestors)) => Some(format!("frame-ancestors {}", ancestors)),
+            (None, None) => None,
+        };
+        if let Some(csp) = csp {
+            headers.push((header::CONTENT_SECURITY_POLICY, header_value(&csp)));
+        }
+
+        // X-Frame-Options can't express a list of origins
+        let frame_options = match policy.frame_ancestors.as_deref() {
+            Some("'none'") => Some("DENY"),
+            Some("'self'") => Some("SAMEORIGIN"),
+            _ => None,
+        };
+        if let Some(frame_options) = frame_options {
+            headers.push((
+                header::X_FRAME_OPTIONS,
+                HeaderValue::from_static(frame_options),
+            ));
+        }
+
+        if policy.nosniff {
+            headers.push((
+                header::X_CONTENT_TYPE_OPTIONS,
+                HeaderValue::from_static("nosniff"),
+            ));
+        }
+        if let Some(referrer_policy) = &policy.referrer_policy {
+            headers.push((header::REFERRER_POLICY, header_value(referrer_policy)));
+        }
+        if let Some(permissions_policy) = &policy.permissions_policy {
+            headers.push((
+                HeaderName::from_static("permissions-policy"),
+                header_value(permissions_policy),
+            ));
+        }
+        if let Some(cache_control) = &policy.cache_control {
+            headers.push((header::CACHE_CONTROL, header_value(cache_control)));
+        }
+
+        SecurityHeaders {
+            headers: Rc::new(headers),
+        }
+    }
+}
+
+impl<S, B> Transform<S> for SecurityHeaders
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = SecurityHeadersMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(SecurityHeadersMiddleware {
+            service,
+            headers: self.headers.clone(),
+        })
+    }
+}
+
+pub struct SecurityHeadersMiddleware<S> {
+    service: S,
+    headers: Rc<Vec<(HeaderName, HeaderValue)>>,
+}
+
+impl<S, B> Service for SecurityHeadersMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let headers = self.headers.clone();
+
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let add_headers = |response_headers: &mut HeaderMap| {
+                for (name, value) in headers.iter() {
+                    if !response_headers.contains_key(name) {
+                        response_headers.insert(name.clone(), value.clone());
+                    }
+                }
+            };
+
+            match fut.await {
+                Ok(mut res) => {
+                    add_headers(res.headers_mut());
+                    Ok(res)
+                }
+                // e.g. a 401 from a middleware below, built here so it gets
+                // the headers too
+                Err(err) => {
+                    let mut response = err.as_response_error().error_response();
+                    add_headers(response.headers_mut());
+                    Err(InternalError::from_response(err, response).into())
+                }
+            }
+        })
+    }
+}
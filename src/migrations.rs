@@ -0,0 +1,117 @@
+// The migrations in `migrations/`, embedded so a deploy can apply them
+// without the directory or diesel_cli. diesel_migrations 1.4 can't revert or
+// list embedded migrations, so `migrate down`, `migrate status` and the
+// readiness check read the directory, found from the working directory up.
+
+use std::collections::HashSet;
+
+use chrono::NaiveDateTime;
+use diesel::{connection::SimpleConnection, dsl::sql, sql_types::Bool, PgConnection, QueryDsl};
+use diesel::{QueryResult, RunQueryDsl};
+use diesel_migrations::{
+    find_migrations_directory, migration_paths_in_directory, version_from_path,
+    MigrationConnection, MigrationError, RunMigrationsError,
+};
+
+use self::__diesel_schema_migrations::dsl::{
+    __diesel_schema_migrations as applied, run_on, version,
+};
+
+embed_migrations!();
+
+// Any constant works, it only has to be the same for every instance
+const MIGRATION_LOCK_KEY: i64 = 4_817_293;
+
+table! {
+    __diesel_schema_migrations (version) {
+        version -> VarChar,
+        run_on -> Timestamp,
+    }
+}
+
+fn tracking_table_exists(conn: &PgConnection) -> QueryResult<bool> {
+    diesel::select(sql::<Bool>(
+        "to_regclass('__diesel_schema_migrations') IS NOT NULL",
+    ))
+    .get_result(conn)
+}
+
+// Versions recorded as applied with when they ran, oldest first, none
+// before the first migration ran
+pub fn applied_versions(conn: &PgConnection) -> QueryResult<Vec<(String, NaiveDateTime)>> {
+    if !tracking_table_exists(conn)? {
+        return Ok(Vec::new());
+    }
+
+    applied.select((version, run_on)).order(version).load(conn)
+}
+
+// Directory names of the migrations in `migrations/`, oldest first, with
+// their versions
+pub fn in_directory() -> Result<Vec<(String, String)>, MigrationError> {
+    let mut migrations = migration_paths_in_directory(&find_migrations_directory()?)?
+        .into_iter()
+        .map(|entry| {
+            let path = entry.path();
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            version_from_path(&path).map(|migration_version| (migration_version, name))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    migrations.sort();
+
+    Ok(migrations)
+}
+
+pub fn pending_count(conn: &PgConnection) -> Result<usize, RunMigrationsError> {
+    let applied_versions: HashSet<String> = if tracking_table_exists(conn)? {
+        conn.previously_run_migration_versions()?
+    } else {
+        HashSet::new()
+    };
+
+    Ok(in_directory()?
+        .iter()
+        .filter(|(migration_version, _)| !applied_versions.contains(migration_version))
+        .count())
+}
+
+// Instances starting together would otherwise run the same migration twice
+fn with_lock<T>(
+    conn: &PgConnection,
+    f: impl FnOnce() -> Result<T, RunMigrationsError>,
+) -> Result<T, RunMigrationsError> {
+    conn.batch_execute(&format!("SELECT pg_advisory_lock({})", MIGRATION_LOCK_KEY))?;
+    let result = f();
+    conn.batch_execute(&format!(
+        "SELECT pg_advisory_unlock({})",
+        MIGRATION_LOCK_KEY
+    ))?;
+
+    result
+}
+
+// Applies every pending migration, each in a transaction of its own, and
+// returns a line for each one that ran
+pub fn run_pending(conn: &PgConnection) -> Result<Vec<String>, RunMigrationsError> {
+    with_lock(conn, || {
+        let mut output = Vec::new();
+        embedded_migrations::run_with_output(conn, &mut output)?;
+
+        Ok(String::from_utf8_lossy(&output)
+            .lines()
+            .map(str::to_string)
+            .collect())
+    })
+}
+
+// Reverts the most recently applied migration and returns its version, none
+// when nothing was applied
+pub fn revert_latest(conn: &PgConnection) -> Result<Option<String>, RunMigrationsError> {
+    with_lock(conn, || {
+        match diesel_migrations::revert_latest_migration(conn) {
+            Ok(reverted) => Ok(Some(reverted)),
+            Err(RunMigrationsError::MigrationError(MigrationError::NoMigrationRun)) => Ok(None),
+            Err(err) => Err(err),
+        }
+    })
+}
@@ -0,0 +1,29 @@
+use crate::schema::api_keys;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+#[derive(Queryable, Identifiable, Serialize)]
+pub struct ApiKey {
+    pub id: i32,
+    #[serde(skip_serializing)]
+    pub user_id: i32,
+    pub name: String,
+    pub prefix: String,
+    #[serde(skip_serializing)]
+    pub key_hash: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[table_name = "api_keys"]
+pub struct NewApiKey<'a> {
+    pub user_id: i32,
+    pub name: &'a str,
+    pub prefix: &'a str,
+    pub key_hash: &'a str,
+    pub scopes: &'a [String],
+    pub expires_at: Option<DateTime<Utc>>,
+}
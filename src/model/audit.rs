@@ -0,0 +1,26 @@
+use crate::schema::audit_events;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+#[derive(Queryable, Serialize)]
+pub struct AuditEvent {
+    pub id: i64,
+    pub event: String,
+    pub actor_user_id: Option<i32>,
+    pub target_user_id: Option<i32>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub details: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[table_name = "audit_events"]
+pub struct NewAuditEvent<'a> {
+    pub event: &'a str,
+    pub actor_user_id: Option<i32>,
+    pub target_user_id: Option<i32>,
+    pub ip: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+    pub details: serde_json::Value,
+}
@@ -1,4 +1,10 @@
 #[derive(Clone)]
 pub struct AuthMiddlewareData {
     pub email: String,
+    // set when the request came in with an API key instead of a session
+    pub api_key_id: Option<i32>,
+    // the session behind the token, None for API keys
+    pub session_id: Option<i32>,
+    // admin behind an impersonation token
+    pub impersonator_id: Option<i32>,
 }
@@ -4,3 +4,6 @@ use diesel::{
 };
 
 pub type Pool = r2d2::Pool<ConnectionManager<PgConnection>>;
+
+// Emails are unique on `lower(email)`, filter with this so the index is used.
+sql_function!(fn lower(x: diesel::sql_types::Text) -> diesel::sql_types::Text);
@@ -1,4 +1,4 @@
-use actix_web::{HttpResponse, ResponseError};
+use actix_web::{http::header, HttpResponse, ResponseError};
 use derive_more::Display;
 use serde::{Deserialize, Serialize};
 
@@ -14,6 +14,44 @@ pub enum ServiceError {
     UserNotFound,
     #[display(fmt = "00004")]
     InvalidToken,
+    #[display(fmt = "00006")]
+    TooManyAttempts,
+    #[display(fmt = "00007")]
+    PermissionDenied,
+    #[display(fmt = "00008")]
+    RateLimited,
+    #[display(fmt = "00009")]
+    InvalidCsrfToken,
+    #[display(fmt = "00010")]
+    OAuthFailed,
+    #[display(fmt = "00011")]
+    IdentityAlreadyLinked,
+    #[display(fmt = "00012")]
+    IdentityNotFound,
+    #[display(fmt = "00013")]
+    InvalidMagicLink,
+    #[display(fmt = "00014")]
+    InvalidPasskey,
+    #[display(fmt = "00015")]
+    PasskeyNotFound,
+    #[display(fmt = "00016")]
+    InsufficientScope,
+    #[display(fmt = "00017")]
+    ApiKeyNotFound,
+    #[display(fmt = "00018")]
+    SessionNotFound,
+    #[display(fmt = "00019")]
+    AccountDisabled,
+    #[display(fmt = "00020")]
+    InvalidPasswordReset,
+    #[display(fmt = "00021")]
+    UsernameAlreadyExists,
+    #[display(fmt = "00022")]
+    RegistrationClosed,
+    #[display(fmt = "00023")]
+    InvalidInviteCode,
+    #[display(fmt = "00024")]
+    InviteNotFound,
 }
 
 pub fn error_to_message(error: Option<ServiceError>) -> Option<String> {
@@ -25,6 +63,37 @@ pub fn error_to_message(error: Option<ServiceError>) -> Option<String> {
         Some(ServiceError::EmailAlreadyExists) => Some("Email already exists".to_string()),
         Some(ServiceError::UserNotFound) => Some("User not found".to_string()),
         Some(ServiceError::InvalidToken) => Some("Invalid token".to_string()),
+        Some(ServiceError::TooManyAttempts) => Some("Too many attempts".to_string()),
+        Some(ServiceError::PermissionDenied) => Some("Permission denied".to_string()),
+        Some(ServiceError::RateLimited) => Some("Rate limit exceeded".to_string()),
+        Some(ServiceError::InvalidCsrfToken) => Some("Invalid CSRF token".to_string()),
+        Some(ServiceError::OAuthFailed) => {
+            Some("Sign in with the external provider failed".to_string())
+        }
+        Some(ServiceError::IdentityAlreadyLinked) => {
+            Some("This external account is linked to another user".to_string())
+        }
+        Some(ServiceError::IdentityNotFound) => Some("Identity not found".to_string()),
+        Some(ServiceError::InvalidMagicLink) => {
+            Some("Sign-in link is invalid or expired".to_string())
+        }
+        Some(ServiceError::InvalidPasskey) => Some("Passkey verification failed".to_string()),
+        Some(ServiceError::PasskeyNotFound) => Some("Passkey not found".to_string()),
+        Some(ServiceError::InsufficientScope) => {
+            Some("The API key is missing the scope for this request".to_string())
+        }
+        Some(ServiceError::ApiKeyNotFound) => Some("API key not found".to_string()),
+        Some(ServiceError::SessionNotFound) => Some("Session not found".to_string()),
+        Some(ServiceError::AccountDisabled) => Some("Account is disabled".to_string()),
+        Some(ServiceError::InvalidPasswordReset) => {
+            Some("Password reset link is invalid or expired".to_string())
+        }
+        Some(ServiceError::UsernameAlreadyExists) => Some("Username already taken".to_string()),
+        Some(ServiceError::RegistrationClosed) => Some("Registration is closed".to_string()),
+        Some(ServiceError::InvalidInviteCode) => {
+            Some("Invite code is invalid, used up or expired".to_string())
+        }
+        Some(ServiceError::InviteNotFound) => Some("Invite not found".to_string()),
     }
 }
 
@@ -38,31 +107,84 @@ pub enum GlobalServiceError {
 
     #[display(fmt = "Unauthorized")]
     Unauthorized(ServiceError),
+
+    #[display(fmt = "Forbidden")]
+    Forbidden(ServiceError),
+
+    #[display(fmt = "Not Found")]
+    NotFound(ServiceError),
+
+    #[display(fmt = "Conflict")]
+    Conflict(ServiceError),
+
+    // the second field is the number of seconds for `Retry-After`
+    #[display(fmt = "Too Many Requests")]
+    TooManyRequests(ServiceError, i64),
+}
+
+// Lets `?` be used inside `Connection::transaction`
+impl From<diesel::result::Error> for GlobalServiceError {
+    fn from(_err: diesel::result::Error) -> Self {
+        GlobalServiceError::InternalServerError
+    }
 }
 
 impl ResponseError for GlobalServiceError {
     fn error_response(&self) -> HttpResponse {
         match self {
-            GlobalServiceError::InternalServerError => HttpResponse::InternalServerError()
-                .json("Internal Server Error, please please pleaseee try again maybe later"),
+            GlobalServiceError::InternalServerError => HttpResponse::InternalServerError().json(
+                ResponseBody::error(&GlobalServiceError::InternalServerError.to_string(), None),
+            ),
             GlobalServiceError::BadRequest(ref message) => {
-                HttpResponse::BadRequest().json(ResponseBody::<()>::new(
+                HttpResponse::BadRequest().json(ResponseBody::error(
                     GlobalServiceError::BadRequest(message.to_owned())
                         .to_string()
                         .as_str(),
                     None,
-                    None,
                 ))
             }
             GlobalServiceError::Unauthorized(ref err) => {
-                HttpResponse::Unauthorized().json(ResponseBody::<()>::new(
+                HttpResponse::Unauthorized().json(ResponseBody::error(
                     GlobalServiceError::Unauthorized(err.to_owned())
                         .to_string()
                         .as_str(),
-                    None,
-                    Some(ServiceError::EmailOrPasswordMismatch),
+                    Some(err.to_owned()),
+                ))
+            }
+            GlobalServiceError::Forbidden(ref err) => {
+                HttpResponse::Forbidden().json(ResponseBody::error(
+                    GlobalServiceError::Forbidden(err.to_owned())
+                        .to_string()
+                        .as_str(),
+                    Some(err.to_owned()),
+                ))
+            }
+            GlobalServiceError::NotFound(ref err) => {
+                HttpResponse::NotFound().json(ResponseBody::error(
+                    GlobalServiceError::NotFound(err.to_owned())
+                        .to_string()
+                        .as_str(),
+                    Some(err.to_owned()),
                 ))
             }
+            GlobalServiceError::Conflict(ref err) => {
+                HttpResponse::Conflict().json(ResponseBody::error(
+                    GlobalServiceError::Conflict(err.to_owned())
+                        .to_string()
+                        .as_str(),
+                    Some(err.to_owned()),
+                ))
+            }
+            GlobalServiceError::TooManyRequests(ref err, retry_after) => {
+                HttpResponse::TooManyRequests()
+                    .header(header::RETRY_AFTER, retry_after.to_string())
+                    .json(ResponseBody::error(
+                        GlobalServiceError::TooManyRequests(err.to_owned(), *retry_after)
+                            .to_string()
+                            .as_str(),
+                        Some(err.to_owned()),
+                    ))
+            }
         }
     }
 }
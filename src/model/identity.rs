@@ -0,0 +1,42 @@
+use crate::schema::{oauth_states, user_identities};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+#[derive(Queryable, Identifiable, Serialize)]
+#[table_name = "user_identities"]
+pub struct UserIdentity {
+    pub id: i32,
+    pub user_id: i32,
+    pub provider: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[table_name = "user_identities"]
+pub struct NewUserIdentity<'a> {
+    pub user_id: i32,
+    pub provider: &'a str,
+    pub subject: &'a str,
+    pub email: Option<&'a str>,
+}
+
+// Loaded by `state` which the caller already has
+#[derive(Queryable)]
+pub struct OAuthState {
+    pub provider: String,
+    pub code_verifier: String,
+    pub link_user_id: Option<i32>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[table_name = "oauth_states"]
+pub struct NewOAuthState<'a> {
+    pub state: &'a str,
+    pub provider: &'a str,
+    pub code_verifier: &'a str,
+    pub link_user_id: Option<i32>,
+    pub nonce_hash: &'a str,
+}
@@ -0,0 +1,28 @@
+use crate::schema::invites;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+#[derive(Queryable, Identifiable, Serialize)]
+pub struct Invite {
+    pub id: i32,
+    #[serde(skip_serializing)]
+    pub code_hash: String,
+    pub prefix: String,
+    pub role: String,
+    pub max_uses: i32,
+    pub uses: i32,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub created_by: Option<i32>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[table_name = "invites"]
+pub struct NewInvite<'a> {
+    pub code_hash: &'a str,
+    pub prefix: &'a str,
+    pub role: &'a str,
+    pub max_uses: i32,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub created_by: Option<i32>,
+}
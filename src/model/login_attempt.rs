@@ -0,0 +1,12 @@
+use crate::schema::login_ip_attempts;
+use chrono::{DateTime, Utc};
+
+#[derive(Queryable, Insertable, AsChangeset)]
+#[table_name = "login_ip_attempts"]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct LoginIpAttempt {
+    pub ip: String,
+    pub failed_attempts: i32,
+    pub last_failed_at: DateTime<Utc>,
+    pub locked_until: Option<DateTime<Utc>>,
+}
@@ -0,0 +1,11 @@
+use crate::schema::magic_links;
+use chrono::{DateTime, Utc};
+
+#[derive(Insertable)]
+#[table_name = "magic_links"]
+pub struct NewMagicLink<'a> {
+    pub user_id: i32,
+    pub token_hash: &'a str,
+    pub nonce_hash: &'a str,
+    pub expires_at: DateTime<Utc>,
+}
@@ -1,5 +1,15 @@
+pub mod api_key;
+pub mod audit;
 pub mod auth;
 pub mod db;
 pub mod errors;
+pub mod identity;
+pub mod invite;
+pub mod login_attempt;
+pub mod magic_link;
+pub mod pagination;
+pub mod password_reset;
 pub mod response;
+pub mod session;
 pub mod user;
+pub mod webauthn;
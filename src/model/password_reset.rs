@@ -0,0 +1,10 @@
+use crate::schema::password_resets;
+use chrono::{DateTime, Utc};
+
+#[derive(Insertable)]
+#[table_name = "password_resets"]
+pub struct NewPasswordReset<'a> {
+    pub user_id: i32,
+    pub token_hash: &'a str,
+    pub expires_at: DateTime<Utc>,
+}
@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
 
 use super::errors::{error_to_message, ServiceError};
+use crate::middleware::request_id::current_request_id;
 
 #[derive(Serialize, Deserialize)]
 pub struct ResponseBody<T> {
@@ -8,6 +9,9 @@ pub struct ResponseBody<T> {
     pub data: Option<T>,
     pub error_code: Option<String>,
     pub error_message: Option<String>,
+    // only on errors, to find the request in the logs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
 }
 
 impl<T> ResponseBody<T> {
@@ -15,11 +19,18 @@ impl<T> ResponseBody<T> {
         ResponseBody {
             message: message.to_string(),
             data,
-            error_code: match error {
-                None => None,
-                Some(code) => Some(code.to_string()),
-            },
+            error_code: error.map(|code| code.to_string()),
             error_message: error_to_message(error),
+            request_id: None,
+        }
+    }
+}
+
+impl ResponseBody<()> {
+    pub fn error(message: &str, error: Option<ServiceError>) -> ResponseBody<()> {
+        ResponseBody {
+            request_id: current_request_id(),
+            ..ResponseBody::new(message, None, error)
         }
     }
 }
@@ -0,0 +1,24 @@
+use crate::schema::sessions;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+#[derive(Queryable, Identifiable, Serialize)]
+pub struct Session {
+    pub id: i32,
+    #[serde(skip_serializing)]
+    pub user_id: i32,
+    pub user_agent: Option<String>,
+    pub ip: String,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[table_name = "sessions"]
+pub struct NewSession<'a> {
+    pub user_id: i32,
+    pub user_agent: Option<&'a str>,
+    pub ip: &'a str,
+    pub expires_at: DateTime<Utc>,
+}
@@ -1,12 +1,27 @@
+use crate::model::db::lower;
+use crate::model::errors::{GlobalServiceError, ServiceError};
 use crate::schema::users;
+use chrono::{DateTime, Utc};
+use diesel::{ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use serde_json::Value;
 
 #[derive(Queryable, Identifiable)]
 pub struct User {
     pub id: i32,
     pub email: String,
     pub hashed_password: String,
-    pub salt: String,
     pub full_name: Option<String>,
+    pub role: String,
+    pub failed_login_attempts: i32,
+    pub last_failed_login_at: Option<DateTime<Utc>>,
+    pub locked_until: Option<DateTime<Utc>>,
+    pub disabled_at: Option<DateTime<Utc>>,
+    pub username: Option<String>,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub website: Option<String>,
+    pub social_links: Value,
+    pub avatar_url: Option<String>,
 }
 
 #[derive(Insertable)]
@@ -14,6 +29,27 @@ pub struct User {
 pub struct NewUser<'a> {
     pub email: &'a str,
     pub hashed_password: &'a str,
-    pub salt: &'a str,
     pub full_name: &'a str,
+    pub username: Option<&'a str>,
+    pub role: &'a str,
+}
+
+// Fields left as None are not touched, Some(None) clears them
+#[derive(AsChangeset)]
+#[table_name = "users"]
+pub struct ProfileChanges {
+    pub username: Option<String>,
+    pub display_name: Option<Option<String>>,
+    pub bio: Option<Option<String>>,
+    pub website: Option<Option<String>>,
+    pub social_links: Option<Value>,
+    pub avatar_url: Option<Option<String>>,
+}
+
+// Loads the account an authenticated request belongs to
+pub fn find_by_email(conn: &PgConnection, user_email: &str) -> Result<User, GlobalServiceError> {
+    users::table
+        .filter(lower(users::email).eq(user_email))
+        .first::<User>(conn)
+        .map_err(|_err| GlobalServiceError::NotFound(ServiceError::UserNotFound))
 }
@@ -0,0 +1,51 @@
+use crate::schema::{webauthn_challenges, webauthn_credentials};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+pub const PURPOSE_REGISTRATION: &str = "registration";
+// passkey as the only factor
+pub const PURPOSE_AUTHENTICATION: &str = "authentication";
+// after the password was verified
+pub const PURPOSE_SECOND_FACTOR: &str = "second_factor";
+
+#[derive(Queryable, Identifiable, Serialize)]
+pub struct WebauthnCredential {
+    pub id: i32,
+    #[serde(skip_serializing)]
+    pub user_id: i32,
+    pub credential_id: String,
+    #[serde(skip_serializing)]
+    pub public_key: Vec<u8>,
+    pub algorithm: i32,
+    pub sign_count: i64,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Insertable)]
+#[table_name = "webauthn_credentials"]
+pub struct NewWebauthnCredential<'a> {
+    pub user_id: i32,
+    pub credential_id: &'a str,
+    pub public_key: &'a [u8],
+    pub algorithm: i32,
+    pub sign_count: i64,
+    pub name: &'a str,
+}
+
+#[derive(Queryable)]
+pub struct WebauthnChallenge {
+    pub user_id: Option<i32>,
+    pub challenge: String,
+    pub purpose: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[table_name = "webauthn_challenges"]
+pub struct NewWebauthnChallenge<'a> {
+    pub user_id: Option<i32>,
+    pub challenge: &'a str,
+    pub purpose: &'a str,
+}
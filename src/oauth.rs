@@ -0,0 +1,154 @@
+// OAuth2 authorization code flow with PKCE against the providers configured
+// in `config::OAuthConfig`. OpenID Connect providers are handled through their
+// userinfo endpoint, so the ID token itself is not needed.
+
+use actix_web::client::Client;
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use crate::config::OAuthProviderConfig;
+use crate::model::errors::{GlobalServiceError, ServiceError};
+
+// What we keep from the provider's user info
+pub struct ExternalProfile {
+    pub subject: String,
+    // only set when the provider says it is verified
+    pub email: Option<String>,
+    pub name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+#[derive(Deserialize)]
+struct GithubEmail {
+    email: String,
+    primary: bool,
+    verified: bool,
+}
+
+fn provider_error<E>(_err: E) -> GlobalServiceError {
+    GlobalServiceError::Unauthorized(ServiceError::OAuthFailed)
+}
+
+// S256 code challenge for a PKCE code verifier
+pub fn pkce_challenge(code_verifier: &str) -> String {
+    let digest = Sha256::digest(code_verifier.as_bytes());
+    base64::encode_config(digest, base64::URL_SAFE_NO_PAD)
+}
+
+pub fn authorize_url(
+    config: &OAuthProviderConfig,
+    state: &str,
+    code_verifier: &str,
+) -> Result<String, GlobalServiceError> {
+    let mut url = Url::parse(&config.authorize_url)
+        .map_err(|_err| GlobalServiceError::InternalServerError)?;
+    url.query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &config.client_id)
+        .append_pair("redirect_uri", &config.redirect_url)
+        .append_pair("scope", &config.scopes)
+        .append_pair("state", state)
+        .append_pair("code_challenge", &pkce_challenge(code_verifier))
+        .append_pair("code_challenge_method", "S256");
+
+    Ok(url.into())
+}
+
+async fn exchange_code(
+    client: &Client,
+    config: &OAuthProviderConfig,
+    code: &str,
+    code_verifier: &str,
+) -> Result<String, GlobalServiceError> {
+    let mut response = client
+        .post(&config.token_url)
+        // GitHub answers form encoded unless asked otherwise
+        .header("Accept", "application/json")
+        .send_form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &config.redirect_url),
+            ("client_id", &config.client_id),
+            ("client_secret", &config.client_secret),
+            ("code_verifier", code_verifier),
+        ])
+        .await
+        .map_err(provider_error)?;
+
+    if !response.status().is_success() {
+        return Err(provider_error(()));
+    }
+
+    response
+        .json::<TokenResponse>()
+        .await
+        .map(|token| token.access_token)
+        .map_err(provider_error)
+}
+
+async fn get_json(
+    client: &Client,
+    url: &str,
+    access_token: &str,
+) -> Result<Value, GlobalServiceError> {
+    let mut response = client
+        .get(url)
+        .header("Accept", "application/json")
+        .bearer_auth(access_token)
+        .send()
+        .await
+        .map_err(provider_error)?;
+
+    if !response.status().is_success() {
+        return Err(provider_error(()));
+    }
+
+    response.json::<Value>().await.map_err(provider_error)
+}
+
+pub async fn fetch_profile(
+    config: &OAuthProviderConfig,
+    code: &str,
+    code_verifier: &str,
+) -> Result<ExternalProfile, GlobalServiceError> {
+    let client = Client::default();
+    let access_token = exchange_code(&client, config, code, code_verifier).await?;
+    let userinfo = get_json(&client, &config.userinfo_url, &access_token).await?;
+
+    let subject = match userinfo.get(&config.subject_field) {
+        Some(Value::String(subject)) => subject.clone(),
+        Some(Value::Number(subject)) => subject.to_string(),
+        _ => return Err(provider_error(())),
+    };
+
+    let email = match config.emails_url {
+        Some(ref emails_url) => {
+            let emails: Vec<GithubEmail> =
+                serde_json::from_value(get_json(&client, emails_url, &access_token).await?)
+                    .map_err(provider_error)?;
+            emails
+                .into_iter()
+                .find(|email| email.primary && email.verified)
+                .map(|email| email.email)
+        }
+        None => match (userinfo.get("email"), userinfo.get("email_verified")) {
+            (Some(Value::String(email)), Some(Value::Bool(true))) => Some(email.clone()),
+            _ => None,
+        },
+    };
+
+    Ok(ExternalProfile {
+        subject,
+        email,
+        name: userinfo
+            .get("name")
+            .and_then(|name| name.as_str())
+            .map(|name| name.to_string()),
+    })
+}
@@ -0,0 +1,36 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use super::{gcra, Quota, RateLimitDecision, RateLimitStore, StoreError};
+
+// Only correct for a single process, use the redis store when running several
+#[derive(Default)]
+pub struct MemoryStore {
+    tats: Mutex<HashMap<String, i64>>,
+}
+
+impl RateLimitStore for MemoryStore {
+    fn check(
+        &self,
+        key: &str,
+        now_ms: i64,
+        quota: &Quota,
+    ) -> Result<RateLimitDecision, StoreError> {
+        let mut tats = self
+            .tats
+            .lock()
+            .map_err(|_err| StoreError("poisoned lock".to_string()))?;
+
+        // expired entries behave exactly like missing ones, drop them so the
+        // map doesn't grow forever
+        if tats.len() > 10_000 {
+            tats.retain(|_, tat| *tat > now_ms);
+        }
+
+        let (allowed, tat) = gcra(tats.get(key).copied(), now_ms, quota);
+        if allowed {
+            tats.insert(key.to_string(), tat);
+        }
+
+        Ok(RateLimitDecision::from_tat(allowed, tat, now_ms, quota))
+    }
+}
@@ -0,0 +1,234 @@
+// Generic cell rate algorithm (GCRA). Every key only needs one stored value,
+// the theoretical arrival time (TAT) of the next request, which makes it easy
+// to keep in any key-value store.
+
+pub mod memory;
+pub mod redis;
+
+use std::{fmt, str::FromStr, sync::Arc};
+
+use crate::config::RateLimitConfig;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Quota {
+    pub count: u32,
+    pub period_ms: i64,
+}
+
+impl Quota {
+    // time it takes for one request to be replenished
+    pub fn emission_interval_ms(&self) -> i64 {
+        self.period_ms / self.count as i64
+    }
+}
+
+// "<count>/<seconds>", e.g. "10/60" for ten requests a minute
+impl FromStr for Quota {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (count, seconds) = s
+            .split_once('/')
+            .ok_or_else(|| format!("expected <count>/<seconds>, got {}", s))?;
+        let count: u32 = count.trim().parse().map_err(|_| "invalid count")?;
+        let seconds: i64 = seconds.trim().parse().map_err(|_| "invalid seconds")?;
+
+        if count == 0 || seconds <= 0 {
+            return Err("count and seconds must be positive".to_string());
+        }
+        let period_ms = seconds
+            .checked_mul(1000)
+            .ok_or_else(|| format!("{} seconds is too long", seconds))?;
+        // the emission interval is whole milliseconds and can't be zero
+        if count as i64 > period_ms {
+            return Err(format!(
+                "at most {} requests fit in {} seconds",
+                period_ms, seconds
+            ));
+        }
+
+        Ok(Quota { count, period_ms })
+    }
+}
+
+pub struct RateLimitDecision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    // seconds until the quota is fully replenished
+    pub reset_seconds: i64,
+    // seconds until the next request is allowed, only set when denied
+    pub retry_after_seconds: Option<i64>,
+}
+
+fn ceil_seconds(ms: i64) -> i64 {
+    (ms.max(0) + 999) / 1000
+}
+
+impl RateLimitDecision {
+    // `tat` is the stored arrival time after the check: the new one when the
+    // request was allowed, the untouched one when it was denied.
+    pub fn from_tat(allowed: bool, tat_ms: i64, now_ms: i64, quota: &Quota) -> RateLimitDecision {
+        let interval = quota.emission_interval_ms();
+        let used_ms = (tat_ms - now_ms).max(0);
+        let remaining = ((quota.period_ms - used_ms) / interval).max(0) as u32;
+
+        RateLimitDecision {
+            allowed,
+            limit: quota.count,
+            remaining,
+            reset_seconds: ceil_seconds(used_ms),
+            retry_after_seconds: if allowed {
+                None
+            } else {
+                Some(ceil_seconds(tat_ms + interval - quota.period_ms - now_ms))
+            },
+        }
+    }
+}
+
+// Shared by the stores: returns whether the request is allowed and the TAT to
+// report (and store, when allowed).
+pub fn gcra(stored_tat_ms: Option<i64>, now_ms: i64, quota: &Quota) -> (bool, i64) {
+    let tat = stored_tat_ms.unwrap_or(now_ms).max(now_ms);
+    let new_tat = tat + quota.emission_interval_ms();
+    let allow_at = new_tat - quota.period_ms;
+
+    if now_ms < allow_at {
+        (false, tat)
+    } else {
+        (true, new_tat)
+    }
+}
+
+#[derive(Debug)]
+pub struct StoreError(pub String);
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rate limit store error: {}", self.0)
+    }
+}
+
+// Implementations may block, the middleware calls them through `web::block`
+pub trait RateLimitStore: Send + Sync {
+    fn check(&self, key: &str, now_ms: i64, quota: &Quota)
+        -> Result<RateLimitDecision, StoreError>;
+}
+
+pub fn store_from_config(config: &RateLimitConfig) -> Arc<dyn RateLimitStore> {
+    match config.store.as_str() {
+        "memory" => Arc::new(memory::MemoryStore::default()),
+        "redis" => Arc::new(redis::RedisStore::new(&config.redis_url)),
+        other => panic!("RATE_LIMIT_STORE must be memory or redis, got {}", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quota(s: &str) -> Quota {
+        s.parse().unwrap()
+    }
+
+    // Runs requests against one key the way the stores do
+    fn check(tat: &mut Option<i64>, now_ms: i64, quota: &Quota) -> RateLimitDecision {
+        let (allowed, new_tat) = gcra(*tat, now_ms, quota);
+        if allowed {
+            *tat = Some(new_tat);
+        }
+        RateLimitDecision::from_tat(allowed, new_tat, now_ms, quota)
+    }
+
+    #[test]
+    fn parses_quotas() {
+        let parsed = quota(" 10 / 60 ");
+        assert_eq!(parsed.count, 10);
+        assert_eq!(parsed.period_ms, 60_000);
+        assert_eq!(parsed.emission_interval_ms(), 6_000);
+        assert_eq!(quota("1000/1").emission_interval_ms(), 1);
+    }
+
+    #[test]
+    fn rejects_invalid_quotas() {
+        for invalid in [
+            "", "10", "10/", "/60", "a/60", "10/b", "0/60", "10/0", "-1/60", "10/-5",
+        ] {
+            assert!(
+                invalid.parse::<Quota>().is_err(),
+                "{:?} was accepted",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_quotas_finer_than_a_millisecond() {
+        assert!("1001/1".parse::<Quota>().is_err());
+        assert!("2000/1".parse::<Quota>().is_err());
+        assert!(format!("1/{}", i64::MAX).parse::<Quota>().is_err());
+    }
+
+    #[test]
+    fn allows_a_burst_up_to_the_count_then_denies() {
+        let quota = quota("3/60");
+        let mut tat = None;
+
+        for remaining in [2, 1, 0] {
+            let decision = check(&mut tat, 0, &quota);
+            assert!(decision.allowed);
+            assert_eq!(decision.limit, 3);
+            assert_eq!(decision.remaining, remaining);
+            assert_eq!(decision.retry_after_seconds, None);
+        }
+
+        let denied = check(&mut tat, 0, &quota);
+        assert!(!denied.allowed);
+        assert_eq!(denied.remaining, 0);
+        // one request comes back after an emission interval
+        assert_eq!(denied.retry_after_seconds, Some(20));
+        assert_eq!(denied.reset_seconds, 60);
+        // a denied request doesn't use up anything
+        assert_eq!(tat, Some(60_000));
+    }
+
+    #[test]
+    fn replenishes_one_request_per_interval() {
+        let quota = quota("3/60");
+        let mut tat = None;
+        for _ in 0..3 {
+            check(&mut tat, 0, &quota);
+        }
+
+        assert!(!check(&mut tat, 19_999, &quota).allowed);
+        let decision = check(&mut tat, 20_000, &quota);
+        assert!(decision.allowed);
+        assert_eq!(decision.remaining, 0);
+        assert!(!check(&mut tat, 20_000, &quota).allowed);
+    }
+
+    #[test]
+    fn resets_fully_after_the_period() {
+        let quota = quota("3/60");
+        let mut tat = None;
+        for _ in 0..4 {
+            check(&mut tat, 0, &quota);
+        }
+
+        let decision = check(&mut tat, 60_000, &quota);
+        assert!(decision.allowed);
+        assert_eq!(decision.remaining, 2);
+        assert_eq!(decision.reset_seconds, 20);
+    }
+
+    #[test]
+    fn memory_store_keeps_keys_apart() {
+        let store = memory::MemoryStore::default();
+        let quota = quota("1/60");
+
+        assert!(store.check("a", 0, &quota).unwrap().allowed);
+        assert!(!store.check("a", 0, &quota).unwrap().allowed);
+        assert!(store.check("b", 0, &quota).unwrap().allowed);
+    }
+}
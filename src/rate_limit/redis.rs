@@ -0,0 +1,227 @@
+// Talks plain RESP over TCP, so any Redis-compatible server that supports
+// EVAL works (Redis, Valkey, KeyDB or a local stand-in for testing).
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    sync::Mutex,
+    time::Duration,
+};
+
+use super::{Quota, RateLimitDecision, RateLimitStore, StoreError};
+
+// Same algorithm as `super::gcra`, run atomically on the server
+const GCRA_SCRIPT: &str = r#"
+local now = tonumber(ARGV[1])
+local interval = tonumber(ARGV[2])
+local period = tonumber(ARGV[3])
+local tat = tonumber(redis.call('GET', KEYS[1]) or now)
+if tat < now then
+    tat = now
+end
+local new_tat = tat + interval
+if now < new_tat - period then
+    return {0, tat}
+end
+redis.call('SET', KEYS[1], new_tat, 'PX', new_tat - now)
+return {1, new_tat}
+"#;
+
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+// only what the GCRA script needs is kept
+enum Reply {
+    Status,
+    Error(String),
+    Integer(i64),
+    Bulk,
+    Array(Option<Vec<Reply>>),
+}
+
+struct Connection {
+    reader: BufReader<TcpStream>,
+}
+
+impl Connection {
+    fn open(url: &RedisUrl) -> Result<Connection, StoreError> {
+        let stream = TcpStream::connect(&url.address).map_err(io_error)?;
+        stream.set_read_timeout(Some(TIMEOUT)).map_err(io_error)?;
+        stream.set_write_timeout(Some(TIMEOUT)).map_err(io_error)?;
+
+        let mut connection = Connection {
+            reader: BufReader::new(stream),
+        };
+        if let Some(ref password) = url.password {
+            connection.command(&["AUTH", password])?;
+        }
+        if url.database != 0 {
+            connection.command(&["SELECT", &url.database.to_string()])?;
+        }
+
+        Ok(connection)
+    }
+
+    fn command(&mut self, args: &[&str]) -> Result<Reply, StoreError> {
+        let mut request = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args {
+            request.extend(format!("${}\r\n", arg.len()).as_bytes());
+            request.extend(arg.as_bytes());
+            request.extend(b"\r\n");
+        }
+        self.reader
+            .get_mut()
+            .write_all(&request)
+            .map_err(io_error)?;
+
+        match self.read_reply()? {
+            Reply::Error(message) => Err(StoreError(message)),
+            reply => Ok(reply),
+        }
+    }
+
+    fn read_line(&mut self) -> Result<String, StoreError> {
+        let mut line = String::new();
+        self.reader.read_line(&mut line).map_err(io_error)?;
+        if !line.ends_with("\r\n") {
+            return Err(StoreError("connection closed".to_string()));
+        }
+        line.truncate(line.len() - 2);
+        Ok(line)
+    }
+
+    fn read_reply(&mut self) -> Result<Reply, StoreError> {
+        let line = self.read_line()?;
+        let (kind, rest) = line.split_at(1.min(line.len()));
+        let parse_len = |rest: &str| {
+            rest.parse::<i64>()
+                .map_err(|_err| StoreError(format!("invalid length: {}", rest)))
+        };
+
+        match kind {
+            "+" => Ok(Reply::Status),
+            "-" => Ok(Reply::Error(rest.to_string())),
+            ":" => Ok(Reply::Integer(parse_len(rest)?)),
+            "$" => match parse_len(rest)? {
+                len if len < 0 => Ok(Reply::Bulk),
+                len => {
+                    let mut data = vec![0; len as usize + 2];
+                    self.reader.read_exact(&mut data).map_err(io_error)?;
+                    Ok(Reply::Bulk)
+                }
+            },
+            "*" => match parse_len(rest)? {
+                len if len < 0 => Ok(Reply::Array(None)),
+                len => (0..len)
+                    .map(|_| self.read_reply())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(|items| Reply::Array(Some(items))),
+            },
+            _ => Err(StoreError(format!("unexpected reply: {}", line))),
+        }
+    }
+}
+
+fn io_error(err: std::io::Error) -> StoreError {
+    StoreError(err.to_string())
+}
+
+// redis://[:password@]host[:port][/database]
+struct RedisUrl {
+    address: String,
+    password: Option<String>,
+    database: u32,
+}
+
+impl RedisUrl {
+    fn parse(url: &str) -> RedisUrl {
+        let rest = url
+            .strip_prefix("redis://")
+            .unwrap_or_else(|| panic!("REDIS_URL must start with redis://, got {}", url));
+        let (credentials, rest) = match rest.rsplit_once('@') {
+            Some((credentials, rest)) => (Some(credentials), rest),
+            None => (None, rest),
+        };
+        let (host, database) = match rest.split_once('/') {
+            Some((host, "")) => (host, 0),
+            Some((host, database)) => (
+                host,
+                database.parse().expect("REDIS_URL has an invalid database"),
+            ),
+            None => (rest, 0),
+        };
+
+        RedisUrl {
+            address: if host.contains(':') {
+                host.to_string()
+            } else {
+                format!("{}:6379", host)
+            },
+            // the username part is ignored, only `AUTH <password>` is sent
+            password: credentials
+                .map(|credentials| match credentials.split_once(':') {
+                    Some((_username, password)) => password.to_string(),
+                    None => credentials.to_string(),
+                })
+                .filter(|password| !password.is_empty()),
+            database,
+        }
+    }
+}
+
+pub struct RedisStore {
+    url: RedisUrl,
+    // a single connection is plenty for rate limiting, it is reopened after
+    // any error
+    connection: Mutex<Option<Connection>>,
+}
+
+impl RedisStore {
+    pub fn new(url: &str) -> RedisStore {
+        RedisStore {
+            url: RedisUrl::parse(url),
+            connection: Mutex::new(None),
+        }
+    }
+}
+
+impl RateLimitStore for RedisStore {
+    fn check(
+        &self,
+        key: &str,
+        now_ms: i64,
+        quota: &Quota,
+    ) -> Result<RateLimitDecision, StoreError> {
+        let mut guard = self
+            .connection
+            .lock()
+            .map_err(|_err| StoreError("poisoned lock".to_string()))?;
+
+        if guard.is_none() {
+            *guard = Some(Connection::open(&self.url)?);
+        }
+
+        let reply = guard.as_mut().unwrap().command(&[
+            "EVAL",
+            GCRA_SCRIPT,
+            "1",
+            &format!("rate_limit:{}", key),
+            &now_ms.to_string(),
+            &quota.emission_interval_ms().to_string(),
+            &quota.period_ms.to_string(),
+        ]);
+
+        let (allowed, tat) = match reply {
+            Ok(Reply::Array(Some(ref items))) => match items.as_slice() {
+                [Reply::Integer(allowed), Reply::Integer(tat)] => (*allowed == 1, *tat),
+                _ => return Err(StoreError("unexpected script result".to_string())),
+            },
+            Ok(_) => return Err(StoreError("unexpected script result".to_string())),
+            Err(err) => {
+                *guard = None;
+                return Err(err);
+            }
+        };
+
+        Ok(RateLimitDecision::from_tat(allowed, tat, now_ms, quota))
+    }
+}
@@ -1,9 +1,169 @@
+table! {
+    api_keys (id) {
+        id -> Int4,
+        user_id -> Int4,
+        name -> Text,
+        prefix -> Text,
+        key_hash -> Text,
+        scopes -> Array<Text>,
+        expires_at -> Nullable<Timestamptz>,
+        last_used_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    audit_events (id) {
+        id -> Int8,
+        event -> Text,
+        actor_user_id -> Nullable<Int4>,
+        target_user_id -> Nullable<Int4>,
+        ip -> Nullable<Text>,
+        user_agent -> Nullable<Text>,
+        details -> Jsonb,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    invites (id) {
+        id -> Int4,
+        code_hash -> Text,
+        prefix -> Text,
+        role -> Text,
+        max_uses -> Int4,
+        uses -> Int4,
+        expires_at -> Nullable<Timestamptz>,
+        created_by -> Nullable<Int4>,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    login_ip_attempts (ip) {
+        ip -> Text,
+        failed_attempts -> Int4,
+        last_failed_at -> Timestamptz,
+        locked_until -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    magic_links (id) {
+        id -> Int4,
+        user_id -> Int4,
+        token_hash -> Text,
+        nonce_hash -> Text,
+        created_at -> Timestamptz,
+        expires_at -> Timestamptz,
+        used_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    oauth_states (state) {
+        state -> Text,
+        provider -> Text,
+        code_verifier -> Text,
+        link_user_id -> Nullable<Int4>,
+        created_at -> Timestamptz,
+        nonce_hash -> Text,
+    }
+}
+
+table! {
+    password_resets (id) {
+        id -> Int4,
+        user_id -> Int4,
+        token_hash -> Text,
+        created_at -> Timestamptz,
+        expires_at -> Timestamptz,
+        used_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    sessions (id) {
+        id -> Int4,
+        user_id -> Int4,
+        user_agent -> Nullable<Text>,
+        ip -> Text,
+        created_at -> Timestamptz,
+        last_seen_at -> Timestamptz,
+        expires_at -> Timestamptz,
+    }
+}
+
+table! {
+    user_identities (id) {
+        id -> Int4,
+        user_id -> Int4,
+        provider -> Text,
+        subject -> Text,
+        email -> Nullable<Text>,
+        created_at -> Timestamptz,
+    }
+}
+
 table! {
     users (id) {
         id -> Int4,
         email -> Text,
         hashed_password -> Text,
-        salt -> Text,
         full_name -> Nullable<Text>,
+        role -> Text,
+        failed_login_attempts -> Int4,
+        last_failed_login_at -> Nullable<Timestamptz>,
+        locked_until -> Nullable<Timestamptz>,
+        disabled_at -> Nullable<Timestamptz>,
+        username -> Nullable<Text>,
+        display_name -> Nullable<Text>,
+        bio -> Nullable<Text>,
+        website -> Nullable<Text>,
+        social_links -> Jsonb,
+        avatar_url -> Nullable<Text>,
+    }
+}
+
+table! {
+    webauthn_challenges (id) {
+        id -> Int4,
+        user_id -> Nullable<Int4>,
+        challenge -> Text,
+        purpose -> Text,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    webauthn_credentials (id) {
+        id -> Int4,
+        user_id -> Int4,
+        credential_id -> Text,
+        public_key -> Bytea,
+        algorithm -> Int4,
+        sign_count -> Int8,
+        name -> Text,
+        created_at -> Timestamptz,
+        last_used_at -> Nullable<Timestamptz>,
     }
 }
+
+joinable!(api_keys -> users (user_id));
+joinable!(invites -> users (created_by));
+joinable!(sessions -> users (user_id));
+
+allow_tables_to_appear_in_same_query!(
+    api_keys,
+    audit_events,
+    invites,
+    login_ip_attempts,
+    magic_links,
+    oauth_states,
+    password_resets,
+    sessions,
+    user_identities,
+    users,
+    webauthn_challenges,
+    webauthn_credentials,
+);
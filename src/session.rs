@@ -0,0 +1,142 @@
+// Every login creates a session row and the token carries its id, so a
+// session can be listed and revoked on its own.
+
+use actix_web::{http::header, HttpRequest};
+use chrono::{Duration, Utc};
+use diesel::{ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, RunQueryDsl};
+use serde_json::json;
+
+use crate::audit;
+use crate::constants::{IMPERSONATION_EXPIRATION_SECONDS, JWT_EXPIRATION_SECONDS};
+use crate::extractor::client_ip::ClientIp;
+use crate::model::errors::{GlobalServiceError, ServiceError};
+use crate::model::session::NewSession;
+use crate::model::user::User;
+use crate::schema::{sessions, users};
+use crate::utils::generate_jwt;
+
+// Where a login came from, shown in the sessions list
+pub struct ClientInfo {
+    pub ip: String,
+    pub user_agent: Option<String>,
+}
+
+impl ClientInfo {
+    pub fn from_request(req: &HttpRequest) -> ClientInfo {
+        ClientInfo {
+            ip: ClientIp::from_request(req).to_string(),
+            user_agent: req
+                .headers()
+                .get(header::USER_AGENT)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string()),
+        }
+    }
+}
+
+// Starts a session for the user and returns the token bound to it, `method`
+// is how they logged in and goes to the audit log
+pub fn create_session_token(
+    conn: &PgConnection,
+    user: &User,
+    client: &ClientInfo,
+    method: &str,
+) -> Result<String, GlobalServiceError> {
+    if user.disabled_at.is_some() {
+        return Err(GlobalServiceError::Forbidden(ServiceError::AccountDisabled));
+    }
+    let session_id = insert_session(conn, user, client, JWT_EXPIRATION_SECONDS)?;
+
+    audit::record(
+        conn,
+        audit::LOGIN_SUCCEEDED,
+        Some(user.id),
+        Some(user.id),
+        client,
+        json!({ "method": method, "session_id": session_id }),
+    )?;
+
+    generate_jwt(&user.email, session_id, None, JWT_EXPIRATION_SECONDS)
+}
+
+// A short lived session as `user` for an admin looking into a problem, the
+// token remembers who the admin was
+pub fn create_impersonation_token(
+    conn: &PgConnection,
+    admin: &User,
+    user: &User,
+    client: &ClientInfo,
+) -> Result<String, GlobalServiceError> {
+    let session_id = insert_session(conn, user, client, IMPERSONATION_EXPIRATION_SECONDS)?;
+
+    audit::record(
+        conn,
+        audit::ADMIN_IMPERSONATED,
+        Some(admin.id),
+        Some(user.id),
+        client,
+        json!({ "session_id": session_id }),
+    )?;
+
+    generate_jwt(
+        &user.email,
+        session_id,
+        Some(admin.id),
+        IMPERSONATION_EXPIRATION_SECONDS,
+    )
+}
+
+fn insert_session(
+    conn: &PgConnection,
+    user: &User,
+    client: &ClientInfo,
+    lifetime_seconds: i64,
+) -> Result<i32, GlobalServiceError> {
+    let now = Utc::now();
+
+    // Their tokens can't be used anymore anyway
+    diesel::delete(
+        sessions::table
+            .filter(sessions::user_id.eq(user.id))
+            .filter(sessions::expires_at.lt(now)),
+    )
+    .execute(conn)?;
+
+    let session_id = diesel::insert_into(sessions::table)
+        .values(&NewSession {
+            user_id: user.id,
+            user_agent: client.user_agent.as_deref(),
+            ip: &client.ip,
+            expires_at: now + Duration::seconds(lifetime_seconds),
+        })
+        .returning(sessions::id)
+        .get_result::<i32>(conn)?;
+
+    Ok(session_id)
+}
+
+// False once the session was revoked or its user disabled, otherwise bumps its
+// last seen time
+pub fn touch_session(conn: &PgConnection, session_id: i32) -> Result<bool, diesel::result::Error> {
+    let now = Utc::now();
+    let last_seen_at = sessions::table
+        .inner_join(users::table)
+        .filter(sessions::id.eq(session_id))
+        .filter(users::disabled_at.is_null())
+        .select(sessions::last_seen_at)
+        .first::<chrono::DateTime<Utc>>(conn)
+        .optional()?;
+
+    match last_seen_at {
+        None => Ok(false),
+        Some(last_seen_at) => {
+            // At most once a minute, not on every request
+            if now - last_seen_at > Duration::minutes(1) {
+                diesel::update(sessions::table.find(session_id))
+                    .set(sessions::last_seen_at.eq(now))
+                    .execute(conn)?;
+            }
+            Ok(true)
+        }
+    }
+}
@@ -0,0 +1,160 @@
+// Logging goes through `tracing`, records from crates that still use `log`
+// are forwarded to it. Each request runs in a span carrying its request id,
+// see `middleware::request_id`. With an OTLP exporter configured the same
+// spans are sent to an OpenTelemetry collector as traces.
+
+use actix_web::{error::BlockingError, http::HeaderMap, web};
+use opentelemetry::{global, propagation::Extractor, trace::TracerProvider};
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    trace::{Sampler, SdkTracerProvider},
+    Resource,
+};
+use tracing::Instrument;
+use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+
+use crate::config::{LogConfig, LogFormat, TraceExporter, TracingConfig};
+
+// Returns the tracer provider when traces are exported, it has to be shut
+// down before exiting so the last batch is sent
+pub fn init(config: &LogConfig, tracing_config: &TracingConfig) -> Option<SdkTracerProvider> {
+    let filter = EnvFilter::try_new(&config.filter)
+        .unwrap_or_else(|_| panic!("RUST_LOG has an invalid value: {}", config.filter));
+
+    let (text, json) = match config.format {
+        LogFormat::Text => (Some(fmt::layer()), None),
+        // the span list puts the request id on every line of a request
+        LogFormat::Json => (
+            None,
+            Some(
+                fmt::layer()
+                    .json()
+                    .with_current_span(false)
+                    .with_span_list(true),
+            ),
+        ),
+    };
+
+    // `traceparent` is read whether or not traces are exported, so request
+    // logs can be matched with the caller's trace
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let provider = match tracing_config.exporter {
+        TraceExporter::None => None,
+        TraceExporter::Otlp => Some(tracer_provider(tracing_config)),
+    };
+    let otel = provider.as_ref().map(|provider| {
+        tracing_opentelemetry::layer().with_tracer(provider.tracer("fakhrusy-com-backend"))
+    });
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(text)
+        .with(json)
+        .with(otel)
+        .init();
+
+    provider
+}
+
+fn tracer_provider(config: &TracingConfig) -> SdkTracerProvider {
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .build()
+        .unwrap_or_else(|err| panic!("Failed to create OTLP exporter: {}", err));
+
+    // The batch processor exports from a thread of its own, the blocking
+    // client keeps it off actix's runtime
+    SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            config.sample_ratio,
+        ))))
+        .with_resource(
+            Resource::builder()
+                .with_service_name(config.service_name.clone())
+                .build(),
+        )
+        .build()
+}
+
+pub fn shutdown(provider: Option<SdkTracerProvider>) {
+    if let Some(provider) = provider {
+        if let Err(err) = provider.shutdown() {
+            tracing::warn!(error = %err, "failed to flush traces");
+        }
+    }
+}
+
+// Reads propagation headers such as `traceparent` from a request
+pub struct HeaderExtractor<'a>(pub &'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+// `web::block` in a span of its own, so time spent waiting for the thread
+// pool and the database shows up under the request. diesel 1.4 has no hook
+// for single statements, so this is as fine grained as query spans get.
+pub async fn block<F, I, E>(f: F) -> Result<I, BlockingError<E>>
+where
+    F: FnOnce() -> Result<I, E> + Send + 'static,
+    I: Send + 'static,
+    E: Send + std::fmt::Debug + 'static,
+{
+    let span = tracing::info_span!(
+        "db",
+        otel.kind = "client",
+        db.system = "postgresql",
+        otel.status_code = tracing::field::Empty,
+    );
+    let blocking_span = span.clone();
+
+    let result = web::block(move || blocking_span.in_scope(f))
+        .instrument(span.clone())
+        .await;
+    if result.is_err() {
+        span.record("otel.status_code", "ERROR");
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::{HeaderName, HeaderValue};
+
+    #[test]
+    fn header_extractor_reads_request_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            HeaderName::from_static("traceparent"),
+            HeaderValue::from_static("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+        );
+        headers.insert(
+            HeaderName::from_static("x-binary"),
+            HeaderValue::from_bytes(b"\xff").unwrap(),
+        );
+        let extractor = HeaderExtractor(&headers);
+
+        assert_eq!(
+            extractor.get("traceparent"),
+            Some("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")
+        );
+        // lookups are case insensitive like the header names themselves
+        assert!(extractor.get("TraceParent").is_some());
+        assert_eq!(extractor.get("tracestate"), None);
+        // not valid text, propagators can't use it
+        assert_eq!(extractor.get("x-binary"), None);
+
+        let mut keys = extractor.keys();
+        keys.sort_unstable();
+        assert_eq!(keys, vec!["traceparent", "x-binary"]);
+    }
+}
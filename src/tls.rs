@@ -0,0 +1,148 @@
+// HTTPS without a reverse proxy. The certificate is handed to rustls through a
+// resolver that a background thread swaps out when the PEM files change on
+// disk, so a renewed certificate is picked up by new connections without a
+// restart.
+
+use std::{
+    fs::{self, File},
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
+use rustls::{
+    internal::pemfile,
+    sign::{self, CertifiedKey},
+    ClientHello, NoClientAuth, ResolvesServerCert, ServerConfig,
+};
+
+use crate::config::TlsConfig;
+
+fn load_certified_key(cert_file: &Path, key_file: &Path) -> Result<CertifiedKey, String> {
+    let open = |path: &Path| {
+        File::open(path)
+            .map(BufReader::new)
+            .map_err(|err| format!("{}: {}", path.display(), err))
+    };
+
+    let certs = pemfile::certs(&mut open(cert_file)?)
+        .map_err(|_| format!("{}: invalid PEM", cert_file.display()))?;
+    if certs.is_empty() {
+        return Err(format!("{}: no certificates found", cert_file.display()));
+    }
+
+    let invalid_key = |_| format!("{}: invalid PEM", key_file.display());
+    let mut keys = pemfile::pkcs8_private_keys(&mut open(key_file)?).map_err(invalid_key)?;
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut open(key_file)?).map_err(invalid_key)?;
+    }
+    let key = keys
+        .first()
+        .ok_or_else(|| format!("{}: no PKCS#8 or RSA private key found", key_file.display()))?;
+    let signing_key = sign::any_supported_type(key)
+        .map_err(|_| format!("{}: unsupported private key", key_file.display()))?;
+
+    let certified_key = CertifiedKey::new(certs, Arc::new(signing_key));
+    // rejects a malformed certificate here rather than on every handshake
+    certified_key
+        .cross_check_end_entity_cert(None)
+        .map_err(|err| format!("{}: {}", cert_file.display(), err))?;
+
+    Ok(certified_key)
+}
+
+struct ReloadingResolver {
+    current: RwLock<CertifiedKey>,
+}
+
+impl ResolvesServerCert for ReloadingResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<CertifiedKey> {
+        self.current.read().ok().map(|key| key.clone())
+    }
+}
+
+fn modified_times(paths: &[&PathBuf]) -> Vec<Option<SystemTime>> {
+    paths
+        .iter()
+        .map(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok())
+        .collect()
+}
+
+// Files are compared by modification time, a renewal caught halfway through
+// writing them has changed again by the next check and is loaded in full then
+fn watch(config: TlsConfig, resolver: Arc<ReloadingResolver>) {
+    let interval = Duration::from_secs(config.reload_interval_seconds);
+    let paths = [&config.cert_file, &config.key_file];
+    let mut loaded = modified_times(&paths);
+
+    loop {
+        thread::sleep(interval);
+
+        let modified = modified_times(&paths);
+        if modified == loaded {
+            continue;
+        }
+        loaded = modified;
+
+        match load_certified_key(&config.cert_file, &config.key_file) {
+            Ok(key) => {
+                if let Ok(mut current) = resolver.current.write() {
+                    *current = key;
+                }
+                tracing::info!("reloaded TLS certificate");
+            }
+            Err(err) => tracing::warn!(error = %err, "failed to reload TLS certificate"),
+        }
+    }
+}
+
+// Fails when the certificate can't be loaded at startup, later reload errors
+// are logged and the previous certificate stays in use
+pub fn server_config(config: &TlsConfig) -> Result<ServerConfig, String> {
+    let resolver = Arc::new(ReloadingResolver {
+        current: RwLock::new(load_certified_key(&config.cert_file, &config.key_file)?),
+    });
+
+    if config.reload_interval_seconds > 0 {
+        let config = config.clone();
+        let resolver = resolver.clone();
+        thread::Builder::new()
+            .name("tls-reload".to_string())
+            .spawn(move || watch(config, resolver))
+            .map_err(|err| err.to_string())?;
+    }
+
+    let mut server_config = ServerConfig::new(NoClientAuth::new());
+    server_config.cert_resolver = resolver;
+
+    Ok(server_config)
+}
+
+// Default service of the plain http redirect listeners
+pub async fn redirect_handler(req: HttpRequest, port: web::Data<u16>) -> HttpResponse {
+    let connection_info = req.connection_info();
+    let host = connection_info.host();
+    // drops the port of the http listener, keeps IPv6 brackets
+    let hostname = match host.rsplit_once(':') {
+        Some((hostname, port)) if !port.contains(']') => hostname,
+        _ => host,
+    };
+    let path = req
+        .uri()
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or("/");
+
+    let location = match **port {
+        443 => format!("https://{}{}", hostname, path),
+        port => format!("https://{}:{}{}", hostname, port, path),
+    };
+
+    // 308 so clients repeat the method and body
+    HttpResponse::PermanentRedirect()
+        .header(header::LOCATION, location)
+        .finish()
+}
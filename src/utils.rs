@@ -1,10 +1,17 @@
+use std::convert::TryFrom;
 use std::env;
 
+use crate::config::Argon2Config;
+use crate::constants::RESERVED_USERNAMES;
+use crate::metrics;
 use crate::model::errors::GlobalServiceError;
 use actix_web::Result;
 use argon2::{
-    password_hash::{rand_core::OsRng, SaltString},
-    Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
+    password_hash::{
+        rand_core::{OsRng, RngCore},
+        SaltString,
+    },
+    Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version,
 };
 use chrono::Utc;
 use dotenv::dotenv;
@@ -13,35 +20,50 @@ use jsonwebtoken::{
 };
 use regex::Regex;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use url::Url;
 
-pub struct HashedPasswordAndSalt {
-    pub hashed_password: String,
-    pub salt: String,
+fn argon2_hasher(config: &Argon2Config) -> Argon2<'static> {
+    Argon2::new(config.algorithm, Version::V0x13, config.params())
 }
 
-pub fn hash_password(password: &str) -> Result<HashedPasswordAndSalt, GlobalServiceError> {
+pub fn hash_password(password: &str, config: &Argon2Config) -> Result<String, GlobalServiceError> {
     let salt = SaltString::generate(&mut OsRng);
-    let argon2 = Argon2::default();
-
-    let hashed_password = argon2
-        .hash_password(password.as_bytes(), &salt)
-        .map_err(|_err| GlobalServiceError::InternalServerError);
+    let argon2 = argon2_hasher(config);
 
-    match hashed_password {
-        Err(err) => Err(err),
-        Ok(data) => Ok(HashedPasswordAndSalt {
-            hashed_password: data.to_string(),
-            salt: salt.as_str().to_string(),
-        }),
-    }
+    metrics::time_password_hash("hash", || argon2.hash_password(password.as_bytes(), &salt))
+        .map(|hash| hash.to_string())
+        .map_err(|_err| GlobalServiceError::InternalServerError)
 }
 
 pub fn verify_password(password: &str, hash_str: &str) -> Result<(), GlobalServiceError> {
-    let hash = PasswordHash::new(hash_str);
+    let hash =
+        PasswordHash::new(hash_str).map_err(|_err| GlobalServiceError::InternalServerError)?;
+    // the algorithm, version and parameters are taken from the stored hash
     let argon2 = Argon2::default();
-    argon2
-        .verify_password(password.as_bytes(), &hash.unwrap())
-        .map_err(|_err| GlobalServiceError::InternalServerError)
+    metrics::time_password_hash("verify", || {
+        argon2.verify_password(password.as_bytes(), &hash)
+    })
+    .map_err(|_err| GlobalServiceError::InternalServerError)
+}
+
+// Whether a stored hash was made with other settings than the configured ones
+// and should be replaced after the next successful verification.
+pub fn password_needs_rehash(hash_str: &str, config: &Argon2Config) -> bool {
+    let hash = match PasswordHash::new(hash_str) {
+        Ok(hash) => hash,
+        Err(_) => return true,
+    };
+    let params = match Params::try_from(&hash) {
+        Ok(params) => params,
+        Err(_) => return true,
+    };
+
+    hash.algorithm != config.algorithm.ident()
+        || hash.version != Some(Version::V0x13.into())
+        || params.m_cost() != config.memory_kib
+        || params.t_cost() != config.iterations
+        || params.p_cost() != config.parallelism
 }
 
 #[derive(Serialize, Deserialize)]
@@ -51,19 +73,30 @@ pub struct JWTClaim {
     // expiration time
     pub exp: i64,
     pub email: String,
+    // row in `sessions`, revoking it invalidates the token
+    pub sid: i32,
+    // the admin acting as this user
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub imp: Option<i32>,
 }
 
-pub fn generate_jwt(email: &String) -> Result<String, GlobalServiceError> {
+pub fn generate_jwt(
+    email: &String,
+    session_id: i32,
+    impersonator_id: Option<i32>,
+    lifetime_seconds: i64,
+) -> Result<String, GlobalServiceError> {
     dotenv().ok();
     let jwt_secret = env::var("JWT_SECRET").expect("JWT_SECRET must be set");
     let jwt_secret_bytes = jwt_secret.as_bytes();
     let now = Utc::now().timestamp_nanos() / 1_000_000_000; // convert nano second to second
-    let one_week_in_second = 60 * 60 * 24 * 7;
 
     let jwt_claim = JWTClaim {
         iat: now,
-        exp: now + one_week_in_second,
+        exp: now + lifetime_seconds,
         email: email.to_string(),
+        sid: session_id,
+        imp: impersonator_id,
     };
 
     let jwt_token = jsonwebtoken::encode::<JWTClaim>(
@@ -87,10 +120,14 @@ pub fn decode_jwt(token: String) -> JWTResult<TokenData<JWTClaim>> {
         &Validation::default(),
     );
 
-    return decoded;
+    decoded
+}
+
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
 }
 
-pub fn validate_email(email: &String) -> bool {
+pub fn validate_email(email: &str) -> bool {
     let email_regex = Regex::new(
         r"^([a-z0-9_+]([a-z0-9_+.]*[a-z0-9_+])?)@([a-z0-9]+([\-\.]{1}[a-z0-9]+)*\.[a-z]{2,6})",
     )
@@ -98,3 +135,46 @@ pub fn validate_email(email: &String) -> bool {
 
     email_regex.is_match(email)
 }
+
+pub fn normalize_username(username: &str) -> String {
+    username.trim().to_lowercase()
+}
+
+// Expects a normalized username, it ends up in profile URLs
+pub fn validate_username(username: &str) -> bool {
+    let username_regex = Regex::new(r"^[a-z0-9][a-z0-9_-]{1,28}[a-z0-9]$").unwrap();
+
+    username_regex.is_match(username) && !RESERVED_USERNAMES.contains(&username)
+}
+
+// Links shown on public profiles, only http(s) so they can't run script
+pub fn validate_web_url(value: &str) -> bool {
+    match Url::parse(value) {
+        Ok(url) => (url.scheme() == "http" || url.scheme() == "https") && url.has_host(),
+        Err(_) => false,
+    }
+}
+
+// Hex encoded random bytes, for tokens handed out to clients
+pub fn generate_random_token(byte_length: usize) -> String {
+    let mut bytes = vec![0u8; byte_length];
+    OsRng.fill_bytes(&mut bytes);
+
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// For secrets we only need to recognise again, like one-time tokens
+pub fn sha256_hex(value: &str) -> String {
+    Sha256::digest(value.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
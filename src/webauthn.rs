@@ -0,0 +1,260 @@
+// The parts of WebAuthn (https://www.w3.org/TR/webauthn-2/) we need for
+// passkeys: parsing what the browser sends and checking the signatures.
+// Attestation statements are not verified, we ask for "none" since we don't
+// restrict which authenticators can be used.
+
+use std::collections::BTreeMap;
+
+use rand_core::{OsRng, RngCore};
+use ring::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey};
+use serde::Deserialize;
+use serde_cbor::Value;
+use sha2::{Digest, Sha256};
+
+use crate::config::WebauthnConfig;
+
+// COSE algorithm identifiers we offer and can verify
+pub const COSE_ALG_ES256: i64 = -7;
+pub const COSE_ALG_EDDSA: i64 = -8;
+pub const COSE_ALG_RS256: i64 = -257;
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+#[derive(Debug)]
+pub struct WebauthnError(pub &'static str);
+
+pub fn base64url_encode(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+pub fn base64url_decode(value: &str) -> Result<Vec<u8>, WebauthnError> {
+    // browsers may or may not pad
+    base64::decode_config(value.trim_end_matches('='), base64::URL_SAFE_NO_PAD)
+        .map_err(|_err| WebauthnError("invalid base64url"))
+}
+
+pub fn generate_challenge() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+
+    base64url_encode(&bytes)
+}
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    ceremony: String,
+    challenge: String,
+    origin: String,
+}
+
+// Checks clientDataJSON and returns its SHA-256, which is what gets signed
+pub fn verify_client_data(
+    client_data_json: &[u8],
+    ceremony: &str,
+    challenge: &str,
+    config: &WebauthnConfig,
+) -> Result<Vec<u8>, WebauthnError> {
+    let client_data: ClientData = serde_json::from_slice(client_data_json)
+        .map_err(|_err| WebauthnError("invalid client data"))?;
+
+    if client_data.ceremony != ceremony {
+        return Err(WebauthnError("unexpected ceremony"));
+    }
+    if client_data.challenge.trim_end_matches('=') != challenge {
+        return Err(WebauthnError("challenge mismatch"));
+    }
+    if client_data.origin != config.origin {
+        return Err(WebauthnError("origin mismatch"));
+    }
+
+    Ok(Sha256::digest(client_data_json).to_vec())
+}
+
+pub struct AuthenticatorData {
+    pub user_verified: bool,
+    pub sign_count: u32,
+    // only present during registration
+    pub credential_id: Option<Vec<u8>>,
+    pub credential_public_key: Option<Vec<u8>>,
+}
+
+pub fn parse_authenticator_data(
+    data: &[u8],
+    config: &WebauthnConfig,
+) -> Result<AuthenticatorData, WebauthnError> {
+    if data.len() < 37 {
+        return Err(WebauthnError("authenticator data too short"));
+    }
+
+    if data[..32] != Sha256::digest(config.rp_id.as_bytes())[..] {
+        return Err(WebauthnError("relying party mismatch"));
+    }
+
+    let flags = data[32];
+    if flags & FLAG_USER_PRESENT == 0 {
+        return Err(WebauthnError("user not present"));
+    }
+    let sign_count = u32::from_be_bytes([data[33], data[34], data[35], data[36]]);
+
+    let (credential_id, credential_public_key) = if flags & FLAG_ATTESTED_CREDENTIAL_DATA != 0 {
+        // aaguid (16) | credential id length (2) | credential id | COSE key
+        let rest = data
+            .get(37 + 16..)
+            .ok_or(WebauthnError("attested credential data too short"))?;
+        if rest.len() < 2 {
+            return Err(WebauthnError("attested credential data too short"));
+        }
+        let id_length = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+        let credential_id = rest
+            .get(2..2 + id_length)
+            .ok_or(WebauthnError("attested credential data too short"))?;
+
+        // the COSE key may be followed by extensions, only take its own bytes
+        let key_bytes = &rest[2 + id_length..];
+        let mut deserializer = serde_cbor::Deserializer::from_slice(key_bytes);
+        serde::Deserialize::deserialize(&mut deserializer)
+            .map(|_: Value| ())
+            .map_err(|_err| WebauthnError("invalid credential public key"))?;
+        let key_length = deserializer.byte_offset();
+
+        (
+            Some(credential_id.to_vec()),
+            Some(key_bytes[..key_length].to_vec()),
+        )
+    } else {
+        (None, None)
+    };
+
+    Ok(AuthenticatorData {
+        user_verified: flags & FLAG_USER_VERIFIED != 0,
+        sign_count,
+        credential_id,
+        credential_public_key,
+    })
+}
+
+// Returns authData from an attestationObject
+pub fn parse_attestation_object(attestation_object: &[u8]) -> Result<Vec<u8>, WebauthnError> {
+    let value: Value = serde_cbor::from_slice(attestation_object)
+        .map_err(|_err| WebauthnError("invalid attestation object"))?;
+
+    match value {
+        Value::Map(map) => match map.get(&Value::Text("authData".to_string())) {
+            Some(Value::Bytes(auth_data)) => Ok(auth_data.clone()),
+            _ => Err(WebauthnError("missing authenticator data")),
+        },
+        _ => Err(WebauthnError("invalid attestation object")),
+    }
+}
+
+// COSE key parameters (https://www.rfc-editor.org/rfc/rfc8152#section-13)
+const COSE_KEY_KTY: i128 = 1;
+const COSE_KEY_ALG: i128 = 3;
+const COSE_KTY_OKP: i128 = 1;
+const COSE_KTY_EC2: i128 = 2;
+const COSE_KTY_RSA: i128 = 3;
+const COSE_CRV_P256: i128 = 1;
+const COSE_CRV_ED25519: i128 = 6;
+
+fn cose_field(map: &BTreeMap<Value, Value>, label: i128) -> Option<&Value> {
+    map.get(&Value::Integer(label))
+}
+
+fn cose_integer(map: &BTreeMap<Value, Value>, label: i128) -> Option<i128> {
+    match cose_field(map, label) {
+        Some(Value::Integer(value)) => Some(*value),
+        _ => None,
+    }
+}
+
+fn cose_bytes(map: &BTreeMap<Value, Value>, label: i128) -> Result<&[u8], WebauthnError> {
+    match cose_field(map, label) {
+        Some(Value::Bytes(bytes)) => Ok(bytes),
+        _ => Err(WebauthnError("invalid credential public key")),
+    }
+}
+
+// Parses a COSE key, its key type and curve have to be the ones its
+// algorithm is defined for
+fn parse_cose_key(cose_key: &[u8]) -> Result<(i64, BTreeMap<Value, Value>), WebauthnError> {
+    let map: BTreeMap<Value, Value> = serde_cbor::from_slice(cose_key)
+        .map_err(|_err| WebauthnError("invalid credential public key"))?;
+
+    let algorithm = match cose_integer(&map, COSE_KEY_ALG) {
+        Some(alg) if alg == COSE_ALG_ES256 as i128 => COSE_ALG_ES256,
+        Some(alg) if alg == COSE_ALG_EDDSA as i128 => COSE_ALG_EDDSA,
+        Some(alg) if alg == COSE_ALG_RS256 as i128 => COSE_ALG_RS256,
+        _ => return Err(WebauthnError("unsupported algorithm")),
+    };
+    let (kty, crv) = match algorithm {
+        COSE_ALG_ES256 => (COSE_KTY_EC2, Some(COSE_CRV_P256)),
+        COSE_ALG_EDDSA => (COSE_KTY_OKP, Some(COSE_CRV_ED25519)),
+        _ => (COSE_KTY_RSA, None),
+    };
+    if cose_integer(&map, COSE_KEY_KTY) != Some(kty) {
+        return Err(WebauthnError("key type does not match the algorithm"));
+    }
+    // -1 is the curve for EC2 and OKP keys, the modulus for RSA
+    if crv.is_some() && cose_integer(&map, -1) != crv {
+        return Err(WebauthnError("curve does not match the algorithm"));
+    }
+
+    Ok((algorithm, map))
+}
+
+// Returns the COSE algorithm of a stored public key, rejecting keys we can't
+// verify signatures for
+pub fn cose_algorithm(cose_key: &[u8]) -> Result<i64, WebauthnError> {
+    parse_cose_key(cose_key).map(|(algorithm, _)| algorithm)
+}
+
+// The assertion signature covers authenticatorData || SHA-256(clientDataJSON)
+pub fn verify_signature(
+    cose_key: &[u8],
+    authenticator_data: &[u8],
+    client_data_hash: &[u8],
+    signature_bytes: &[u8],
+) -> Result<(), WebauthnError> {
+    let (algorithm, map) = parse_cose_key(cose_key)?;
+    let message = [authenticator_data, client_data_hash].concat();
+    let invalid_signature = |_err| WebauthnError("invalid signature");
+
+    match algorithm {
+        COSE_ALG_ES256 => {
+            // uncompressed point
+            let point = [&[0x04][..], cose_bytes(&map, -2)?, cose_bytes(&map, -3)?].concat();
+            UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_ASN1, point)
+                .verify(&message, signature_bytes)
+                .map_err(invalid_signature)
+        }
+        COSE_ALG_EDDSA => UnparsedPublicKey::new(&signature::ED25519, cose_bytes(&map, -2)?)
+            .verify(&message, signature_bytes)
+            .map_err(invalid_signature),
+        _ => RsaPublicKeyComponents {
+            n: cose_bytes(&map, -1)?,
+            e: cose_bytes(&map, -2)?,
+        }
+        .verify(
+            &signature::RSA_PKCS1_2048_8192_SHA256,
+            &message,
+            signature_bytes,
+        )
+        .map_err(invalid_signature),
+    }
+}
+
+// Authenticators that keep a counter must move it forward, anything else
+// hints at a cloned key. Only one that never counted may keep sending 0.
+pub fn check_sign_count(stored: i64, received: i64) -> Result<(), WebauthnError> {
+    if stored == 0 && received == 0 {
+        return Ok(());
+    }
+    if received <= stored {
+        return Err(WebauthnError("sign count went backwards"));
+    }
+
+    Ok(())
+}
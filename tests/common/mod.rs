@@ -0,0 +1,68 @@
+// Shared by the tests that need a database. They run against DATABASE_URL and
+// are skipped when it isn't set, every test creates its own users so they can
+// share the database with each other and with development data.
+
+#![allow(dead_code)]
+
+use std::env;
+
+use diesel::{
+    r2d2::{self, ConnectionManager},
+    PgConnection, RunQueryDsl,
+};
+use dotenv::dotenv;
+use fakhrusy_com_backend::config::Argon2Config;
+use fakhrusy_com_backend::constants::ROLE_USER;
+use fakhrusy_com_backend::migrations;
+use fakhrusy_com_backend::model::db::Pool;
+use fakhrusy_com_backend::model::user::{NewUser, User};
+use fakhrusy_com_backend::schema::users;
+use fakhrusy_com_backend::utils::{generate_random_token, hash_password};
+
+pub const PASSWORD: &str = "correct horse battery staple";
+
+pub fn pool() -> Option<Pool> {
+    dotenv().ok();
+    let database_url = match env::var("DATABASE_URL") {
+        Ok(database_url) => database_url,
+        Err(_) => {
+            eprintln!("DATABASE_URL is not set, skipping");
+            return None;
+        }
+    };
+
+    let pool = r2d2::Pool::builder()
+        .build(ConnectionManager::<PgConnection>::new(database_url))
+        .expect("Failed to create pool");
+    migrations::run_pending(&pool.get().unwrap()).expect("Failed to run migrations");
+
+    Some(pool)
+}
+
+// Cheap parameters, the tests don't need strong hashes
+pub fn argon2_config() -> Argon2Config {
+    Argon2Config {
+        memory_kib: 1024,
+        iterations: 1,
+        ..Argon2Config::from_env().unwrap()
+    }
+}
+
+pub fn unique_email(prefix: &str) -> String {
+    format!("{}-{}@test.invalid", prefix, generate_random_token(8))
+}
+
+pub fn create_user(conn: &PgConnection, email: &str) -> User {
+    let hashed_password = hash_password(PASSWORD, &argon2_config()).unwrap();
+
+    diesel::insert_into(users::table)
+        .values(&NewUser {
+            email,
+            hashed_password: &hashed_password,
+            full_name: "Test User",
+            username: None,
+            role: ROLE_USER,
+        })
+        .get_result(conn)
+        .unwrap()
+}
@@ -0,0 +1,103 @@
+// Double-submit CSRF protection of cookie sessions, and the schemes that
+// don't need it, against the database
+
+mod common;
+
+use std::env;
+
+use actix_web::{cookie::Cookie, http::Method, test, web, App, HttpResponse};
+use diesel::RunQueryDsl;
+use fakhrusy_com_backend::api_key::generate_api_key;
+use fakhrusy_com_backend::config::AuthCookieConfig;
+use fakhrusy_com_backend::constants::{API_KEY_SCOPE_WRITE, CSRF_TOKEN_HEADER};
+use fakhrusy_com_backend::middleware::auth::Authentication;
+use fakhrusy_com_backend::model::api_key::NewApiKey;
+use fakhrusy_com_backend::schema::api_keys;
+use fakhrusy_com_backend::session::{create_session_token, ClientInfo};
+use fakhrusy_com_backend::utils::sha256_hex;
+
+#[actix_rt::test]
+async fn cookie_sessions_need_the_csrf_token_other_schemes_do_not() {
+    let pool = match common::pool() {
+        Some(pool) => pool,
+        None => return,
+    };
+    if env::var("JWT_SECRET").is_err() {
+        env::set_var("JWT_SECRET", "test-secret");
+    }
+
+    let conn = pool.get().unwrap();
+    let user = common::create_user(&conn, &common::unique_email("csrf"));
+    let client = ClientInfo {
+        ip: "192.0.2.1".to_string(),
+        user_agent: None,
+    };
+    let token = create_session_token(&conn, &user, &client, "password").unwrap();
+    let (prefix, key) = generate_api_key();
+    diesel::insert_into(api_keys::table)
+        .values(&NewApiKey {
+            user_id: user.id,
+            name: "csrf test",
+            prefix: &prefix,
+            key_hash: &sha256_hex(&key),
+            scopes: &[API_KEY_SCOPE_WRITE.to_string()],
+            expires_at: None,
+        })
+        .execute(&conn)
+        .unwrap();
+
+    let cookie_config = AuthCookieConfig::from_env().unwrap();
+    let mut app = test::init_service(
+        App::new()
+            .data(pool.clone())
+            .data(cookie_config.clone())
+            .wrap(Authentication)
+            .default_service(web::route().to(HttpResponse::Ok)),
+    )
+    .await;
+    let session_cookie = Cookie::new(cookie_config.name.clone(), token.clone());
+    let csrf_cookie = Cookie::new(cookie_config.csrf_name.clone(), "abc123");
+
+    let request = |method| test::TestRequest::with_uri("/v1/profile").method(method);
+    let cases = [
+        (
+            "cookie, safe method",
+            request(Method::GET).cookie(session_cookie.clone()),
+            200,
+        ),
+        (
+            "cookie, no header",
+            request(Method::POST)
+                .cookie(session_cookie.clone())
+                .cookie(csrf_cookie.clone()),
+            403,
+        ),
+        (
+            "cookie, matching header",
+            request(Method::POST)
+                .cookie(session_cookie.clone())
+                .cookie(csrf_cookie.clone())
+                .header(CSRF_TOKEN_HEADER, "abc123"),
+            200,
+        ),
+        (
+            "bearer, no header",
+            request(Method::POST)
+                .cookie(csrf_cookie.clone())
+                .header("Authorization", format!("Bearer {}", token)),
+            200,
+        ),
+        (
+            "api key next to a session cookie, no header",
+            request(Method::DELETE)
+                .cookie(session_cookie.clone())
+                .header("Authorization", format!("ApiKey {}", key)),
+            200,
+        ),
+    ];
+
+    for (case, req, status) in cases {
+        let res = test::call_service(&mut app, req.to_request()).await;
+        assert_eq!(res.status(), status, "{}", case);
+    }
+}
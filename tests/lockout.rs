@@ -0,0 +1,193 @@
+// Failed login counting and lockouts, against the database
+
+mod common;
+
+use std::thread;
+
+use actix_web::{test, web, App};
+use chrono::{Duration, Utc};
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use fakhrusy_com_backend::api::auth::lockout;
+use fakhrusy_com_backend::api::auth::login::login_handler;
+use fakhrusy_com_backend::config::{
+    AuthCookieConfig, LoginThrottleConfig, MailConfig, WebauthnConfig,
+};
+use fakhrusy_com_backend::model::errors::GlobalServiceError;
+use fakhrusy_com_backend::model::user::User;
+use fakhrusy_com_backend::schema::users;
+use fakhrusy_com_backend::utils::generate_random_token;
+use serde_json::{json, Value};
+
+fn throttle_config() -> LoginThrottleConfig {
+    LoginThrottleConfig {
+        max_attempts_per_account: 3,
+        max_attempts_per_ip: 1000,
+        attempt_window_seconds: 15 * 60,
+        lockout_base_seconds: 60,
+        lockout_max_seconds: 60 * 60,
+    }
+}
+
+// Documentation range, a fresh address per test so their counts don't mix
+fn unique_ip() -> String {
+    format!("2001:db8::{}", generate_random_token(2))
+}
+
+#[test]
+fn concurrent_failures_are_all_counted() {
+    let pool = match common::pool() {
+        Some(pool) => pool,
+        None => return,
+    };
+    let user = common::create_user(&pool.get().unwrap(), &common::unique_email("lockout"));
+    let config = LoginThrottleConfig {
+        max_attempts_per_account: 1000,
+        ..throttle_config()
+    };
+
+    thread::scope(|scope| {
+        for _ in 0..16 {
+            scope.spawn(|| {
+                lockout::record_account_failure(&pool.get().unwrap(), &user, &config).unwrap();
+            });
+        }
+    });
+
+    let failed_attempts = users::table
+        .find(user.id)
+        .select(users::failed_login_attempts)
+        .first::<i32>(&pool.get().unwrap())
+        .unwrap();
+    assert_eq!(failed_attempts, 16);
+}
+
+#[test]
+fn failures_outside_the_window_start_over() {
+    let pool = match common::pool() {
+        Some(pool) => pool,
+        None => return,
+    };
+    let conn = pool.get().unwrap();
+    let user = common::create_user(&conn, &common::unique_email("lockout"));
+    let config = throttle_config();
+
+    assert_eq!(
+        lockout::record_account_failure(&conn, &user, &config).unwrap(),
+        (1, None)
+    );
+    assert_eq!(
+        lockout::record_account_failure(&conn, &user, &config).unwrap(),
+        (2, None)
+    );
+
+    diesel::update(users::table.find(user.id))
+        .set(users::last_failed_login_at.eq(Utc::now() - Duration::hours(1)))
+        .execute(&conn)
+        .unwrap();
+    assert_eq!(
+        lockout::record_account_failure(&conn, &user, &config).unwrap(),
+        (1, None)
+    );
+}
+
+#[test]
+fn locks_the_account_and_doubles_the_lockout() {
+    let pool = match common::pool() {
+        Some(pool) => pool,
+        None => return,
+    };
+    let conn = pool.get().unwrap();
+    let user = common::create_user(&conn, &common::unique_email("lockout"));
+    let config = throttle_config();
+    let reload = || users::table.find(user.id).first::<User>(&conn).unwrap();
+
+    lockout::record_account_failure(&conn, &user, &config).unwrap();
+    lockout::record_account_failure(&conn, &user, &config).unwrap();
+    assert!(!lockout::account_locked(&reload()));
+
+    let before = Utc::now();
+    let (failed_attempts, locked_until) =
+        lockout::record_account_failure(&conn, &user, &config).unwrap();
+    assert_eq!(failed_attempts, 3);
+    let locked_until = locked_until.unwrap();
+    assert!(locked_until >= before + Duration::seconds(60));
+    assert!(locked_until <= Utc::now() + Duration::seconds(60));
+    assert!(lockout::account_locked(&reload()));
+
+    let (_, locked_until) = lockout::record_account_failure(&conn, &user, &config).unwrap();
+    assert!(locked_until.unwrap() >= before + Duration::seconds(120));
+
+    lockout::reset_account(&conn, user.id).unwrap();
+    let user = reload();
+    assert!(!lockout::account_locked(&user));
+    assert_eq!(user.failed_login_attempts, 0);
+}
+
+#[test]
+fn locks_the_ip() {
+    let pool = match common::pool() {
+        Some(pool) => pool,
+        None => return,
+    };
+    let conn = pool.get().unwrap();
+    let ip = unique_ip();
+    let config = LoginThrottleConfig {
+        max_attempts_per_ip: 2,
+        ..throttle_config()
+    };
+
+    lockout::record_ip_failure(&conn, &ip, &config).unwrap();
+    assert!(lockout::check_ip(&conn, &ip).is_ok());
+
+    lockout::record_ip_failure(&conn, &ip, &config).unwrap();
+    match lockout::check_ip(&conn, &ip) {
+        Err(GlobalServiceError::TooManyRequests(_, retry_after)) => {
+            assert!(retry_after > 0 && retry_after <= 60)
+        }
+        _ => panic!("expected the IP to be locked"),
+    }
+}
+
+#[actix_rt::test]
+async fn locked_account_answers_like_an_unknown_email() {
+    let pool = match common::pool() {
+        Some(pool) => pool,
+        None => return,
+    };
+    let conn = pool.get().unwrap();
+    let user = common::create_user(&conn, &common::unique_email("lockout"));
+    diesel::update(users::table.find(user.id))
+        .set(users::locked_until.eq(Utc::now() + Duration::hours(1)))
+        .execute(&conn)
+        .unwrap();
+
+    let mut app = test::init_service(
+        App::new()
+            .data(pool.clone())
+            .data(AuthCookieConfig::from_env().unwrap())
+            .data(WebauthnConfig::from_env().unwrap())
+            .data(throttle_config())
+            .data(common::argon2_config())
+            .data(MailConfig::from_env().unwrap())
+            .route("/login", web::post().to(login_handler)),
+    )
+    .await;
+
+    // even the right password doesn't tell them apart
+    let mut responses = Vec::new();
+    for email in [common::unique_email("nobody"), user.email.clone()] {
+        let req = test::TestRequest::post()
+            .uri("/login")
+            .peer_addr(format!("[{}]:4000", unique_ip()).parse().unwrap())
+            .set_json(&json!({ "email": email, "password": common::PASSWORD }))
+            .to_request();
+        let res = test::call_service(&mut app, req).await;
+        let status = res.status();
+        let body: Value = test::read_body_json(res).await;
+        responses.push((status, body));
+    }
+    let (unknown, locked) = (&responses[0], &responses[1]);
+
+    assert_eq!(unknown.0, 401);
+    assert_eq!(locked, unknown);
+}
@@ -0,0 +1,489 @@
+// The authorization code flow against an in-process identity provider that
+// checks PKCE the way a real one would, and the database
+
+mod common;
+
+use std::{
+    collections::HashMap,
+    env,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use actix_web::{dev::ServiceResponse, test, web, App};
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use fakhrusy_com_backend::api::auth::oauth::{authorize_handler, callback_handler};
+use fakhrusy_com_backend::api::profile::identities::link_identity_handler;
+use fakhrusy_com_backend::config::{
+    AuthCookieConfig, OAuthConfig, OAuthProviderConfig, RegistrationConfig, RegistrationMode,
+};
+use fakhrusy_com_backend::middleware::auth::Authentication;
+use fakhrusy_com_backend::model::db::Pool;
+use fakhrusy_com_backend::oauth::pkce_challenge;
+use fakhrusy_com_backend::schema::{user_identities, users};
+use fakhrusy_com_backend::session::{create_session_token, ClientInfo};
+use fakhrusy_com_backend::utils::generate_random_token;
+use serde_json::{json, Value};
+use url::{form_urlencoded, Url};
+
+const CLIENT_ID: &str = "blog";
+const CLIENT_SECRET: &str = "blog-secret";
+const REDIRECT_URL: &str = "https://blog.test.invalid/oauth/callback";
+
+struct IssuedCode {
+    challenge: String,
+    redirect_uri: String,
+    profile: Value,
+}
+
+#[derive(Default)]
+struct MockIdp {
+    // who the next /authorize logs in as
+    profile: Value,
+    codes: HashMap<String, IssuedCode>,
+    access_tokens: HashMap<String, Value>,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: HashMap<String, String>,
+    headers: HashMap<String, String>,
+    body: String,
+}
+
+fn read_request(stream: &TcpStream) -> Option<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let url = Url::parse(&format!("http://idp{}", parts.next()?)).ok()?;
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':')?;
+        headers.insert(name.to_ascii_lowercase(), value.trim().to_string());
+    }
+
+    let len = headers
+        .get("content-length")
+        .and_then(|len| len.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body).ok()?;
+
+    Some(Request {
+        method,
+        path: url.path().to_string(),
+        query: url.query_pairs().into_owned().collect(),
+        headers,
+        body: String::from_utf8(body).ok()?,
+    })
+}
+
+fn handle(idp: &mut MockIdp, req: Request) -> (u16, Vec<(&'static str, String)>, Value) {
+    let invalid_grant = (400, vec![], json!({ "error": "invalid_grant" }));
+
+    match (req.method.as_str(), req.path.as_str()) {
+        ("GET", "/authorize") => {
+            let param = |name: &str| req.query.get(name).cloned().unwrap_or_default();
+            if param("response_type") != "code"
+                || param("client_id") != CLIENT_ID
+                || param("code_challenge_method") != "S256"
+            {
+                return (400, vec![], json!({ "error": "invalid_request" }));
+            }
+
+            let code = generate_random_token(16);
+            let mut location = Url::parse(&param("redirect_uri")).unwrap();
+            location
+                .query_pairs_mut()
+                .append_pair("code", &code)
+                .append_pair("state", &param("state"));
+            idp.codes.insert(
+                code,
+                IssuedCode {
+                    challenge: param("code_challenge"),
+                    redirect_uri: param("redirect_uri"),
+                    profile: idp.profile.clone(),
+                },
+            );
+            (302, vec![("Location", location.into())], json!({}))
+        }
+        ("POST", "/token") => {
+            let form: HashMap<String, String> = form_urlencoded::parse(req.body.as_bytes())
+                .into_owned()
+                .collect();
+            let field = |name: &str| form.get(name).map(String::as_str).unwrap_or_default();
+            if field("client_id") != CLIENT_ID || field("client_secret") != CLIENT_SECRET {
+                return (401, vec![], json!({ "error": "invalid_client" }));
+            }
+
+            // codes are single use, a failed exchange burns them too
+            let issued = match idp.codes.remove(field("code")) {
+                Some(issued) => issued,
+                None => return invalid_grant,
+            };
+            if field("grant_type") != "authorization_code"
+                || field("redirect_uri") != issued.redirect_uri
+                || pkce_challenge(field("code_verifier")) != issued.challenge
+            {
+                return invalid_grant;
+            }
+
+            let access_token = generate_random_token(16);
+            idp.access_tokens
+                .insert(access_token.clone(), issued.profile);
+            (
+                200,
+                vec![],
+                json!({ "access_token": access_token, "token_type": "Bearer" }),
+            )
+        }
+        ("GET", "/userinfo") => {
+            let profile = req
+                .headers
+                .get("authorization")
+                .and_then(|auth| auth.strip_prefix("Bearer "))
+                .and_then(|token| idp.access_tokens.get(token));
+            match profile {
+                Some(profile) => (200, vec![], profile.clone()),
+                None => (401, vec![], json!({ "error": "invalid_token" })),
+            }
+        }
+        _ => (404, vec![], json!({})),
+    }
+}
+
+fn start_idp() -> (String, Arc<Mutex<MockIdp>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let state = Arc::new(Mutex::new(MockIdp::default()));
+
+    let shared = state.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let req = match read_request(&stream) {
+                Some(req) => req,
+                None => continue,
+            };
+            let (status, headers, body) = handle(&mut shared.lock().unwrap(), req);
+
+            let body = body.to_string();
+            let mut response = format!(
+                "HTTP/1.1 {} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+                status,
+                body.len()
+            );
+            for (name, value) in headers {
+                response.push_str(&format!("{}: {}\r\n", name, value));
+            }
+            response.push_str("\r\n");
+            response.push_str(&body);
+            let _ = stream.write_all(response.as_bytes());
+        }
+    });
+
+    (address, state)
+}
+
+fn oauth_config(idp_address: &str) -> OAuthConfig {
+    let url = |path: &str| format!("http://{}{}", idp_address, path);
+    let mut config = OAuthConfig::default();
+    config.providers.insert(
+        "mock".to_string(),
+        OAuthProviderConfig {
+            client_id: CLIENT_ID.to_string(),
+            client_secret: CLIENT_SECRET.to_string(),
+            authorize_url: url("/authorize"),
+            token_url: url("/token"),
+            userinfo_url: url("/userinfo"),
+            emails_url: None,
+            scopes: "openid email profile".to_string(),
+            redirect_url: REDIRECT_URL.to_string(),
+            subject_field: "sub".to_string(),
+        },
+    );
+
+    config
+}
+
+// What the browser does between our authorize and callback endpoints: follow
+// the authorize url and pick `code` and `state` off the redirect
+fn visit_idp(authorize_url: &str) -> (String, String) {
+    let url = Url::parse(authorize_url).unwrap();
+    let mut stream = TcpStream::connect((url.host_str().unwrap(), url.port().unwrap())).unwrap();
+    write!(
+        stream,
+        "GET {}?{} HTTP/1.1\r\nHost: idp\r\n\r\n",
+        url.path(),
+        url.query().unwrap()
+    )
+    .unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 302"), "{}", response);
+    let location = response
+        .lines()
+        .find_map(|line| line.strip_prefix("Location: "))
+        .unwrap();
+    assert!(location.starts_with(REDIRECT_URL));
+
+    let params: HashMap<String, String> = Url::parse(location)
+        .unwrap()
+        .query_pairs()
+        .into_owned()
+        .collect();
+    (params["code"].clone(), params["state"].clone())
+}
+
+fn profile(subject: &str, email: &str) -> Value {
+    json!({
+        "sub": subject,
+        "email": email,
+        "email_verified": true,
+        "name": "Mock User",
+    })
+}
+
+async fn json_body(res: ServiceResponse) -> (u16, Value) {
+    let status = res.status().as_u16();
+    (status, test::read_body_json(res).await)
+}
+
+fn identity_owner(pool: &Pool, subject: &str) -> Option<i32> {
+    user_identities::table
+        .filter(user_identities::provider.eq("mock"))
+        .filter(user_identities::subject.eq(subject))
+        .select(user_identities::user_id)
+        .first(&pool.get().unwrap())
+        .ok()
+}
+
+macro_rules! app {
+    ($pool:expr, $idp_address:expr) => {
+        test::init_service(
+            App::new()
+                .data($pool.clone())
+                .data(oauth_config($idp_address))
+                .data(AuthCookieConfig::from_env().unwrap())
+                .data(RegistrationConfig {
+                    mode: RegistrationMode::Open,
+                })
+                .data(common::argon2_config())
+                .wrap(Authentication)
+                .route(
+                    "/v1/auth/oauth/{provider}",
+                    web::get().to(authorize_handler),
+                )
+                .route(
+                    "/v1/auth/oauth/{provider}/callback",
+                    web::post().to(callback_handler),
+                )
+                .route(
+                    "/v1/profile/identities/{key}",
+                    web::post().to(link_identity_handler),
+                ),
+        )
+        .await
+    };
+}
+
+// Starts a flow, logged in when given a session token, returns the
+// authorize url and the nonce for the callback
+macro_rules! authorize {
+    ($app:expr, $token:expr) => {{
+        let token: Option<&str> = $token;
+        let req = match token {
+            Some(token) => test::TestRequest::post()
+                .uri("/v1/profile/identities/mock")
+                .header("Authorization", format!("Bearer {}", token)),
+            None => test::TestRequest::get().uri("/v1/auth/oauth/mock"),
+        };
+        let (status, body) = json_body(test::call_service($app, req.to_request()).await).await;
+        assert_eq!(status, 200, "{}", body);
+
+        (
+            body["data"]["authorize_url"].as_str().unwrap().to_string(),
+            body["data"]["nonce"].as_str().unwrap().to_string(),
+        )
+    }};
+}
+
+macro_rules! callback {
+    ($app:expr, $code:expr, $state:expr, $nonce:expr) => {{
+        let req = test::TestRequest::post()
+            .uri("/v1/auth/oauth/mock/callback")
+            .peer_addr("192.0.2.1:4000".parse().unwrap())
+            .set_json(&json!({ "code": $code, "state": $state, "nonce": $nonce }))
+            .to_request();
+        json_body(test::call_service($app, req).await).await
+    }};
+}
+
+fn setup() -> Option<Pool> {
+    let pool = common::pool()?;
+    if env::var("JWT_SECRET").is_err() {
+        env::set_var("JWT_SECRET", "test-secret");
+    }
+    Some(pool)
+}
+
+#[actix_rt::test]
+async fn signs_up_and_logs_in_through_the_provider() {
+    let pool = match setup() {
+        Some(pool) => pool,
+        None => return,
+    };
+    let (idp_address, idp) = start_idp();
+    let mut app = app!(pool, &idp_address);
+    let subject = generate_random_token(8);
+    let email = common::unique_email("oauth");
+    idp.lock().unwrap().profile = profile(&subject, &email);
+
+    let (authorize_url, nonce) = authorize!(&mut app, None);
+    let params: HashMap<String, String> = Url::parse(&authorize_url)
+        .unwrap()
+        .query_pairs()
+        .into_owned()
+        .collect();
+    assert_eq!(params["redirect_uri"], REDIRECT_URL);
+    assert_eq!(params["scope"], "openid email profile");
+
+    let (code, state) = visit_idp(&authorize_url);
+    let (status, body) = callback!(&mut app, &code, &state, &nonce);
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["data"]["email"], email);
+    assert!(body["data"]["token"].is_string());
+
+    let user_id = users::table
+        .filter(users::email.eq(&email))
+        .select(users::id)
+        .first::<i32>(&pool.get().unwrap())
+        .unwrap();
+    assert_eq!(identity_owner(&pool, &subject), Some(user_id));
+
+    // a second round trip logs the same account in
+    let (authorize_url, nonce) = authorize!(&mut app, None);
+    let (code, state) = visit_idp(&authorize_url);
+    let (status, body) = callback!(&mut app, &code, &state, &nonce);
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["data"]["email"], email);
+}
+
+#[actix_rt::test]
+async fn a_state_can_only_be_used_once() {
+    let pool = match setup() {
+        Some(pool) => pool,
+        None => return,
+    };
+    let (idp_address, idp) = start_idp();
+    let mut app = app!(pool, &idp_address);
+    idp.lock().unwrap().profile =
+        profile(&generate_random_token(8), &common::unique_email("oauth"));
+
+    let (authorize_url, nonce) = authorize!(&mut app, None);
+    let (code, state) = visit_idp(&authorize_url);
+    let (status, _) = callback!(&mut app, &code, &state, &nonce);
+    assert_eq!(status, 200);
+
+    let (status, body) = callback!(&mut app, &code, &state, &nonce);
+    assert_eq!(status, 400);
+    assert_eq!(body["message"], "Bad Request: Invalid OAuth state");
+}
+
+#[actix_rt::test]
+async fn rejects_a_nonce_from_another_browser() {
+    let pool = match setup() {
+        Some(pool) => pool,
+        None => return,
+    };
+    let (idp_address, idp) = start_idp();
+    let mut app = app!(pool, &idp_address);
+    idp.lock().unwrap().profile =
+        profile(&generate_random_token(8), &common::unique_email("oauth"));
+
+    let (authorize_url, nonce) = authorize!(&mut app, None);
+    let (_, other_nonce) = authorize!(&mut app, None);
+    let (code, state) = visit_idp(&authorize_url);
+
+    let (status, body) = callback!(&mut app, &code, &state, &other_nonce);
+    assert_eq!(status, 400);
+    assert_eq!(body["message"], "Bad Request: Invalid OAuth state");
+
+    // the state is left alone for the browser that started the flow
+    let (status, body) = callback!(&mut app, &code, &state, &nonce);
+    assert_eq!(status, 200, "{}", body);
+}
+
+#[actix_rt::test]
+async fn rejects_a_code_issued_to_another_flow() {
+    let pool = match setup() {
+        Some(pool) => pool,
+        None => return,
+    };
+    let (idp_address, idp) = start_idp();
+    let mut app = app!(pool, &idp_address);
+    let subject = generate_random_token(8);
+    idp.lock().unwrap().profile = profile(&subject, &common::unique_email("oauth"));
+
+    // an attacker's code injected into the victim's callback, our verifier
+    // doesn't match the challenge the code was issued for
+    let (attacker_url, _) = authorize!(&mut app, None);
+    let (attacker_code, _) = visit_idp(&attacker_url);
+    let (victim_url, victim_nonce) = authorize!(&mut app, None);
+    let (_, victim_state) = visit_idp(&victim_url);
+
+    let (status, _) = callback!(&mut app, &attacker_code, &victim_state, &victim_nonce);
+    assert_eq!(status, 401);
+    assert!(idp.lock().unwrap().access_tokens.is_empty());
+    assert_eq!(identity_owner(&pool, &subject), None);
+}
+
+#[actix_rt::test]
+async fn links_the_provider_to_a_logged_in_account() {
+    let pool = match setup() {
+        Some(pool) => pool,
+        None => return,
+    };
+    let (idp_address, idp) = start_idp();
+    let mut app = app!(pool, &idp_address);
+    let conn = pool.get().unwrap();
+    let user = common::create_user(&conn, &common::unique_email("oauth"));
+    let client = ClientInfo {
+        ip: "192.0.2.1".to_string(),
+        user_agent: None,
+    };
+    let token = create_session_token(&conn, &user, &client, "password").unwrap();
+
+    // the provider account uses another address, it's linked all the same
+    let subject = generate_random_token(8);
+    idp.lock().unwrap().profile = profile(&subject, &common::unique_email("elsewhere"));
+
+    let (authorize_url, nonce) = authorize!(&mut app, Some(&token));
+    let (code, state) = visit_idp(&authorize_url);
+    let (status, body) = callback!(&mut app, &code, &state, &nonce);
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["data"]["email"], user.email);
+    assert_eq!(identity_owner(&pool, &subject), Some(user.id));
+
+    // and it can't be linked to a second account
+    let other = common::create_user(&conn, &common::unique_email("oauth"));
+    let other_token = create_session_token(&conn, &other, &client, "password").unwrap();
+    let (authorize_url, nonce) = authorize!(&mut app, Some(&other_token));
+    let (code, state) = visit_idp(&authorize_url);
+    let (status, _) = callback!(&mut app, &code, &state, &nonce);
+    assert_eq!(status, 409);
+    assert_eq!(identity_owner(&pool, &subject), Some(user.id));
+}
@@ -0,0 +1,233 @@
+// The Redis store and the middleware against an in-process stand-in that
+// speaks enough RESP to run the GCRA script
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use actix_web::{test, web, App, HttpResponse};
+use fakhrusy_com_backend::middleware::rate_limit::{RateLimit, RateLimitKey};
+use fakhrusy_com_backend::rate_limit::{redis::RedisStore, Quota, RateLimitStore};
+
+#[derive(Default)]
+struct StandIn {
+    // every command received, in order, across connections
+    commands: Vec<Vec<String>>,
+    connections: usize,
+    values: HashMap<String, i64>,
+    // the next EVAL is answered with this error instead
+    fail_next: Option<String>,
+}
+
+fn read_line(reader: &mut BufReader<TcpStream>) -> Option<String> {
+    let mut line = String::new();
+    match reader.read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line.trim_end().to_string()),
+    }
+}
+
+fn read_command(reader: &mut BufReader<TcpStream>) -> Option<Vec<String>> {
+    let count: usize = read_line(reader)?.strip_prefix('*')?.parse().ok()?;
+    let mut args = Vec::new();
+    for _ in 0..count {
+        let len: usize = read_line(reader)?.strip_prefix('$')?.parse().ok()?;
+        let mut data = vec![0; len + 2];
+        reader.read_exact(&mut data).ok()?;
+        data.truncate(len);
+        args.push(String::from_utf8(data).ok()?);
+    }
+
+    Some(args)
+}
+
+// The GCRA script, EVAL <script> 1 <key> <now> <interval> <period>
+fn eval(values: &mut HashMap<String, i64>, args: &[String]) -> String {
+    let key = &args[3];
+    let [now, interval, period] =
+        [&args[4], &args[5], &args[6]].map(|arg| arg.parse::<i64>().unwrap());
+
+    let tat = values.get(key).copied().unwrap_or(now).max(now);
+    let new_tat = tat + interval;
+    if now < new_tat - period {
+        return format!("*2\r\n:0\r\n:{}\r\n", tat);
+    }
+    values.insert(key.clone(), new_tat);
+    format!("*2\r\n:1\r\n:{}\r\n", new_tat)
+}
+
+fn start_stand_in(password: &'static str) -> (String, Arc<Mutex<StandIn>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let state = Arc::new(Mutex::new(StandIn::default()));
+
+    let shared = state.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let state = shared.clone();
+            state.lock().unwrap().connections += 1;
+
+            thread::spawn(move || {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut authenticated = password.is_empty();
+
+                while let Some(args) = read_command(&mut reader) {
+                    let mut state = state.lock().unwrap();
+                    state.commands.push(args.clone());
+
+                    let reply = match args[0].as_str() {
+                        "AUTH" if args[1] == password => {
+                            authenticated = true;
+                            "+OK\r\n".to_string()
+                        }
+                        "AUTH" => "-WRONGPASS invalid password\r\n".to_string(),
+                        _ if !authenticated => "-NOAUTH Authentication required.\r\n".to_string(),
+                        "SELECT" => "+OK\r\n".to_string(),
+                        "EVAL" => match state.fail_next.take() {
+                            Some(error) => format!("-{}\r\n", error),
+                            None => eval(&mut state.values, &args),
+                        },
+                        _ => "-ERR unknown command\r\n".to_string(),
+                    };
+                    if stream.write_all(reply.as_bytes()).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+
+    (address, state)
+}
+
+fn quota(s: &str) -> Quota {
+    s.parse().unwrap()
+}
+
+#[test]
+fn redis_store_runs_the_script_on_the_server() {
+    let (address, stand_in) = start_stand_in("secret");
+    let store = RedisStore::new(&format!("redis://:secret@{}/2", address));
+    let quota = quota("2/60");
+
+    let first = store.check("login:ip:192.0.2.1", 1_000, &quota).unwrap();
+    assert!(first.allowed);
+    assert_eq!(first.remaining, 1);
+
+    let second = store.check("login:ip:192.0.2.1", 1_000, &quota).unwrap();
+    assert!(second.allowed);
+    assert_eq!(second.remaining, 0);
+
+    let denied = store.check("login:ip:192.0.2.1", 1_000, &quota).unwrap();
+    assert!(!denied.allowed);
+    assert_eq!(denied.retry_after_seconds, Some(30));
+
+    // other keys have their own budget
+    assert!(
+        store
+            .check("login:ip:192.0.2.2", 1_000, &quota)
+            .unwrap()
+            .allowed
+    );
+
+    let stand_in = stand_in.lock().unwrap();
+    assert_eq!(stand_in.connections, 1);
+    assert_eq!(stand_in.commands[0], ["AUTH", "secret"]);
+    assert_eq!(stand_in.commands[1], ["SELECT", "2"]);
+    let eval = &stand_in.commands[2];
+    assert_eq!(eval[0], "EVAL");
+    assert!(eval[1].contains("redis.call('SET', KEYS[1]"));
+    assert_eq!(
+        eval[2..],
+        [
+            "1",
+            "rate_limit:login:ip:192.0.2.1",
+            "1000",
+            "30000",
+            "60000"
+        ]
+    );
+}
+
+#[test]
+fn redis_store_reconnects_after_an_error() {
+    let (address, stand_in) = start_stand_in("");
+    let store = RedisStore::new(&format!("redis://{}", address));
+    let quota = quota("5/60");
+
+    stand_in.lock().unwrap().fail_next = Some("BUSY script is running".to_string());
+    let err = store.check("key", 1_000, &quota).err().unwrap();
+    assert_eq!(err.0, "BUSY script is running");
+
+    assert!(store.check("key", 1_000, &quota).unwrap().allowed);
+    assert_eq!(stand_in.lock().unwrap().connections, 2);
+}
+
+#[test]
+fn redis_store_reports_a_wrong_password() {
+    let (address, _stand_in) = start_stand_in("secret");
+    let store = RedisStore::new(&format!("redis://:wrong@{}", address));
+
+    assert!(store.check("key", 1_000, &quota("5/60")).is_err());
+}
+
+#[actix_rt::test]
+async fn middleware_limits_through_the_store() {
+    let (address, _stand_in) = start_stand_in("");
+    let store: Arc<dyn RateLimitStore> = Arc::new(RedisStore::new(&format!("redis://{}", address)));
+    let mut app = test::init_service(
+        App::new()
+            .wrap(RateLimit::new(
+                "test",
+                store,
+                quota("2/60"),
+                RateLimitKey::Ip,
+            ))
+            .route("/", web::get().to(HttpResponse::Ok)),
+    )
+    .await;
+
+    let mut statuses = Vec::new();
+    for _ in 0..3 {
+        let res = test::call_service(&mut app, test::TestRequest::get().to_request()).await;
+        statuses.push(res.status().as_u16());
+        if res.status() == 429 {
+            assert!(res.headers().contains_key("retry-after"));
+        } else {
+            assert!(res.headers().contains_key("ratelimit-remaining"));
+        }
+    }
+    assert_eq!(statuses, [200, 200, 429]);
+}
+
+#[actix_rt::test]
+async fn middleware_fails_open_when_the_store_is_down() {
+    // a port nothing listens on any more
+    let address = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let store: Arc<dyn RateLimitStore> = Arc::new(RedisStore::new(&format!("redis://{}", address)));
+    let mut app = test::init_service(
+        App::new()
+            .wrap(RateLimit::new(
+                "test",
+                store,
+                quota("1/60"),
+                RateLimitKey::Ip,
+            ))
+            .route("/", web::get().to(HttpResponse::Ok)),
+    )
+    .await;
+
+    for _ in 0..3 {
+        let res = test::call_service(&mut app, test::TestRequest::get().to_request()).await;
+        assert_eq!(res.status(), 200);
+        assert!(!res.headers().contains_key("ratelimit-remaining"));
+    }
+}
@@ -0,0 +1,66 @@
+// Headers added by the outer middlewares, also on errors returned by the
+// ones below them
+
+use actix_service::Service;
+use actix_web::dev::ServiceResponse;
+use actix_web::{error::ErrorUnauthorized, http::header, test, web, App, HttpResponse};
+use futures::future;
+
+use fakhrusy_com_backend::config::{CorsConfig, SecurityHeadersConfig};
+use fakhrusy_com_backend::middleware::cors::Cors;
+use fakhrusy_com_backend::middleware::request_id::RequestIdentifier;
+use fakhrusy_com_backend::middleware::security_headers::SecurityHeaders;
+
+fn cors_config() -> CorsConfig {
+    CorsConfig {
+        allowed_origins: vec!["https://app.example.com".to_string()],
+        allowed_methods: vec!["GET".to_string()],
+        allowed_headers: vec!["Content-Type".to_string()],
+        exposed_headers: Vec::new(),
+        allow_credentials: true,
+        max_age_seconds: 600,
+    }
+}
+
+#[actix_rt::test]
+async fn error_responses_get_the_headers_too() {
+    let security_headers_config = SecurityHeadersConfig::from_env().unwrap();
+    let mut app = test::init_service(
+        App::new()
+            // refuses everything the way an authentication middleware would
+            .wrap_fn(|_, _| future::err::<ServiceResponse, _>(ErrorUnauthorized("Invalid token")))
+            .wrap(SecurityHeaders::new(&security_headers_config.default))
+            .wrap(Cors::new(cors_config()))
+            .wrap(RequestIdentifier)
+            .route("/", web::get().to(HttpResponse::Ok)),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .header(header::ORIGIN, "https://app.example.com")
+        .to_request();
+    // what the server sends for an error that reaches it
+    let err = app
+        .call(req)
+        .await
+        .expect_err("the request was let through");
+    let res = err.as_response_error().error_response();
+
+    assert_eq!(res.status(), 401);
+    let headers = res.headers();
+    assert_eq!(
+        headers.get(header::CONTENT_SECURITY_POLICY).unwrap(),
+        "default-src 'none'; frame-ancestors 'none'"
+    );
+    assert_eq!(headers.get(header::X_FRAME_OPTIONS).unwrap(), "DENY");
+    assert_eq!(
+        headers.get(header::X_CONTENT_TYPE_OPTIONS).unwrap(),
+        "nosniff"
+    );
+    assert_eq!(
+        headers.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+        "https://app.example.com"
+    );
+    assert_eq!(headers.get(header::VARY).unwrap(), "Origin");
+    assert!(headers.contains_key("x-request-id"));
+}
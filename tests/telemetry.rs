@@ -0,0 +1,122 @@
+// Exports a request's span to a stand-in OTLP collector and checks it joined
+// the caller's trace. The tracing subscriber is process wide, so this file
+// holds a single test.
+
+use std::{
+    env,
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+use actix_web::{test, web, App, HttpResponse};
+use fakhrusy_com_backend::config::{LogConfig, TracingConfig};
+use fakhrusy_com_backend::middleware::request_id::RequestIdentifier;
+use fakhrusy_com_backend::telemetry;
+use opentelemetry_proto::tonic::collector::trace::v1::ExportTraceServiceRequest;
+use prost::Message;
+
+const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+const PARENT_SPAN_ID: &str = "00f067aa0ba902b7";
+
+// Accepts OTLP/HTTP exports and passes on the request bodies
+fn start_collector() -> (u16, mpsc::Receiver<Vec<u8>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                .unwrap();
+            if sender.send(body).is_err() {
+                break;
+            }
+        }
+    });
+
+    (port, receiver)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[test]
+fn request_span_continues_the_callers_trace() {
+    let (port, exports) = start_collector();
+    env::set_var("OTEL_TRACES_EXPORTER", "otlp");
+    env::set_var(
+        "OTEL_EXPORTER_OTLP_ENDPOINT",
+        format!("http://127.0.0.1:{}", port),
+    );
+    env::set_var("OTEL_TRACES_SAMPLER_ARG", "1.0");
+
+    let provider = telemetry::init(
+        &LogConfig::from_env().unwrap(),
+        &TracingConfig::from_env().unwrap(),
+    );
+    assert!(provider.is_some());
+
+    actix_web::rt::System::new("test").block_on(async {
+        let mut app =
+            test::init_service(App::new().wrap(RequestIdentifier).service(
+                web::resource("/ping").route(web::get().to(|| HttpResponse::Ok().finish())),
+            ))
+            .await;
+
+        let req = test::TestRequest::get()
+            .uri("/ping")
+            .header(
+                "traceparent",
+                format!("00-{}-{}-01", TRACE_ID, PARENT_SPAN_ID),
+            )
+            .to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert!(res.status().is_success());
+    });
+
+    // sends the batch
+    telemetry::shutdown(provider);
+
+    let body = exports.recv_timeout(Duration::from_secs(10)).unwrap();
+    let export = ExportTraceServiceRequest::decode(&body[..]).unwrap();
+    let spans: Vec<_> = export
+        .resource_spans
+        .iter()
+        .flat_map(|resource_spans| resource_spans.scope_spans.iter())
+        .flat_map(|scope_spans| scope_spans.spans.iter())
+        .collect();
+
+    let span = spans
+        .iter()
+        .find(|span| span.name == "GET /ping")
+        .unwrap_or_else(|| {
+            let names: Vec<_> = spans.iter().map(|span| &span.name).collect();
+            panic!("no request span among {:?}", names)
+        });
+    assert_eq!(hex(&span.trace_id), TRACE_ID);
+    assert_eq!(hex(&span.parent_span_id), PARENT_SPAN_ID);
+}
@@ -0,0 +1,342 @@
+// Passkey assertions checked the way the login handler does, signed with the
+// key pairs in fixtures/webauthn
+
+use std::collections::BTreeMap;
+
+use fakhrusy_com_backend::config::WebauthnConfig;
+use fakhrusy_com_backend::webauthn::{
+    base64url_encode, check_sign_count, cose_algorithm, parse_authenticator_data,
+    verify_client_data, verify_signature, COSE_ALG_EDDSA, COSE_ALG_ES256, COSE_ALG_RS256,
+};
+use ring::rand::SystemRandom;
+use ring::signature::{
+    EcdsaKeyPair, Ed25519KeyPair, KeyPair, RsaKeyPair, ECDSA_P256_SHA256_ASN1_SIGNING,
+    RSA_PKCS1_SHA256,
+};
+use serde_cbor::Value;
+use sha2::{Digest, Sha256};
+
+const CHALLENGE: &str = "c2lnbi1tZS1wbGVhc2U";
+
+fn config() -> WebauthnConfig {
+    WebauthnConfig {
+        rp_id: "fakhrusy.com".to_string(),
+        rp_name: "fakhrusy.com".to_string(),
+        origin: "https://fakhrusy.com".to_string(),
+        challenge_lifetime_seconds: 300,
+    }
+}
+
+enum Signer {
+    Es256(EcdsaKeyPair),
+    EdDsa(Ed25519KeyPair),
+    Rs256(RsaKeyPair),
+}
+
+impl Signer {
+    fn es256() -> Signer {
+        let pkcs8 = include_bytes!("fixtures/webauthn/es256.pk8");
+        Signer::Es256(EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8).unwrap())
+    }
+
+    fn eddsa() -> Signer {
+        // openssl writes version 1 PKCS#8, without the public key
+        let pkcs8 = include_bytes!("fixtures/webauthn/eddsa.pk8");
+        Signer::EdDsa(Ed25519KeyPair::from_pkcs8_maybe_unchecked(pkcs8).unwrap())
+    }
+
+    fn rs256() -> Signer {
+        // ring only takes RSA keys encoded as RSAPrivateKey
+        let der = include_bytes!("fixtures/webauthn/rs256.der");
+        Signer::Rs256(RsaKeyPair::from_der(der).unwrap())
+    }
+
+    // The public key as an authenticator reports it during registration
+    fn cose_key(&self) -> Vec<(i128, Value)> {
+        match self {
+            Signer::Es256(key_pair) => {
+                let point = key_pair.public_key().as_ref();
+                vec![
+                    (1, Value::Integer(2)),
+                    (3, Value::Integer(COSE_ALG_ES256.into())),
+                    (-1, Value::Integer(1)),
+                    (-2, Value::Bytes(point[1..33].to_vec())),
+                    (-3, Value::Bytes(point[33..].to_vec())),
+                ]
+            }
+            Signer::EdDsa(key_pair) => vec![
+                (1, Value::Integer(1)),
+                (3, Value::Integer(COSE_ALG_EDDSA.into())),
+                (-1, Value::Integer(6)),
+                (-2, Value::Bytes(key_pair.public_key().as_ref().to_vec())),
+            ],
+            Signer::Rs256(key_pair) => {
+                let public_key = key_pair.public_key();
+                vec![
+                    (1, Value::Integer(3)),
+                    (3, Value::Integer(COSE_ALG_RS256.into())),
+                    (
+                        -1,
+                        Value::Bytes(
+                            public_key
+                                .modulus()
+                                .big_endian_without_leading_zero()
+                                .to_vec(),
+                        ),
+                    ),
+                    (
+                        -2,
+                        Value::Bytes(
+                            public_key
+                                .exponent()
+                                .big_endian_without_leading_zero()
+                                .to_vec(),
+                        ),
+                    ),
+                ]
+            }
+        }
+    }
+
+    fn sign(&self, message: &[u8]) -> Vec<u8> {
+        let rng = SystemRandom::new();
+        match self {
+            Signer::Es256(key_pair) => key_pair.sign(&rng, message).unwrap().as_ref().to_vec(),
+            Signer::EdDsa(key_pair) => key_pair.sign(message).as_ref().to_vec(),
+            Signer::Rs256(key_pair) => {
+                let mut signature = vec![0; key_pair.public_modulus_len()];
+                key_pair
+                    .sign(&RSA_PKCS1_SHA256, &rng, message, &mut signature)
+                    .unwrap();
+                signature
+            }
+        }
+    }
+}
+
+fn encode_cose_key(fields: Vec<(i128, Value)>) -> Vec<u8> {
+    let map: BTreeMap<Value, Value> = fields
+        .into_iter()
+        .map(|(label, value)| (Value::Integer(label), value))
+        .collect();
+    serde_cbor::to_vec(&map).unwrap()
+}
+
+// Replaces or adds one COSE key field
+fn with_field(mut fields: Vec<(i128, Value)>, label: i128, value: Value) -> Vec<u8> {
+    fields.retain(|(field, _)| *field != label);
+    fields.push((label, value));
+    encode_cose_key(fields)
+}
+
+struct Assertion {
+    client_data_json: Vec<u8>,
+    authenticator_data: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+fn authenticator_data(rp_id: &str, sign_count: u32) -> Vec<u8> {
+    let mut data = Sha256::digest(rp_id.as_bytes()).to_vec();
+    // user present and verified
+    data.push(0x05);
+    data.extend_from_slice(&sign_count.to_be_bytes());
+    data
+}
+
+fn client_data_json(challenge: &str, origin: &str) -> Vec<u8> {
+    serde_json::to_vec(&serde_json::json!({
+        "type": "webauthn.get",
+        "challenge": challenge,
+        "origin": origin,
+        "crossOrigin": false,
+    }))
+    .unwrap()
+}
+
+fn sign_assertion(
+    signer: &Signer,
+    authenticator_data: Vec<u8>,
+    client_data_json: Vec<u8>,
+) -> Assertion {
+    let message = [
+        &authenticator_data[..],
+        &Sha256::digest(&client_data_json)[..],
+    ]
+    .concat();
+
+    Assertion {
+        signature: signer.sign(&message),
+        client_data_json,
+        authenticator_data,
+    }
+}
+
+fn assertion(signer: &Signer, sign_count: u32) -> Assertion {
+    let config = config();
+    sign_assertion(
+        signer,
+        authenticator_data(&config.rp_id, sign_count),
+        client_data_json(CHALLENGE, &config.origin),
+    )
+}
+
+// What verify_assertion in api::auth::webauthn does, minus the database
+fn verify(cose_key: &[u8], assertion: &Assertion) -> Result<u32, &'static str> {
+    let config = config();
+    let client_data_hash = verify_client_data(
+        &assertion.client_data_json,
+        "webauthn.get",
+        CHALLENGE,
+        &config,
+    )
+    .map_err(|err| err.0)?;
+    let auth_data =
+        parse_authenticator_data(&assertion.authenticator_data, &config).map_err(|err| err.0)?;
+    verify_signature(
+        cose_key,
+        &assertion.authenticator_data,
+        &client_data_hash,
+        &assertion.signature,
+    )
+    .map_err(|err| err.0)?;
+
+    Ok(auth_data.sign_count)
+}
+
+fn signers() -> [(i64, Signer); 3] {
+    [
+        (COSE_ALG_ES256, Signer::es256()),
+        (COSE_ALG_EDDSA, Signer::eddsa()),
+        (COSE_ALG_RS256, Signer::rs256()),
+    ]
+}
+
+#[test]
+fn accepts_valid_assertions() {
+    for (algorithm, signer) in signers() {
+        let cose_key = encode_cose_key(signer.cose_key());
+        assert_eq!(cose_algorithm(&cose_key).unwrap(), algorithm);
+        assert_eq!(verify(&cose_key, &assertion(&signer, 7)), Ok(7));
+    }
+}
+
+#[test]
+fn rejects_a_tampered_signature() {
+    for (_, signer) in signers() {
+        let cose_key = encode_cose_key(signer.cose_key());
+        let mut assertion = assertion(&signer, 7);
+        let last = assertion.signature.len() - 1;
+        assertion.signature[last] ^= 0x01;
+
+        assert_eq!(verify(&cose_key, &assertion), Err("invalid signature"));
+    }
+}
+
+#[test]
+fn rejects_signed_data_changed_afterwards() {
+    let signer = Signer::es256();
+    let cose_key = encode_cose_key(signer.cose_key());
+    let mut assertion = assertion(&signer, 7);
+    // bump the sign count past what the authenticator signed
+    assertion.authenticator_data[36] += 1;
+
+    assert_eq!(verify(&cose_key, &assertion), Err("invalid signature"));
+}
+
+#[test]
+fn rejects_a_signature_by_another_key() {
+    let cose_key = encode_cose_key(Signer::es256().cose_key());
+    let assertion = assertion(&Signer::eddsa(), 7);
+
+    assert_eq!(verify(&cose_key, &assertion), Err("invalid signature"));
+}
+
+#[test]
+fn rejects_another_relying_party() {
+    let signer = Signer::es256();
+    let cose_key = encode_cose_key(signer.cose_key());
+    let assertion = sign_assertion(
+        &signer,
+        authenticator_data("evil.example", 7),
+        client_data_json(CHALLENGE, &config().origin),
+    );
+
+    assert_eq!(verify(&cose_key, &assertion), Err("relying party mismatch"));
+}
+
+#[test]
+fn rejects_another_origin_or_challenge() {
+    let signer = Signer::es256();
+    let cose_key = encode_cose_key(signer.cose_key());
+    let config = config();
+
+    let wrong_origin = sign_assertion(
+        &signer,
+        authenticator_data(&config.rp_id, 7),
+        client_data_json(CHALLENGE, "https://evil.example"),
+    );
+    assert_eq!(verify(&cose_key, &wrong_origin), Err("origin mismatch"));
+
+    let wrong_challenge = sign_assertion(
+        &signer,
+        authenticator_data(&config.rp_id, 7),
+        client_data_json(&base64url_encode(b"another challenge"), &config.origin),
+    );
+    assert_eq!(
+        verify(&cose_key, &wrong_challenge),
+        Err("challenge mismatch")
+    );
+}
+
+#[test]
+fn rejects_keys_whose_type_or_curve_does_not_fit_the_algorithm() {
+    let es256 = Signer::es256().cose_key();
+    let eddsa = Signer::eddsa().cose_key();
+    let rs256 = Signer::rs256().cose_key();
+
+    let keys = [
+        (
+            "ES256 as an OKP key",
+            with_field(es256.clone(), 1, Value::Integer(1)),
+        ),
+        (
+            "ES256 on P-384",
+            with_field(es256.clone(), -1, Value::Integer(2)),
+        ),
+        ("ES256 without a key type", {
+            let mut fields = es256;
+            fields.retain(|(label, _)| *label != 1);
+            encode_cose_key(fields)
+        }),
+        (
+            "EdDSA as an EC2 key",
+            with_field(eddsa.clone(), 1, Value::Integer(2)),
+        ),
+        ("EdDSA on X25519", with_field(eddsa, -1, Value::Integer(4))),
+        (
+            "RS256 as an EC2 key",
+            with_field(rs256, 1, Value::Integer(2)),
+        ),
+    ];
+    for (case, cose_key) in keys {
+        assert!(cose_algorithm(&cose_key).is_err(), "{}", case);
+    }
+
+    // and they are refused at login too, not only at registration
+    let signer = Signer::es256();
+    let cose_key = with_field(signer.cose_key(), -1, Value::Integer(2));
+    assert!(verify(&cose_key, &assertion(&signer, 7)).is_err());
+}
+
+#[test]
+fn sign_count_has_to_move_forward() {
+    // authenticators without a counter always send 0
+    assert!(check_sign_count(0, 0).is_ok());
+    assert!(check_sign_count(0, 1).is_ok());
+    assert!(check_sign_count(7, 8).is_ok());
+
+    assert!(check_sign_count(7, 7).is_err());
+    assert!(check_sign_count(7, 3).is_err());
+    // a counter that drops back to 0 is a regression too
+    assert!(check_sign_count(7, 0).is_err());
+}